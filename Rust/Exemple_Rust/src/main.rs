@@ -2,15 +2,29 @@
 // © 2026 Mounir Azizi - DeLTa-X Tunisia - All Rights Reserved
 // This project is for demonstration purposes only.
 
-mod sdk;
-
 use colored::*;
+use futures_util::StreamExt;
+use smartc::signaling::{self, HubEvent, SignalingClient};
 use std::io::{self, BufRead, Write};
 use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
 
-const SIGNAL_HUB_URL: &str = "http://localhost:5001/signalhub";
 const DEFAULT_ROOM: &str = "rust-chat-room";
 
+/// `--login` authenticates against the SmaRTC API and connects with its JWT
+fn use_login() -> bool {
+    std::env::args().any(|arg| arg == "--login")
+}
+
+/// `--messagepack` selects the binary protocol used by the optimized server
+fn hub_protocol() -> signaling::HubProtocol {
+    if std::env::args().any(|arg| arg == "--messagepack") {
+        signaling::HubProtocol::MessagePack(signaling::Compression::Lz4BlockArray)
+    } else {
+        signaling::HubProtocol::Json
+    }
+}
+
 fn print_banner() {
     println!(
         "{}",
@@ -36,11 +50,64 @@ fn print_help() {
     println!("\n{}", "📋 Commandes disponibles:".yellow());
     println!("   /quit    - Quitter le chat");
     println!("   /room    - Afficher la room actuelle");
+    println!("   /join X  - Rejoindre aussi la room X et y écrire");
+    println!("   /members - Afficher les membres de la room actuelle");
     println!("   /help    - Afficher cette aide");
     println!("   /clear   - Effacer l'écran");
     println!();
 }
 
+fn print_prompt() {
+    print!("{}", "📝 Vous: ".green().bold());
+    io::stdout().flush().unwrap();
+}
+
+fn print_event(event: &HubEvent) {
+    match event {
+        HubEvent::Message { room, user, message } => {
+            let room = room.as_deref().map(|r| format!("[{}] ", r)).unwrap_or_default();
+            println!("\n{} {}{}: {}", "💬".blue(), room.dimmed(), user.cyan().bold(), message);
+        }
+        HubEvent::UserJoined { room, user } => {
+            println!("\n{} {} {} {}", "👋".magenta(), user.cyan().bold(), "a rejoint".magenta(), room.cyan());
+        }
+        HubEvent::UserLeft { room, user } => {
+            println!("\n{} {} {} {}", "👋".yellow(), user.cyan().bold(), "a quitté".yellow(), room.cyan());
+        }
+        HubEvent::Reconnecting => {
+            println!("\n{}", "🔄 Connexion perdue, reconnexion...".yellow());
+        }
+        HubEvent::Reconnected => {
+            println!("\n{}", "✅ Reconnecté au serveur SignalR!".green());
+        }
+        HubEvent::Disconnected => {
+            println!("\n{}", "❌ Déconnecté du serveur".red().bold());
+        }
+        HubEvent::Failed { error } => {
+            println!("\n{} {}", "❌ Erreur interne du client:".red().bold(), error);
+        }
+        HubEvent::InvalidInvocation { target, error } => {
+            println!("\n{} {}: {}", "⚠️  Message invalide".yellow(), target, error);
+        }
+        HubEvent::Undelivered { room, message, users } => {
+            let users = users.join(", ");
+            println!("\n{} [{}] {} ({})", "⚠️  Message non remis".yellow(), room, message, users.cyan());
+        }
+        HubEvent::Outbox { message, status, .. } => {
+            let status = match status {
+                signaling::OutboxStatus::Queued => "⏳ En attente de reconnexion".yellow(),
+                signaling::OutboxStatus::Sent => "📤 Envoyé".green(),
+                signaling::OutboxStatus::Expired => "⌛ Expiré".red(),
+                signaling::OutboxStatus::Cancelled => "🚫 Annulé".red(),
+            };
+            println!("\n{} : {}", status, message.dimmed());
+        }
+        HubEvent::Lagged { missed } => {
+            println!("\n{} {} événements perdus", "⚠️  Affichage en retard :".yellow(), missed);
+        }
+    }
+}
+
 fn clear_screen() {
     print!("\x1B[2J\x1B[1;1H");
     io::stdout().flush().unwrap();
@@ -59,44 +126,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     stdin.lock().read_line(&mut username).unwrap();
     let username = username.trim().to_string();
 
-    let username = if username.is_empty() {
+    let mut username = if username.is_empty() {
         format!("RustUser_{}", std::process::id() % 1000)
     } else {
         username
     };
 
-    let username_clone = username.clone();
-
-    // Create client
-    let client = Arc::new(sdk::SmaRTCClient::new(SIGNAL_HUB_URL));
-    
-    // Set callbacks
-    let client_ref = unsafe { 
-        &mut *(Arc::as_ptr(&client) as *mut sdk::SmaRTCClient) 
-    };
-    
-    client_ref.on_signal = Some(Arc::new(move |user: String, message: String| {
-        println!("\n{} {}: {}", "💬".blue(), user.cyan().bold(), message);
-        print!("{}", "📝 Vous: ".green().bold());
-        io::stdout().flush().unwrap();
-    }));
-
-    client_ref.on_user_joined = Some(Arc::new(move |user: String| {
-        println!("\n{} {} {}", "👋".magenta(), user.cyan().bold(), "a rejoint le chat".magenta());
-        print!("{}", "📝 Vous: ".green().bold());
+    // Create client, authenticated with the API token when logging in
+    let config = smartc::Config::default();
+    let hub_url = config.hub_url();
+    // Messages typed while the connection is down are sent once reconnected
+    let mut builder = SignalingClient::builder(&hub_url).outbox(signaling::OutboxOptions::default());
+    if use_login() {
+        print!("{}", "🔑 Mot de passe: ".green().bold());
         io::stdout().flush().unwrap();
-    }));
+        let mut password = String::new();
+        stdin.lock().read_line(&mut password).unwrap();
 
-    client_ref.on_user_left = Some(Arc::new(move |user: String| {
-        println!("\n{} {} {}", "👋".yellow(), user.cyan().bold(), "a quitté le chat".yellow());
-        print!("{}", "📝 Vous: ".green().bold());
-        io::stdout().flush().unwrap();
-    }));
+        let mut api = smartc::SmaRTCClient::new(Some(config));
+        if let Err(e) = api.login(&username, password.trim()).await {
+            println!("{} {}", "❌ Échec de l'authentification:".red().bold(), e);
+            return Ok(());
+        }
+        username = api.current_username().unwrap_or(&username).to_string();
+        let api = Arc::new(tokio::sync::Mutex::new(api));
+        builder = builder.access_token_factory(signaling::auth::smartc_token(api));
+    }
+    let client = builder.build();
+    let events = client.events();
+    tokio::pin!(events);
 
     // Connect
-    println!("\n{} {}", "🔄 Connexion à".yellow(), SIGNAL_HUB_URL.cyan());
+    println!("\n{} {}", "🔄 Connexion à".yellow(), hub_url.cyan());
     
-    match client.connect().await {
+    match client.connect_with(hub_protocol()).await {
         Ok(_) => {
             println!("{}", "✅ Connecté au serveur SignalR!".green().bold());
         }
@@ -109,64 +172,82 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     // Join room
     println!("{} '{}'...", "🚪 Rejoindre la room".yellow(), DEFAULT_ROOM.cyan());
-    client.join_room(DEFAULT_ROOM, &username_clone).await?;
+    client.join_room(DEFAULT_ROOM, &username).await?;
     
     println!(
         "{} '{}' {} '{}'",
         "✅ Connecté en tant que".green().bold(),
-        username_clone.cyan().bold(),
+        username.cyan().bold(),
         "dans la room".green(),
         DEFAULT_ROOM.cyan()
     );
     
     print_help();
 
-    // Main chat loop
-    print!("{}", "📝 Vous: ".green().bold());
-    io::stdout().flush().unwrap();
+    // Main chat loop: hub events and user input side by side
+    print_prompt();
 
-    let client_loop = client.clone();
-    
-    for line in stdin.lock().lines() {
-        let message = match line {
-            Ok(m) => m.trim().to_string(),
-            Err(_) => break,
-        };
-
-        if message.is_empty() {
-            print!("{}", "📝 Vous: ".green().bold());
-            io::stdout().flush().unwrap();
-            continue;
-        }
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
 
-        // Handle commands
-        match message.to_lowercase().as_str() {
-            "/quit" | "/exit" | "/q" => {
-                println!("{}", "👋 Au revoir!".yellow());
-                let _ = client_loop.leave_room().await;
-                break;
-            }
-            "/help" | "/h" | "/?" => {
-                print_help();
-            }
-            "/room" => {
-                let room = client_loop.get_room().await;
-                println!("{} {}", "🚪 Room actuelle:".cyan(), room.cyan().bold());
-            }
-            "/clear" | "/cls" => {
-                clear_screen();
-                print_banner();
+    loop {
+        tokio::select! {
+            Some(event) = events.next() => {
+                print_event(&event);
+                print_prompt();
             }
-            _ => {
-                // Send message
-                if let Err(e) = client_loop.send_message(&message).await {
-                    println!("{} {}", "❌ Erreur d'envoi:".red(), e);
+            line = lines.next_line() => {
+                let message = match line {
+                    Ok(Some(m)) => m.trim().to_string(),
+                    _ => break,
+                };
+
+                if message.is_empty() {
+                    print_prompt();
+                    continue;
                 }
+
+                // Handle commands
+                match message.to_lowercase().as_str() {
+                    "/quit" | "/exit" | "/q" => {
+                        println!("{}", "👋 Au revoir!".yellow());
+                        let _ = client.disconnect().await;
+                        break;
+                    }
+                    "/help" | "/h" | "/?" => {
+                        print_help();
+                    }
+                    "/room" => {
+                        let room = client.get_room().await;
+                        println!("{} {}", "🚪 Room actuelle:".cyan(), room.cyan().bold());
+                    }
+                    "/members" => {
+                        if let Some(room) = client.room(&client.get_room().await).await {
+                            println!("{} {}", "👥 Membres:".cyan(), room.members().await.join(", "));
+                        }
+                    }
+                    command if command.starts_with("/join ") => {
+                        let room = message[6..].trim();
+                        let user = client.get_username().await;
+                        match client.join_room(room, &user).await {
+                            Ok(_) => println!("{} '{}'", "✅ Room rejointe:".green(), room.cyan()),
+                            Err(e) => println!("{} {}", "❌ Erreur:".red(), e),
+                        }
+                    }
+                    "/clear" | "/cls" => {
+                        clear_screen();
+                        print_banner();
+                    }
+                    _ => {
+                        // Send message
+                        if let Err(e) = client.send_message(&message).await {
+                            println!("{} {}", "❌ Erreur d'envoi:".red(), e);
+                        }
+                    }
+                }
+
+                print_prompt();
             }
         }
-
-        print!("{}", "📝 Vous: ".green().bold());
-        io::stdout().flush().unwrap();
     }
 
     Ok(())
@@ -0,0 +1,212 @@
+// SmaRTC Rust SDK - `#[hub]` proxy generation
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Error, FnArg, Ident, ItemTrait, LitStr, Pat, Path, ReturnType,
+    TraitItem, TraitItemFn, Type,
+};
+
+/// Turns a trait describing the client-to-server methods of a hub into a
+/// strongly typed proxy.
+///
+/// ```ignore
+/// #[hub]
+/// pub trait SignalHub {
+///     async fn join_session(&self, session_id: &str, username: &str);
+///     #[hub(name = "GetSessionStats")]
+///     async fn stats(&self, session_id: &str) -> SessionStats;
+///     #[hub(send)]
+///     async fn heartbeat(&self);
+/// }
+/// ```
+///
+/// Each method is rewritten to return `Result<T, HubError>` and a
+/// `SignalHubProxy` implementing the trait over a `HubConnection` is
+/// generated. Hub method names default to the PascalCase of the Rust name;
+/// `#[hub(send)]` methods do not wait for the server's completion.
+///
+/// `#[hub(crate = path)]` overrides the path of the SDK module.
+#[proc_macro_attribute]
+pub fn hub(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut sdk: Path = parse_quote!(::smartc::signaling);
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("crate") {
+            sdk = meta.value()?.parse()?;
+            Ok(())
+        } else {
+            Err(meta.error("unsupported hub attribute"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+
+    let item = parse_macro_input!(item as ItemTrait);
+    expand(item, sdk)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct HubMethod {
+    name: String,
+    send: bool,
+}
+
+fn expand(mut item: ItemTrait, sdk: Path) -> syn::Result<proc_macro2::TokenStream> {
+    let trait_name = &item.ident;
+    let proxy = format_ident!("{}Proxy", trait_name);
+    let vis = &item.vis;
+    let mut proxy_methods = Vec::new();
+
+    for trait_item in &mut item.items {
+        let TraitItem::Fn(method) = trait_item else {
+            return Err(Error::new_spanned(
+                trait_item,
+                "hub traits may only contain methods",
+            ));
+        };
+        let options = hub_method(method)?;
+        let sig = &mut method.sig;
+
+        if sig.asyncness.take().is_none() {
+            return Err(Error::new_spanned(
+                sig.fn_token,
+                "hub methods must be `async fn`",
+            ));
+        }
+        if !sig.generics.params.is_empty() {
+            return Err(Error::new_spanned(
+                &sig.generics,
+                "hub methods cannot be generic",
+            ));
+        }
+
+        let mut arguments = Vec::new();
+        for input in sig.inputs.iter().skip(1) {
+            let FnArg::Typed(arg) = input else {
+                return Err(Error::new_spanned(input, "unexpected receiver"));
+            };
+            let Pat::Ident(ident) = &*arg.pat else {
+                return Err(Error::new_spanned(
+                    &arg.pat,
+                    "hub method arguments must be identifiers",
+                ));
+            };
+            arguments.push(ident.ident.clone());
+        }
+        if !matches!(sig.inputs.first(), Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_none())
+        {
+            return Err(Error::new_spanned(
+                &sig.inputs,
+                "hub methods must take `&self`",
+            ));
+        }
+
+        let output: Type = match &sig.output {
+            ReturnType::Default => parse_quote!(()),
+            ReturnType::Type(_, ty) => (**ty).clone(),
+        };
+        if options.send && !matches!(&output, Type::Tuple(t) if t.elems.is_empty()) {
+            return Err(Error::new_spanned(
+                &sig.output,
+                "`#[hub(send)]` methods cannot return a value",
+            ));
+        }
+        sig.output = parse_quote! {
+            -> impl ::std::future::Future<Output = ::std::result::Result<#output, #sdk::HubError>> + Send
+        };
+
+        let target = LitStr::new(&options.name, Span::call_site());
+        let call = if options.send {
+            quote! { self.connection.send(#target, arguments).await }
+        } else {
+            quote! {
+                let result = self.connection.invoke(#target, arguments).await?;
+                #sdk::registry::from_result::<#output>(result)
+            }
+        };
+
+        proxy_methods.push(quote! {
+            #sig {
+                async move {
+                    let arguments = vec![#(#sdk::registry::to_argument(&#arguments)?),*];
+                    #call
+                }
+            }
+        });
+    }
+
+    Ok(quote! {
+        #item
+
+        /// Typed proxy generated by `#[hub]`
+        #[derive(Clone)]
+        #vis struct #proxy {
+            connection: ::std::sync::Arc<#sdk::connection::HubConnection>,
+        }
+
+        impl #proxy {
+            pub fn new(connection: ::std::sync::Arc<#sdk::connection::HubConnection>) -> Self {
+                Self { connection }
+            }
+        }
+
+        impl #trait_name for #proxy {
+            #(#proxy_methods)*
+        }
+    })
+}
+
+/// Reads and strips the `#[hub(...)]` attributes of a method
+fn hub_method(method: &mut TraitItemFn) -> syn::Result<HubMethod> {
+    let mut options = HubMethod {
+        name: pascal_case(&method.sig.ident),
+        send: false,
+    };
+
+    for attr in method.attrs.iter().filter(|a| a.path().is_ident("hub")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                options.name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("send") {
+                options.send = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"` or `send`"))
+            }
+        })?;
+    }
+    method.attrs.retain(|a| !a.path().is_ident("hub"));
+
+    Ok(options)
+}
+
+/// `send_signal_to_session` -> `SendSignalToSession`
+fn pascal_case(ident: &Ident) -> String {
+    ident
+        .to_string()
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pascal_case() {
+        let ident = Ident::new("send_signal_to_session", Span::call_site());
+        assert_eq!(pascal_case(&ident), "SendSignalToSession");
+        let ident = Ident::new("heartbeat", Span::call_site());
+        assert_eq!(pascal_case(&ident), "Heartbeat");
+    }
+}
@@ -0,0 +1,158 @@
+// SmaRTC Rust SDK - Time-limited TURN credentials (TURN REST API, coturn `use-auth-secret`)
+
+use super::url::IceUrl;
+use crate::ICEServer;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Separator between the expiry timestamp and the user, coturn's
+/// `rest-api-separator`
+pub const SEPARATOR: char = ':';
+
+/// Why TURN REST credentials were rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CredentialError {
+    /// The username does not start with an expiry timestamp
+    #[error("Not a TURN REST username: {0:?}")]
+    Malformed(String),
+    #[error("The credentials expired at {}", .expired_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())]
+    Expired { expired_at: SystemTime },
+    /// The credential was not derived from the shared secret
+    #[error("The credential does not match the username")]
+    InvalidCredential,
+}
+
+/// A username and credential valid until `expires_at`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnCredentials {
+    /// `expiry:user`
+    pub username: String,
+    /// Base64 of HMAC-SHA1(secret, username)
+    pub credential: String,
+    pub expires_at: SystemTime,
+}
+
+/// The secret shared with coturn (`static-auth-secret`), minting and
+/// checking credentials that expire after `ttl`
+#[derive(Clone)]
+pub struct TurnSecret {
+    secret: String,
+    pub ttl: Duration,
+}
+
+impl fmt::Debug for TurnSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TurnSecret").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+impl TurnSecret {
+    /// One day, as suggested by the TURN REST API draft
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn new(secret: &str, ttl: Duration) -> Self {
+        Self { secret: secret.to_string(), ttl }
+    }
+
+    /// Credentials for `user`, valid for `ttl` from now
+    pub fn mint(&self, user: &str) -> TurnCredentials {
+        self.mint_at(user, SystemTime::now())
+    }
+
+    pub fn mint_at(&self, user: &str, now: SystemTime) -> TurnCredentials {
+        // Timestamps are whole seconds: round the expiry down
+        let expiry = (now + self.ttl).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let username = match user.is_empty() {
+            true => expiry.to_string(),
+            false => format!("{}{}{}", expiry, SEPARATOR, user),
+        };
+        TurnCredentials {
+            credential: BASE64.encode(self.sign(&username).finalize().into_bytes()),
+            expires_at: UNIX_EPOCH + Duration::from_secs(expiry),
+            username,
+        }
+    }
+
+    /// An ICE server for `urls` with fresh credentials for `user`
+    pub fn server(&self, urls: Vec<IceUrl>, user: &str) -> ICEServer {
+        let credentials = self.mint(user);
+        ICEServer { urls, username: Some(credentials.username), credential: Some(credentials.credential) }
+    }
+
+    /// Checks credentials as coturn does, returning their expiry
+    pub fn validate(&self, username: &str, credential: &str) -> Result<SystemTime, CredentialError> {
+        self.validate_at(username, credential, SystemTime::now())
+    }
+
+    pub fn validate_at(
+        &self,
+        username: &str,
+        credential: &str,
+        now: SystemTime,
+    ) -> Result<SystemTime, CredentialError> {
+        let expires_at = expiry(username).ok_or_else(|| CredentialError::Malformed(username.to_string()))?;
+        let signature = BASE64.decode(credential).map_err(|_| CredentialError::InvalidCredential)?;
+        self.sign(username).verify_slice(&signature).map_err(|_| CredentialError::InvalidCredential)?;
+        if expires_at <= now {
+            return Err(CredentialError::Expired { expired_at: expires_at });
+        }
+        Ok(expires_at)
+    }
+
+    fn sign(&self, username: &str) -> Hmac<Sha1> {
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(username.as_bytes());
+        mac
+    }
+}
+
+/// Expiry of a TURN REST username, `None` for static usernames
+pub fn expiry(username: &str) -> Option<SystemTime> {
+    let timestamp = match username.split_once(SEPARATOR) {
+        Some((timestamp, _)) => timestamp,
+        None => username,
+    };
+    let seconds = timestamp.parse::<u64>().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_are_minted_and_validated() {
+        let secret = TurnSecret::new("north", Duration::from_secs(3600));
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let minted = secret.mint_at("alice", now);
+        assert_eq!(minted.username, "1700003600:alice");
+        // echo -n 1700003600:alice | openssl dgst -sha1 -hmac north -binary | base64
+        assert_eq!(minted.credential, "wjwSXO2ch1B6VaLTLMy2Avn5O9o=");
+        assert_eq!(expiry(&minted.username), Some(minted.expires_at));
+
+        assert_eq!(secret.validate_at(&minted.username, &minted.credential, now), Ok(minted.expires_at));
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(
+            secret.validate_at(&minted.username, &minted.credential, later),
+            Err(CredentialError::Expired { expired_at: minted.expires_at })
+        );
+        let forged = secret.validate_at("1800000000:alice", &minted.credential, now);
+        assert_eq!(forged, Err(CredentialError::InvalidCredential));
+        let other = TurnSecret::new("south", secret.ttl).validate_at(&minted.username, &minted.credential, now);
+        assert_eq!(other, Err(CredentialError::InvalidCredential));
+        assert_eq!(secret.validate_at("user", "password", now), Err(CredentialError::Malformed("user".into())));
+        assert_eq!(expiry("user"), None);
+
+        // Consumers of `get_ice_servers` can refetch before the expiry
+        let server = secret.server(vec!["turn:coturn:3478".parse().unwrap()], "alice");
+        assert!(!server.expires_within(Duration::from_secs(60)));
+        assert!(server.expires_within(Duration::from_secs(7200)));
+        let fixed = ICEServer { username: Some("user".into()), ..server };
+        assert!(fixed.expires_at().is_none() && !fixed.expires_within(Duration::MAX));
+    }
+}
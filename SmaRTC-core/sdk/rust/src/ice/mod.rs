@@ -0,0 +1,28 @@
+// SmaRTC Rust SDK - ICE servers: URLs, diagnostics and helpers around `get_ice_servers`
+
+pub mod credentials;
+pub mod stun;
+pub mod turn;
+pub mod url;
+
+#[cfg(test)]
+mod testing;
+
+pub use credentials::{CredentialError, TurnCredentials, TurnSecret};
+pub use self::stun::{diagnose, probe, IceDiagnostics, NatBehavior, ProbeError, ProbeOptions, StunProbe};
+pub use self::turn::{check_turn, check_turn_servers, TurnCheck, TurnCheckError, TurnReport, TurnStep};
+pub use url::{IceScheme, IceTransport, IceUrl, IceUrlError};
+
+use crate::ICEServer;
+
+/// Keeps the URLs matching `keep`, dropping servers left without any, e.g.
+/// `|url| url.scheme().is_turn()` for relays only
+pub fn filter_servers(servers: &[ICEServer], keep: impl Fn(&IceUrl) -> bool) -> Vec<ICEServer> {
+    servers
+        .iter()
+        .filter_map(|server| {
+            let urls: Vec<IceUrl> = server.urls.iter().filter(|url| keep(url)).cloned().collect();
+            (!urls.is_empty()).then(|| ICEServer { urls, ..server.clone() })
+        })
+        .collect()
+}
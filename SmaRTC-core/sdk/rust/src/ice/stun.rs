@@ -0,0 +1,357 @@
+// SmaRTC Rust SDK - STUN Binding probes: reachability, reflexive address and NAT hints
+
+use super::url::{IceTransport, IceUrl};
+use crate::ICEServer;
+use std::net::{SocketAddr, UdpSocket as StdUdpSocket};
+use std::time::Duration;
+use stun::addr::MappedAddress;
+use stun::agent::TransactionId;
+use stun::attributes::ATTR_FINGERPRINT;
+use stun::error_code::ErrorCodeAttribute;
+use stun::fingerprint::FINGERPRINT;
+use stun::message::{is_message, Getter, Message, BINDING_REQUEST, BINDING_SUCCESS, CLASS_ERROR_RESPONSE};
+use stun::xoraddr::XorMappedAddress;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+/// Retransmissions of a Binding request, as in RFC 5389 §7.2.1 but shorter
+/// so that a dead server is reported within a few seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeOptions {
+    /// Wait before the first retransmission, doubled after each one
+    pub rto: Duration,
+    pub attempts: u32,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        Self { rto: Duration::from_millis(500), attempts: 3 }
+    }
+}
+
+/// Why a server could not be probed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ProbeError {
+    /// The host name did not resolve
+    #[error("Could not resolve the server: {0}")]
+    Resolve(String),
+    #[error("Network error: {0}")]
+    Io(String),
+    /// Only UDP servers are probed
+    #[error("Cannot probe over {}, only over udp", .0.as_str())]
+    UnsupportedTransport(IceTransport),
+    /// A TURN server without `username` and `credential`
+    #[error("No username or credential for the TURN server")]
+    MissingCredentials,
+    /// No answer after every attempt
+    #[error("The server did not answer")]
+    Timeout,
+    /// Not a Binding response, or a wrong FINGERPRINT
+    #[error("Invalid STUN response: {0}")]
+    InvalidResponse(String),
+    /// The server answered with a Binding error
+    #[error("The server answered {code} {reason}")]
+    ErrorResponse { code: u16, reason: String },
+}
+
+impl From<std::io::Error> for ProbeError {
+    fn from(e: std::io::Error) -> Self {
+        ProbeError::Io(e.to_string())
+    }
+}
+
+/// A server that answered a Binding request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StunProbe {
+    pub url: IceUrl,
+    pub server: SocketAddr,
+    /// Our address towards the server
+    pub local: SocketAddr,
+    /// Our address as seen by the server, the server-reflexive candidate
+    pub reflexive: SocketAddr,
+    pub rtt: Duration,
+}
+
+impl StunProbe {
+    /// Whether a NAT rewrites our address towards this server
+    pub fn behind_nat(&self) -> bool {
+        self.local != self.reflexive
+    }
+}
+
+/// What the reflexive addresses tell about the NAT (RFC 4787)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatBehavior {
+    /// Our address is public, no NAT
+    Open,
+    /// Every server sees the same address: reflexive candidates work
+    EndpointIndependent,
+    /// Each server sees another address, e.g. a symmetric NAT: expect to
+    /// need TURN relays
+    EndpointDependent,
+    /// Behind a NAT, but less than two servers answered
+    Unknown,
+}
+
+/// Outcome of probing one URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeReport {
+    pub url: IceUrl,
+    pub result: Result<StunProbe, ProbeError>,
+}
+
+/// Probes of every UDP URL of a list of servers, sent from the same socket
+/// so that their reflexive addresses can be compared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceDiagnostics {
+    pub reports: Vec<ProbeReport>,
+}
+
+impl IceDiagnostics {
+    /// The servers that answered, fastest first
+    pub fn reachable(&self) -> Vec<&StunProbe> {
+        let mut reachable: Vec<&StunProbe> =
+            self.reports.iter().filter_map(|report| report.result.as_ref().ok()).collect();
+        reachable.sort_by_key(|probe| probe.rtt);
+        reachable
+    }
+
+    pub fn reflexive_address(&self) -> Option<SocketAddr> {
+        self.reachable().first().map(|probe| probe.reflexive)
+    }
+
+    pub fn nat_behavior(&self) -> NatBehavior {
+        let reachable = self.reachable();
+        if reachable.is_empty() {
+            return NatBehavior::Unknown;
+        }
+        if reachable.iter().all(|probe| !probe.behind_nat()) {
+            return NatBehavior::Open;
+        }
+        // Mappings are compared within an address family
+        let ipv4: Vec<SocketAddr> =
+            reachable.iter().map(|probe| probe.reflexive).filter(|address| address.is_ipv4()).collect();
+        let mappings = if ipv4.len() >= 2 { ipv4 } else { reachable.iter().map(|probe| probe.reflexive).collect() };
+        match mappings.as_slice() {
+            [] | [_] => NatBehavior::Unknown,
+            [first, rest @ ..] if rest.iter().all(|address| address == first) => NatBehavior::EndpointIndependent,
+            _ => NatBehavior::EndpointDependent,
+        }
+    }
+
+    /// Orders `servers` by the best round trip of their URLs; servers that
+    /// did not answer, or were not probed, come last in their original order
+    pub fn rank(&self, servers: &[ICEServer]) -> Vec<ICEServer> {
+        let best = |server: &ICEServer| {
+            self.reachable().into_iter().find(|probe| server.urls.contains(&probe.url)).map(|probe| probe.rtt)
+        };
+        let mut ranked: Vec<(Option<Duration>, &ICEServer)> =
+            servers.iter().map(|server| (best(server), server)).collect();
+        ranked.sort_by_key(|(rtt, _)| (rtt.is_none(), *rtt));
+        ranked.into_iter().map(|(_, server)| server.clone()).collect()
+    }
+}
+
+/// Sends a Binding request to `url` from a new socket
+pub async fn probe(url: &IceUrl, options: ProbeOptions) -> Result<StunProbe, ProbeError> {
+    Prober::default().probe(url, options).await
+}
+
+/// Probes every UDP URL of `servers`, STUN and TURN alike since TURN
+/// servers answer Binding requests too
+pub async fn diagnose(servers: &[ICEServer], options: ProbeOptions) -> IceDiagnostics {
+    let mut prober = Prober::default();
+    let mut reports = Vec::new();
+    for url in servers.iter().flat_map(|server| &server.urls) {
+        if reports.iter().any(|report: &ProbeReport| &report.url == url) {
+            continue;
+        }
+        let result = prober.probe(url, options).await;
+        reports.push(ProbeReport { url: url.clone(), result });
+    }
+    IceDiagnostics { reports }
+}
+
+/// One socket per address family, shared by the probes
+#[derive(Default)]
+struct Prober {
+    ipv4: Option<UdpSocket>,
+    ipv6: Option<UdpSocket>,
+}
+
+impl Prober {
+    async fn probe(&mut self, url: &IceUrl, options: ProbeOptions) -> Result<StunProbe, ProbeError> {
+        let server = resolve(url).await?;
+        let socket = self.socket(server).await?;
+        let local = local_address(server, socket.local_addr()?.port())?;
+
+        let mut request = Message::new();
+        request
+            .build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST), Box::new(FINGERPRINT)])
+            .map_err(|e| ProbeError::InvalidResponse(e.to_string()))?;
+        let (response, rtt) = transact(socket, server, &request, options).await?;
+        let reflexive = reflexive_address(&response)?;
+        Ok(StunProbe { url: url.clone(), server, local, reflexive, rtt })
+    }
+
+    async fn socket(&mut self, server: SocketAddr) -> Result<&UdpSocket, ProbeError> {
+        let (slot, any) = match server {
+            SocketAddr::V4(_) => (&mut self.ipv4, "0.0.0.0:0"),
+            SocketAddr::V6(_) => (&mut self.ipv6, "[::]:0"),
+        };
+        if slot.is_none() {
+            *slot = Some(UdpSocket::bind(any).await?);
+        }
+        Ok(slot.as_ref().expect("bound above"))
+    }
+}
+
+/// The address of a UDP server
+pub(super) async fn resolve(url: &IceUrl) -> Result<SocketAddr, ProbeError> {
+    if url.transport() != IceTransport::Udp {
+        return Err(ProbeError::UnsupportedTransport(url.transport()));
+    }
+    tokio::net::lookup_host(url.address())
+        .await
+        .map_err(|e| ProbeError::Resolve(e.to_string()))?
+        .next()
+        .ok_or_else(|| ProbeError::Resolve(format!("no address for {}", url.host())))
+}
+
+/// The interface address the system picks towards `server`, with our port
+fn local_address(server: SocketAddr, port: u16) -> Result<SocketAddr, ProbeError> {
+    let any = match server {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let route = StdUdpSocket::bind(any)?;
+    route.connect(server)?;
+    Ok(SocketAddr::new(route.local_addr()?.ip(), port))
+}
+
+/// Sends `request` until it is answered, and checks the FINGERPRINT of the
+/// response. The round trip is measured from the last transmission.
+pub(super) async fn transact(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    request: &Message,
+    options: ProbeOptions,
+) -> Result<(Message, Duration), ProbeError> {
+    let mut rto = options.rto;
+    let mut buffer = [0u8; 1500];
+    for _ in 0..options.attempts {
+        socket.send_to(&request.raw, server).await?;
+        let sent = Instant::now();
+        loop {
+            let Ok(received) = tokio::time::timeout_at(sent + rto, socket.recv_from(&mut buffer)).await else {
+                break;
+            };
+            let (size, from) = received?;
+            // Late answers to a previous probe share the socket
+            if from != server || !is_message(&buffer[..size]) {
+                continue;
+            }
+            let mut response = Message::new();
+            if response.unmarshal_binary(&buffer[..size]).is_err() || response.transaction_id != request.transaction_id
+            {
+                continue;
+            }
+            if response.contains(ATTR_FINGERPRINT) {
+                FINGERPRINT.check(&response).map_err(|e| ProbeError::InvalidResponse(e.to_string()))?;
+            }
+            return Ok((response, sent.elapsed()));
+        }
+        rto *= 2;
+    }
+    Err(ProbeError::Timeout)
+}
+
+/// The error carried by an error response
+pub(super) fn rejection(response: &Message) -> Option<ProbeError> {
+    if response.typ.class != CLASS_ERROR_RESPONSE {
+        return None;
+    }
+    let mut error = ErrorCodeAttribute::default();
+    if let Err(e) = error.get_from(response) {
+        return Some(ProbeError::InvalidResponse(e.to_string()));
+    }
+    let reason = String::from_utf8_lossy(&error.reason).to_string();
+    Some(ProbeError::ErrorResponse { code: error.code.0, reason })
+}
+
+fn reflexive_address(response: &Message) -> Result<SocketAddr, ProbeError> {
+    if let Some(error) = rejection(response) {
+        return Err(error);
+    }
+    if response.typ != BINDING_SUCCESS {
+        return Err(ProbeError::InvalidResponse(format!("unexpected {}", response.typ)));
+    }
+
+    // Old servers only send MAPPED-ADDRESS
+    let mut xor_mapped = XorMappedAddress::default();
+    if xor_mapped.get_from(response).is_ok() {
+        return Ok(SocketAddr::new(xor_mapped.ip, xor_mapped.port));
+    }
+    let mut mapped = MappedAddress::default();
+    mapped.get_from(response).map_err(|_| ProbeError::InvalidResponse("no mapped address".into()))?;
+    Ok(SocketAddr::new(mapped.ip, mapped.port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::StunResponder;
+    use super::*;
+
+    fn server(responder: &StunResponder) -> ICEServer {
+        ICEServer { urls: vec![responder.url.clone()], username: None, credential: None }
+    }
+
+    #[tokio::test]
+    async fn test_probes_report_the_reflexive_address() {
+        let responder = StunResponder::start().await;
+        let probe = probe(&responder.url, ProbeOptions::default()).await.unwrap();
+        assert_eq!(probe.server, responder.address);
+        assert_eq!(probe.reflexive, probe.local);
+        assert!(!probe.behind_nat());
+
+        // Nothing answers on a bound but silent socket
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = format!("stun:127.0.0.1:{}", silent.local_addr().unwrap().port()).parse().unwrap();
+        let options = ProbeOptions { rto: Duration::from_millis(20), attempts: 2 };
+        assert_eq!(super::probe(&url, options).await, Err(ProbeError::Timeout));
+
+        let tls = "turns:127.0.0.1".parse().unwrap();
+        assert_eq!(super::probe(&tls, options).await, Err(ProbeError::UnsupportedTransport(IceTransport::Tcp)));
+    }
+
+    #[tokio::test]
+    async fn test_servers_are_ranked_by_latency() {
+        let slow = StunResponder::with(Duration::from_millis(100), |source| source).await;
+        let fast = StunResponder::start().await;
+        let servers = vec![server(&slow), server(&fast)];
+
+        let diagnostics = diagnose(&servers, ProbeOptions::default()).await;
+        assert_eq!(diagnostics.reachable().len(), 2);
+        assert_eq!(diagnostics.nat_behavior(), NatBehavior::Open);
+        let ranked = diagnostics.rank(&servers);
+        assert_eq!(ranked[0].urls, vec![fast.url.clone()]);
+        assert_eq!(ranked[1].urls, vec![slow.url.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_nat_behavior_is_inferred_from_the_mappings() {
+        let public = |port| move |_| SocketAddr::from(([203, 0, 113, 7], port));
+        let first = StunResponder::with(Duration::ZERO, public(40000)).await;
+        let same = StunResponder::with(Duration::ZERO, public(40000)).await;
+        let other = StunResponder::with(Duration::ZERO, public(40001)).await;
+
+        let independent = diagnose(&[server(&first), server(&same)], ProbeOptions::default()).await;
+        assert_eq!(independent.nat_behavior(), NatBehavior::EndpointIndependent);
+        assert_eq!(independent.reflexive_address(), Some(SocketAddr::from(([203, 0, 113, 7], 40000))));
+        let symmetric = diagnose(&[server(&first), server(&other)], ProbeOptions::default()).await;
+        assert_eq!(symmetric.nat_behavior(), NatBehavior::EndpointDependent);
+        let alone = diagnose(&[server(&first)], ProbeOptions::default()).await;
+        assert_eq!(alone.nat_behavior(), NatBehavior::Unknown);
+    }
+}
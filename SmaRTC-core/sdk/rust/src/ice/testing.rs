@@ -0,0 +1,129 @@
+// SmaRTC Rust SDK - In-process STUN and TURN servers used by the SDK tests
+
+use super::url::IceUrl;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use stun::fingerprint::FINGERPRINT;
+use stun::message::{is_message, Message, Setter, BINDING_REQUEST, BINDING_SUCCESS};
+use stun::xoraddr::XorMappedAddress;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use webrtc::turn::auth::{generate_auth_key, AuthHandler};
+use webrtc::turn::relay::relay_static::RelayAddressGeneratorStatic;
+use webrtc::turn::server::config::{ConnConfig, ServerConfig};
+use webrtc::turn::server::Server;
+use webrtc::util::vnet::net::Net;
+
+/// Answers Binding requests on a loopback port, like coturn with
+/// `fingerprint` enabled
+pub struct StunResponder {
+    pub url: IceUrl,
+    pub address: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl StunResponder {
+    /// Reports the source address of the requests as is
+    pub async fn start() -> Self {
+        Self::with(Duration::ZERO, |source| source).await
+    }
+
+    /// Answers after `delay`, reporting `map(source)` as if behind a NAT
+    pub async fn with(delay: Duration, map: impl Fn(SocketAddr) -> SocketAddr + Send + Sync + 'static) -> Self {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let address = socket.local_addr().unwrap();
+        let map = Arc::new(map);
+        let task = tokio::spawn(async move {
+            let mut buffer = [0u8; 1500];
+            while let Ok((size, source)) = socket.recv_from(&mut buffer).await {
+                let mut request = Message::new();
+                if !is_message(&buffer[..size]) || request.unmarshal_binary(&buffer[..size]).is_err() {
+                    continue;
+                }
+                if request.typ != BINDING_REQUEST {
+                    continue;
+                }
+                let mapped = map(source);
+                let mut response = Message::new();
+                let built = response.build(&[
+                    Box::new(request) as Box<dyn Setter>,
+                    Box::new(BINDING_SUCCESS),
+                    Box::new(XorMappedAddress { ip: mapped.ip(), port: mapped.port() }),
+                    Box::new(FINGERPRINT),
+                ]);
+                if built.is_err() {
+                    continue;
+                }
+                let socket = socket.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = socket.send_to(&response.raw, source).await;
+                });
+            }
+        });
+        let url = format!("stun:{}", address).parse().unwrap();
+        Self { url, address, task }
+    }
+}
+
+impl Drop for StunResponder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A TURN server with one long-term user, relaying on loopback
+pub struct TurnResponder {
+    pub url: IceUrl,
+    pub address: SocketAddr,
+    server: Arc<Server>,
+}
+
+struct OneUser {
+    username: String,
+    password: String,
+}
+
+impl AuthHandler for OneUser {
+    fn auth_handle(&self, username: &str, realm: &str, _source: SocketAddr) -> Result<Vec<u8>, webrtc::turn::Error> {
+        match username == self.username {
+            true => Ok(generate_auth_key(username, realm, &self.password)),
+            false => Err(webrtc::turn::Error::ErrNoSuchUser),
+        }
+    }
+}
+
+impl TurnResponder {
+    /// Same realm as `turnserver.conf`
+    pub async fn start(username: &str, password: &str) -> Self {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let address = socket.local_addr().unwrap();
+        let server = Server::new(ServerConfig {
+            conn_configs: vec![ConnConfig {
+                conn: socket,
+                relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                    relay_address: address.ip(),
+                    address: "127.0.0.1".to_string(),
+                    net: Arc::new(Net::new(None)),
+                }),
+            }],
+            realm: "tunrtc.org".to_string(),
+            auth_handler: Arc::new(OneUser { username: username.to_string(), password: password.to_string() }),
+            channel_bind_timeout: Duration::ZERO,
+        })
+        .await
+        .unwrap();
+        let url = format!("turn:{}", address).parse().unwrap();
+        Self { url, address, server: Arc::new(server) }
+    }
+}
+
+impl Drop for TurnResponder {
+    fn drop(&mut self) {
+        let server = self.server.clone();
+        tokio::spawn(async move {
+            let _ = server.close().await;
+        });
+    }
+}
@@ -0,0 +1,381 @@
+// SmaRTC Rust SDK - TURN allocation self-test with long-term credentials (RFC 5766)
+
+use super::stun::{rejection, resolve, transact, ProbeError, ProbeOptions};
+use super::url::{IceScheme, IceTransport, IceUrl};
+use crate::ICEServer;
+use std::net::SocketAddr;
+use std::time::Duration;
+use stun::agent::TransactionId;
+use stun::attributes::{ATTR_NONCE, ATTR_REALM, ATTR_USERNAME};
+use stun::fingerprint::FINGERPRINT;
+use stun::integrity::MessageIntegrity;
+use stun::message::{
+    is_message, Getter, Message, MessageType, Setter, CLASS_INDICATION, CLASS_REQUEST, CLASS_SUCCESS_RESPONSE,
+    METHOD_ALLOCATE, METHOD_CREATE_PERMISSION, METHOD_DATA, METHOD_REFRESH, METHOD_SEND,
+};
+use stun::textattrs::{Nonce, Realm, Username};
+use stun::xoraddr::XorMappedAddress;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use webrtc::turn::proto::data::Data;
+use webrtc::turn::proto::lifetime::Lifetime;
+use webrtc::turn::proto::peeraddr::PeerAddress;
+use webrtc::turn::proto::relayaddr::RelayedAddress;
+use webrtc::turn::proto::reqtrans::RequestedTransport;
+use webrtc::turn::proto::PROTO_UDP;
+
+/// 401: credentials are required, or wrong
+const CODE_UNAUTHORIZED: u16 = 401;
+/// 438: the nonce expired, the request is sent again with the new one
+const CODE_STALE_NONCE: u16 = 438;
+
+const PAYLOAD: &[u8] = b"smartc-turn-check";
+
+/// Steps of [`check_turn`], in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnStep {
+    /// Resolving the server address
+    Resolve,
+    /// An unauthenticated Allocate, answered with the realm and a nonce
+    Challenge,
+    /// The authenticated Allocate: a wrong username or credential fails here
+    Allocate,
+    CreatePermission,
+    /// Data sent through one relay and received through another one
+    Relay,
+}
+
+impl TurnStep {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TurnStep::Resolve => "resolve",
+            TurnStep::Challenge => "challenge",
+            TurnStep::Allocate => "allocate",
+            TurnStep::CreatePermission => "create-permission",
+            TurnStep::Relay => "relay",
+        }
+    }
+}
+
+/// The step that failed, and why
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("TURN {} failed: {error}", .step.as_str())]
+pub struct TurnCheckError {
+    pub step: TurnStep,
+    pub error: ProbeError,
+}
+
+/// A TURN server that relayed data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnReport {
+    pub url: IceUrl,
+    pub server: SocketAddr,
+    /// The relayed transport address of the allocation
+    pub relayed: SocketAddr,
+    /// Our address as seen by the server
+    pub reflexive: SocketAddr,
+    pub lifetime: Duration,
+    /// Round trip of the authenticated Allocate
+    pub rtt: Duration,
+}
+
+/// Outcome of checking one URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnCheck {
+    pub url: IceUrl,
+    pub result: Result<TurnReport, TurnCheckError>,
+}
+
+/// Checks every UDP `turn:` URL of `servers` with their credentials; `turns:`
+/// and `?transport=tcp` URLs are skipped
+pub async fn check_turn_servers(servers: &[ICEServer], options: ProbeOptions) -> Vec<TurnCheck> {
+    let mut checks = Vec::new();
+    for server in servers {
+        let udp = |url: &&IceUrl| url.scheme() == IceScheme::Turn && url.transport() == IceTransport::Udp;
+        for url in server.urls.iter().filter(udp) {
+            let result = match (&server.username, &server.credential) {
+                (Some(username), Some(credential)) => check_turn(url, username, credential, options).await,
+                _ => Err(TurnCheckError { step: TurnStep::Challenge, error: ProbeError::MissingCredentials }),
+            };
+            checks.push(TurnCheck { url: url.clone(), result });
+        }
+    }
+    checks
+}
+
+/// Allocates two relays on the server, lets them talk to each other with
+/// CreatePermission, and sends data from one to the other. Going through
+/// two relays works whatever our NAT filters.
+pub async fn check_turn(
+    url: &IceUrl,
+    username: &str,
+    credential: &str,
+    options: ProbeOptions,
+) -> Result<TurnReport, TurnCheckError> {
+    let fail = |step| move |error| TurnCheckError { step, error };
+    let server = resolve(url).await.map_err(fail(TurnStep::Resolve))?;
+
+    let mut sender = Allocation::open(server, username, credential, options).await?;
+    let mut receiver = match Allocation::open(server, username, credential, options).await {
+        Ok(receiver) => receiver,
+        Err(error) => {
+            sender.release().await;
+            return Err(error);
+        }
+    };
+    let checked = async {
+        sender.create_permission(receiver.relayed).await.map_err(fail(TurnStep::CreatePermission))?;
+        receiver.create_permission(sender.relayed).await.map_err(fail(TurnStep::CreatePermission))?;
+        relay(&sender, &receiver).await.map_err(fail(TurnStep::Relay))
+    }
+    .await;
+    sender.release().await;
+    receiver.release().await;
+    checked?;
+
+    Ok(TurnReport {
+        url: url.clone(),
+        server,
+        relayed: sender.relayed,
+        reflexive: sender.reflexive,
+        lifetime: sender.lifetime,
+        rtt: sender.rtt,
+    })
+}
+
+/// Sends a Send indication through `sender` until `receiver` gets it as a
+/// Data indication; indications are not retransmitted by the server
+async fn relay(sender: &Allocation, receiver: &Allocation) -> Result<(), ProbeError> {
+    let mut rto = sender.options.rto;
+    let mut buffer = [0u8; 1500];
+    for _ in 0..sender.options.attempts {
+        let mut indication = Message::new();
+        indication
+            .build(&[
+                Box::new(TransactionId::new()),
+                Box::new(MessageType::new(METHOD_SEND, CLASS_INDICATION)),
+                Box::new(peer_address(receiver.relayed)),
+                Box::new(Data(PAYLOAD.to_vec())),
+                Box::new(FINGERPRINT),
+            ])
+            .map_err(|e| ProbeError::InvalidResponse(e.to_string()))?;
+        sender.socket.send_to(&indication.raw, sender.server).await?;
+
+        let sent = Instant::now();
+        while let Ok(received) = tokio::time::timeout_at(sent + rto, receiver.socket.recv_from(&mut buffer)).await {
+            let (size, from) = received?;
+            let mut message = Message::new();
+            let packet = &buffer[..size];
+            if from != receiver.server || !is_message(packet) || message.unmarshal_binary(packet).is_err() {
+                continue;
+            }
+            if message.typ != MessageType::new(METHOD_DATA, CLASS_INDICATION) {
+                continue;
+            }
+            let (mut data, mut peer) = (Data::default(), PeerAddress::default());
+            if data.get_from(&message).is_ok()
+                && peer.get_from(&message).is_ok()
+                && data.0 == PAYLOAD
+                && SocketAddr::new(peer.ip, peer.port) == sender.relayed
+            {
+                return Ok(());
+            }
+        }
+        rto *= 2;
+    }
+    Err(ProbeError::Timeout)
+}
+
+fn peer_address(address: SocketAddr) -> PeerAddress {
+    PeerAddress { ip: address.ip(), port: address.port() }
+}
+
+/// Long-term credentials, once the server sent its realm and nonce
+struct Credentials {
+    username: String,
+    realm: String,
+    nonce: String,
+    integrity: MessageIntegrity,
+}
+
+/// An allocation, on its own socket
+struct Allocation {
+    socket: UdpSocket,
+    server: SocketAddr,
+    options: ProbeOptions,
+    credentials: Credentials,
+    relayed: SocketAddr,
+    reflexive: SocketAddr,
+    lifetime: Duration,
+    rtt: Duration,
+}
+
+impl Allocation {
+    async fn open(
+        server: SocketAddr,
+        username: &str,
+        password: &str,
+        options: ProbeOptions,
+    ) -> Result<Self, TurnCheckError> {
+        let fail = |step| move |error| TurnCheckError { step, error };
+        let challenge_failed = fail(TurnStep::Challenge);
+        let any = match server {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(any).await.map_err(|e| challenge_failed(e.into()))?;
+
+        // The server answers 401 with its realm and a nonce
+        let mut request = Message::new();
+        request
+            .build(&[
+                Box::new(TransactionId::new()),
+                Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+                Box::new(RequestedTransport { protocol: PROTO_UDP }),
+                Box::new(FINGERPRINT),
+            ])
+            .map_err(|e| challenge_failed(ProbeError::InvalidResponse(e.to_string())))?;
+        let (challenge, _) = transact(&socket, server, &request, options).await.map_err(challenge_failed)?;
+        match rejection(&challenge) {
+            Some(ProbeError::ErrorResponse { code: CODE_UNAUTHORIZED, .. }) => {}
+            Some(error) => return Err(challenge_failed(error)),
+            None => {
+                return Err(challenge_failed(ProbeError::InvalidResponse("allocated without credentials".into())));
+            }
+        }
+        let (Ok(realm), Ok(nonce)) =
+            (Realm::get_from_as(&challenge, ATTR_REALM), Nonce::get_from_as(&challenge, ATTR_NONCE))
+        else {
+            return Err(challenge_failed(ProbeError::InvalidResponse("no REALM or NONCE in the challenge".into())));
+        };
+        let integrity =
+            MessageIntegrity::new_long_term_integrity(username.to_string(), realm.text.clone(), password.to_string());
+        let credentials =
+            Credentials { username: username.to_string(), realm: realm.text, nonce: nonce.text, integrity };
+
+        let mut allocation = Allocation {
+            socket,
+            server,
+            options,
+            credentials,
+            relayed: server,
+            reflexive: server,
+            lifetime: Duration::ZERO,
+            rtt: Duration::ZERO,
+        };
+        let (response, rtt) = allocation
+            .request(METHOD_ALLOCATE, || vec![Box::new(RequestedTransport { protocol: PROTO_UDP })])
+            .await
+            .map_err(fail(TurnStep::Allocate))?;
+
+        let (mut relayed, mut reflexive, mut lifetime) =
+            (RelayedAddress::default(), XorMappedAddress::default(), Lifetime::default());
+        relayed
+            .get_from(&response)
+            .map_err(|_| fail(TurnStep::Allocate)(ProbeError::InvalidResponse("no XOR-RELAYED-ADDRESS".into())))?;
+        // Both are optional in practice
+        let _ = reflexive.get_from(&response);
+        let _ = lifetime.get_from(&response);
+        allocation.relayed = SocketAddr::new(relayed.ip, relayed.port);
+        allocation.reflexive = SocketAddr::new(reflexive.ip, reflexive.port);
+        allocation.lifetime = lifetime.0;
+        allocation.rtt = rtt;
+        Ok(allocation)
+    }
+
+    async fn create_permission(&mut self, peer: SocketAddr) -> Result<(), ProbeError> {
+        self.request(METHOD_CREATE_PERMISSION, || vec![Box::new(peer_address(peer))]).await?;
+        Ok(())
+    }
+
+    /// Frees the relay right away instead of waiting for its lifetime
+    async fn release(&mut self) {
+        let _ = self.request(METHOD_REFRESH, || vec![Box::new(Lifetime(Duration::ZERO))]).await;
+    }
+
+    /// Sends an authenticated request, again with the new nonce if it went
+    /// stale, and checks the MESSAGE-INTEGRITY of the success response
+    async fn request(
+        &mut self,
+        method: stun::message::Method,
+        attributes: impl Fn() -> Vec<Box<dyn Setter>>,
+    ) -> Result<(Message, Duration), ProbeError> {
+        let mut stale = false;
+        loop {
+            let credentials = &self.credentials;
+            let mut setters: Vec<Box<dyn Setter>> =
+                vec![Box::new(TransactionId::new()), Box::new(MessageType::new(method, CLASS_REQUEST))];
+            setters.extend(attributes());
+            setters.push(Box::new(Username::new(ATTR_USERNAME, credentials.username.clone())));
+            setters.push(Box::new(Realm::new(ATTR_REALM, credentials.realm.clone())));
+            setters.push(Box::new(Nonce::new(ATTR_NONCE, credentials.nonce.clone())));
+            setters.push(Box::new(credentials.integrity.clone()));
+            setters.push(Box::new(FINGERPRINT));
+            let mut request = Message::new();
+            request.build(&setters).map_err(|e| ProbeError::InvalidResponse(e.to_string()))?;
+
+            let (mut response, rtt) = transact(&self.socket, self.server, &request, self.options).await?;
+            match rejection(&response) {
+                Some(ProbeError::ErrorResponse { code: CODE_STALE_NONCE, .. }) if !stale => {
+                    let nonce = Nonce::get_from_as(&response, ATTR_NONCE)
+                        .map_err(|_| ProbeError::InvalidResponse("no NONCE with 438".into()))?;
+                    self.credentials.nonce = nonce.text;
+                    stale = true;
+                    continue;
+                }
+                Some(error) => return Err(error),
+                None => {}
+            }
+            if response.typ != MessageType::new(method, CLASS_SUCCESS_RESPONSE) {
+                return Err(ProbeError::InvalidResponse(format!("unexpected {}", response.typ)));
+            }
+            self.credentials
+                .integrity
+                .check(&mut response)
+                .map_err(|_| ProbeError::InvalidResponse("wrong MESSAGE-INTEGRITY".into()))?;
+            return Ok((response, rtt));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::TurnResponder;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_allocations_relay_data() {
+        let turn = TurnResponder::start("user", "password").await;
+        let report = check_turn(&turn.url, "user", "password", ProbeOptions::default()).await.unwrap();
+        assert_eq!(report.server, turn.address);
+        assert_eq!(report.relayed.ip(), turn.address.ip());
+        assert!(report.lifetime > Duration::ZERO);
+
+        let urls = ["stun:127.0.0.1", "turn:127.0.0.1?transport=tcp", "turns:127.0.0.1"];
+        let servers = vec![
+            ICEServer { urls: vec![turn.url.clone()], username: None, credential: None },
+            ICEServer { urls: urls.iter().map(|url| url.parse().unwrap()).collect(), username: None, credential: None },
+        ];
+        let checks = check_turn_servers(&servers, ProbeOptions::default()).await;
+        assert_eq!(checks.len(), 1);
+        let missing = checks[0].result.clone().unwrap_err();
+        assert_eq!(missing.error, ProbeError::MissingCredentials);
+    }
+
+    #[tokio::test]
+    async fn test_the_failing_step_is_reported() {
+        let turn = TurnResponder::start("user", "password").await;
+        let wrong = check_turn(&turn.url, "user", "wrong", ProbeOptions::default()).await.unwrap_err();
+        assert_eq!(wrong.step, TurnStep::Allocate);
+        // coturn answers 401, this server 400
+        assert!(matches!(wrong.error, ProbeError::ErrorResponse { .. }), "{}", wrong);
+
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = format!("turn:{}", silent.local_addr().unwrap()).parse().unwrap();
+        let options = ProbeOptions { rto: Duration::from_millis(20), attempts: 2 };
+        let unreachable = check_turn(&url, "user", "password", options).await.unwrap_err();
+        assert_eq!(unreachable, TurnCheckError { step: TurnStep::Challenge, error: ProbeError::Timeout });
+        assert_eq!(unreachable.to_string(), "TURN challenge failed: The server did not answer");
+    }
+}
@@ -0,0 +1,318 @@
+// SmaRTC Rust SDK - STUN and TURN URLs (RFC 7064, RFC 7065)
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Scheme of an ICE server URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IceScheme {
+    Stun,
+    /// STUN over TLS
+    Stuns,
+    Turn,
+    /// TURN over TLS
+    Turns,
+}
+
+impl IceScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IceScheme::Stun => "stun",
+            IceScheme::Stuns => "stuns",
+            IceScheme::Turn => "turn",
+            IceScheme::Turns => "turns",
+        }
+    }
+
+    /// Whether the server relays media, and thus needs credentials
+    pub fn is_turn(&self) -> bool {
+        matches!(self, IceScheme::Turn | IceScheme::Turns)
+    }
+
+    /// Whether the server is reached over TLS
+    pub fn is_secure(&self) -> bool {
+        matches!(self, IceScheme::Stuns | IceScheme::Turns)
+    }
+
+    /// 3478, or 5349 over TLS
+    pub fn default_port(&self) -> u16 {
+        match self.is_secure() {
+            true => 5349,
+            false => 3478,
+        }
+    }
+}
+
+/// Transport used to reach a server, the `?transport=` parameter of TURN URLs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IceTransport {
+    Udp,
+    Tcp,
+}
+
+impl IceTransport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IceTransport::Udp => "udp",
+            IceTransport::Tcp => "tcp",
+        }
+    }
+}
+
+/// Why an ICE server URL was rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IceUrlError {
+    /// Neither `stun`, `stuns`, `turn` nor `turns`
+    #[error("Unknown scheme {0:?} (expected stun, stuns, turn or turns)")]
+    UnknownScheme(String),
+    /// Empty, or not a host name nor an IP address
+    #[error("Invalid host {0:?}")]
+    InvalidHost(String),
+    #[error("Invalid port {0:?}")]
+    InvalidPort(String),
+    /// `transport` other than `udp` or `tcp`
+    #[error("Invalid transport {0:?} (expected udp or tcp)")]
+    InvalidTransport(String),
+    /// STUN URLs take no parameters, TURN ones only `transport`
+    #[error("Unexpected parameters {0:?}")]
+    UnexpectedQuery(String),
+}
+
+/// A `stun:`, `stuns:`, `turn:` or `turns:` URL, as found in `RTCIceServer.urls`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IceUrl {
+    scheme: IceScheme,
+    /// Without the brackets of IPv6 addresses
+    host: String,
+    port: Option<u16>,
+    transport: Option<IceTransport>,
+}
+
+impl IceUrl {
+    /// A URL without `transport` parameter
+    pub fn new(scheme: IceScheme, host: &str, port: Option<u16>) -> Result<Self, IceUrlError> {
+        validate_host(host)?;
+        Ok(Self { scheme, host: host.to_string(), port, transport: None })
+    }
+
+    pub fn scheme(&self) -> IceScheme {
+        self.scheme
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port of the URL, or the default one of its scheme
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.scheme.default_port())
+    }
+
+    /// The `transport` parameter, or the transport implied by the scheme:
+    /// TCP over TLS, UDP otherwise
+    pub fn transport(&self) -> IceTransport {
+        match (self.transport, self.scheme.is_secure()) {
+            (Some(transport), _) => transport,
+            (None, true) => IceTransport::Tcp,
+            (None, false) => IceTransport::Udp,
+        }
+    }
+
+    /// `host:port`, ready to be resolved
+    pub fn address(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port()),
+            false => format!("{}:{}", self.host, self.port()),
+        }
+    }
+}
+
+impl FromStr for IceUrl {
+    type Err = IceUrlError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let url = url.trim();
+        let (scheme, rest) = url.split_once(':').ok_or_else(|| IceUrlError::UnknownScheme(url.to_string()))?;
+        let scheme = match scheme.to_ascii_lowercase().as_str() {
+            "stun" => IceScheme::Stun,
+            "stuns" => IceScheme::Stuns,
+            "turn" => IceScheme::Turn,
+            "turns" => IceScheme::Turns,
+            _ => return Err(IceUrlError::UnknownScheme(scheme.to_string())),
+        };
+        let (authority, query) = match rest.split_once('?') {
+            Some((authority, query)) => (authority, Some(query)),
+            None => (rest, None),
+        };
+
+        // `[v6]:port`, `host:port` or just the host
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) =
+                    bracketed.split_once(']').ok_or_else(|| IceUrlError::InvalidHost(authority.to_string()))?;
+                host.parse::<Ipv6Addr>().map_err(|_| IceUrlError::InvalidHost(host.to_string()))?;
+                match after {
+                    "" => (host, None),
+                    _ => {
+                        let port = after.strip_prefix(':').ok_or_else(|| IceUrlError::InvalidPort(after.to_string()))?;
+                        (host, Some(port))
+                    }
+                }
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if !host.contains(':') {
+            validate_host(host)?;
+        }
+        let port = match port {
+            Some(port) => Some(
+                port.parse::<u16>()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or_else(|| IceUrlError::InvalidPort(port.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let transport = match query {
+            None => None,
+            Some(query) if !scheme.is_turn() => return Err(IceUrlError::UnexpectedQuery(query.to_string())),
+            Some(query) => match query.split_once('=') {
+                Some(("transport", transport)) => match transport.to_ascii_lowercase().as_str() {
+                    "udp" => Some(IceTransport::Udp),
+                    "tcp" => Some(IceTransport::Tcp),
+                    _ => return Err(IceUrlError::InvalidTransport(transport.to_string())),
+                },
+                _ => return Err(IceUrlError::UnexpectedQuery(query.to_string())),
+            },
+        };
+        Ok(Self { scheme, host: host.to_string(), port, transport })
+    }
+}
+
+/// Host names are letters, digits, `-`, `_` and dots; IPv6 addresses are
+/// checked when parsing the brackets
+fn validate_host(host: &str) -> Result<(), IceUrlError> {
+    let valid = match host.contains(':') {
+        true => host.parse::<Ipv6Addr>().is_ok(),
+        false => {
+            !host.is_empty() && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        }
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(IceUrlError::InvalidHost(host.to_string())),
+    }
+}
+
+impl fmt::Display for IceUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.scheme.as_str())?;
+        match self.host.contains(':') {
+            true => write!(f, "[{}]", self.host)?,
+            false => write!(f, "{}", self.host)?,
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        if let Some(transport) = self.transport {
+            write!(f, "?transport={}", transport.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for IceUrl {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IceUrl {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let url = String::deserialize(deserializer)?;
+        url.parse().map_err(|e| de::Error::custom(format!("invalid ICE URL {:?}: {}", url, e)))
+    }
+}
+
+/// `urls` of `RTCIceServer`: one URL, or a list of them
+pub fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<IceUrl>, D::Error> {
+    unparsed(deserializer)?
+        .into_iter()
+        .map(|url| url.parse().map_err(|e| de::Error::custom(format!("invalid ICE URL {:?}: {}", url, e))))
+        .collect()
+}
+
+/// Like [`one_or_many`], leaving the URLs unparsed so that the caller can
+/// report which one is invalid
+pub(crate) fn unparsed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Urls {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Urls::deserialize(deserializer)? {
+        Urls::One(url) => vec![url],
+        Urls::Many(urls) => urls,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ICEServer;
+
+    #[test]
+    fn test_urls_are_parsed() {
+        let stun: IceUrl = "stun:coturn:3478".parse().unwrap();
+        assert_eq!((stun.scheme(), stun.host(), stun.port()), (IceScheme::Stun, "coturn", 3478));
+        assert_eq!(stun.transport(), IceTransport::Udp);
+
+        let turn: IceUrl = "turn:192.0.2.1?transport=tcp".parse().unwrap();
+        assert_eq!((turn.port(), turn.transport()), (3478, IceTransport::Tcp));
+        let turns: IceUrl = "TURNS:[2001:db8::1]".parse().unwrap();
+        assert_eq!((turns.host(), turns.port(), turns.transport()), ("2001:db8::1", 5349, IceTransport::Tcp));
+        assert_eq!(turns.address(), "[2001:db8::1]:5349");
+        assert_eq!(turns.to_string(), "turns:[2001:db8::1]");
+        assert_eq!(turn.to_string(), "turn:192.0.2.1?transport=tcp");
+
+        let parse = |url: &str| url.parse::<IceUrl>().unwrap_err();
+        assert_eq!(parse("http://coturn"), IceUrlError::UnknownScheme("http".into()));
+        assert_eq!(parse("coturn"), IceUrlError::UnknownScheme("coturn".into()));
+        assert_eq!(parse("stun://coturn"), IceUrlError::InvalidHost("//coturn".into()));
+        assert_eq!(parse("stun:"), IceUrlError::InvalidHost("".into()));
+        assert_eq!(parse("stun:2001:db8::1"), IceUrlError::InvalidPort("db8::1".into()));
+        assert_eq!(parse("turn:coturn:99999"), IceUrlError::InvalidPort("99999".into()));
+        assert_eq!(parse("turn:coturn?transport=sctp"), IceUrlError::InvalidTransport("sctp".into()));
+        assert_eq!(parse("stun:coturn?transport=udp"), IceUrlError::UnexpectedQuery("transport=udp".into()));
+    }
+
+    #[test]
+    fn test_servers_accept_one_url_or_many() {
+        // As returned by api/appsettings.json
+        let servers: Vec<ICEServer> = serde_json::from_str(
+            r#"[{"urls":"stun:coturn:3478"},
+                {"urls":["turn:coturn:3478","turns:coturn"],"username":"user","credential":"password"}]"#,
+        )
+        .unwrap();
+        assert_eq!(servers[0].urls, vec!["stun:coturn:3478".parse().unwrap()]);
+        assert_eq!(servers[1].urls_with_scheme(IceScheme::Turns).count(), 1);
+        assert_eq!(servers[1].urls_with_transport(IceTransport::Udp).count(), 1);
+
+        let relays = crate::ice::filter_servers(&servers, |url| url.scheme().is_turn());
+        assert_eq!(relays.len(), 1);
+        assert_eq!(relays[0].username.as_deref(), Some("user"));
+
+        let malformed = serde_json::from_str::<ICEServer>(r#"{"urls":["stun:coturn","turn:coturn:0"]}"#);
+        let error = malformed.unwrap_err().to_string();
+        assert!(error.contains(r#"invalid ICE URL "turn:coturn:0": Invalid port "0""#), "{}", error);
+    }
+}
@@ -1,9 +1,24 @@
 // 🦀 SmaRTC Rust SDK
 // Wrapper performant et sécurisé pour WebRTC
-use reqwest::{Client as HttpClient, Response};
+use reqwest::Client as HttpClient;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
+use tokio::sync::Mutex;
+
+// Permet à `#[hub]` de désigner le SDK par `::smartc` depuis ce crate
+extern crate self as smartc;
+
+pub mod ice;
+pub mod rtc;
+pub mod signaling;
+
+use ice::{IceScheme, IceTransport, IceUrl, IceUrlError};
+use signaling::{HubError, RoomHandle, SignalingClient, SignalingClientBuilder};
+
+/// Chemin du hub de signalisation sur `signal_server_url`
+pub const SIGNAL_HUB_PATH: &str = "/signalhub";
 
 // ============================================================================
 // Configuration
@@ -17,6 +32,13 @@ pub struct Config {
     pub enable_logs: bool,
 }
 
+impl Config {
+    /// URL du hub SignalR, dérivée de `signal_server_url`
+    pub fn hub_url(&self) -> String {
+        format!("{}{}", self.signal_server_url.trim_end_matches('/'), SIGNAL_HUB_PATH)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -52,13 +74,58 @@ pub struct Session {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ICEServer {
-    pub urls: Vec<String>,
+    /// Une URL ou une liste d'URL, comme `RTCIceServer.urls`
+    #[serde(deserialize_with = "ice::url::one_or_many")]
+    pub urls: Vec<IceUrl>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub username: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub credential: Option<String>,
 }
 
+impl ICEServer {
+    /// URL de ce serveur ayant ce schéma (`stun`, `turns`...)
+    pub fn urls_with_scheme(&self, scheme: IceScheme) -> impl Iterator<Item = &IceUrl> {
+        self.urls.iter().filter(move |url| url.scheme() == scheme)
+    }
+
+    /// Expiration des identifiants temporaires (`timestamp:utilisateur`), `None` s'ils sont statiques
+    pub fn expires_at(&self) -> Option<std::time::SystemTime> {
+        ice::credentials::expiry(self.username.as_deref()?)
+    }
+
+    /// Vrai si les identifiants expirent dans moins de `margin` : il est temps de rappeler `get_ice_servers`
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        let deadline = std::time::SystemTime::now().checked_add(margin);
+        self.expires_at().is_some_and(|expires_at| deadline.is_none_or(|deadline| expires_at <= deadline))
+    }
+
+    /// URL de ce serveur joignables par ce transport
+    pub fn urls_with_transport(&self, transport: IceTransport) -> impl Iterator<Item = &IceUrl> {
+        self.urls.iter().filter(move |url| url.transport() == transport)
+    }
+}
+
+/// `ICEServer` tel que renvoyé par l'API, avant la validation des URL
+#[derive(Debug, Deserialize)]
+struct UnparsedICEServer {
+    #[serde(deserialize_with = "ice::url::unparsed")]
+    urls: Vec<String>,
+    username: Option<String>,
+    credential: Option<String>,
+}
+
+impl UnparsedICEServer {
+    fn parse(self) -> Result<ICEServer, SmaRTCError> {
+        let urls = self
+            .urls
+            .into_iter()
+            .map(|url| url.parse().map_err(|error| SmaRTCError::InvalidIceUrl { url, error }))
+            .collect::<Result<_, _>>()?;
+        Ok(ICEServer { urls, username: self.username, credential: self.credential })
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct LoginRequest {
     username: String,
@@ -98,6 +165,12 @@ pub enum SmaRTCError {
     #[error("Problème de connexion : {0}")]
     Network(String),
 
+    #[error("Signalisation : {0}")]
+    Signaling(#[from] HubError),
+
+    #[error("URL ICE invalide {url:?} : {error}")]
+    InvalidIceUrl { url: String, error: IceUrlError },
+
     #[error("Erreur : {0}")]
     Generic(String),
 }
@@ -148,6 +221,12 @@ pub fn current_username(&self) -> Option<&str> {
         self.current_username.as_deref()
     }
 
+    /// Retourne le jeton JWT obtenu par `login`, par exemple pour authentifier
+    /// la connexion au hub de signalisation
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
     /// Retourne l'ID de session actuel
     pub fn current_session_id(&self) -> Option<&str> {
         self.current_session_id.as_deref()
@@ -323,17 +402,21 @@ pub async fn get_available_calls(&self) -> Result<Vec<Session>, SmaRTCError> {
         Ok(sessions)
     }
 
-    /// Récupère la configuration STUN/TURN
+    /// Récupère la configuration STUN/TURN ; une URL invalide est une erreur,
+    /// seule une API injoignable se rabat sur le STUN de Google
     pub async fn get_ice_servers(&self) -> Result<Vec<ICEServer>, SmaRTCError> {
         match self
-            .request::<Vec<ICEServer>>(reqwest::Method::GET, "/api/webrtc/ice", None::<()>, true)
+            .request::<Vec<UnparsedICEServer>>(reqwest::Method::GET, "/api/webrtc/ice", None::<()>, true)
             .await
         {
-            Ok(servers) => Ok(servers),
-            Err(_) => {
+            Ok(servers) => servers.into_iter().map(UnparsedICEServer::parse).collect(),
+            Err(e) => {
+                if self.config.enable_logs {
+                    eprintln!("[SmaRTC] Serveurs ICE indisponibles, STUN Google utilisé : {}", e);
+                }
                 // Fallback vers Google STUN
                 Ok(vec![ICEServer {
-                    urls: vec!["stun:stun.l.google.com:19302".to_string()],
+                    urls: vec![IceUrl::new(IceScheme::Stun, "stun.l.google.com", Some(19302)).expect("hôte valide")],
                     username: None,
                     credential: None,
                 }])
@@ -364,6 +447,104 @@ pub async fn logout(&mut self) -> Result<(), SmaRTCError> {
     }
 }
 
+// ============================================================================
+// Façade SmaRTC : API REST + signalisation
+// ============================================================================
+
+/// Réunit l'API REST et la signalisation temps réel sous une même identité :
+/// le JWT de `login` authentifie aussi le hub, et chaque session créée ou
+/// rejointe est rejointe sur le hub avec le même nom d'utilisateur.
+pub struct SmaRTC {
+    api: Arc<Mutex<SmaRTCClient>>,
+    signaling: SignalingClient,
+}
+
+impl SmaRTC {
+    /// Crée la façade ; le hub est celui de `Config::signal_server_url`
+    pub fn new(config: Option<Config>) -> Self {
+        Self::with_signaling(config, |builder| builder)
+    }
+
+    /// Comme `new`, en complétant la configuration de la signalisation
+    /// (handlers, délais de reconnexion…)
+    pub fn with_signaling(
+        config: Option<Config>,
+        configure: impl FnOnce(SignalingClientBuilder) -> SignalingClientBuilder,
+    ) -> Self {
+        let config = config.unwrap_or_default();
+        let builder = SignalingClient::builder(&config.hub_url());
+        let api = Arc::new(Mutex::new(SmaRTCClient::new(Some(config))));
+        let builder = builder.access_token_factory(signaling::auth::smartc_token(api.clone()));
+
+        Self {
+            api,
+            signaling: configure(builder).build(),
+        }
+    }
+
+    /// Client REST partagé, par exemple pour `get_ice_servers`
+    pub fn api(&self) -> Arc<Mutex<SmaRTCClient>> {
+        self.api.clone()
+    }
+
+    /// Client de signalisation, pour ses événements et ses rooms
+    pub fn signaling(&self) -> &SignalingClient {
+        &self.signaling
+    }
+
+    /// Authentifie l'utilisateur auprès de l'API
+    pub async fn login(&self, username: &str, password: &str) -> Result<(), SmaRTCError> {
+        self.api.lock().await.login(username, password).await
+    }
+
+    /// Crée une session via l'API puis rejoint son groupe sur le hub
+    pub async fn create_session(&self, room_name: &str) -> Result<(Session, RoomHandle), SmaRTCError> {
+        let session = self.api.lock().await.start_call(room_name).await?;
+        let room = self.join_hub(&session.session_id).await?;
+        Ok((session, room))
+    }
+
+    /// Rejoint une session existante via l'API puis sur le hub
+    pub async fn join_session(&self, session_id: &str) -> Result<(Session, RoomHandle), SmaRTCError> {
+        let session = self.api.lock().await.join_call(session_id).await?;
+        let room = self.join_hub(&session.session_id).await?;
+        Ok((session, room))
+    }
+
+    /// Quitte le groupe du hub puis termine la session côté API
+    pub async fn end_session(&self) -> Result<(), SmaRTCError> {
+        let mut api = self.api.lock().await;
+        if let Some(session_id) = api.current_session_id() {
+            if let Some(room) = self.signaling.room(session_id).await {
+                room.leave().await?;
+            }
+        }
+        api.end_call().await
+    }
+
+    /// Ferme la signalisation puis déconnecte l'utilisateur
+    pub async fn logout(&self) -> Result<(), SmaRTCError> {
+        let closed = self.signaling.disconnect().await;
+        self.api.lock().await.logout().await?;
+        Ok(closed?)
+    }
+
+    async fn join_hub(&self, session_id: &str) -> Result<RoomHandle, SmaRTCError> {
+        let username = self
+            .api
+            .lock()
+            .await
+            .current_username()
+            .map(str::to_string)
+            .ok_or(SmaRTCError::Authentication)?;
+
+        if !self.signaling.is_connected().await {
+            self.signaling.connect().await?;
+        }
+        Ok(self.signaling.join_room(session_id, &username).await?)
+    }
+}
+
 // ============================================================================
 // Tests
 // ============================================================================
@@ -384,5 +565,22 @@ fn test_config_default() {
         let config = Config::default();
         assert_eq!(config.api_base_url, "http://localhost:8080");
         assert_eq!(config.timeout, Duration::from_secs(10));
+        assert_eq!(config.hub_url(), "http://localhost:5001/signalhub");
+    }
+
+    #[test]
+    fn test_invalid_ice_urls_are_reported() {
+        let servers: Vec<UnparsedICEServer> =
+            serde_json::from_str(r#"[{"urls": "stun:coturn"}, {"urls": ["turn:coturn", "turn:coturn:99999"]}]"#)
+                .unwrap();
+        let results: Vec<_> = servers.into_iter().map(UnparsedICEServer::parse).collect();
+        assert_eq!(results[0].as_ref().unwrap().urls[0].host(), "coturn");
+        match &results[1] {
+            Err(SmaRTCError::InvalidIceUrl { url, error }) => {
+                assert_eq!(url, "turn:coturn:99999");
+                assert_eq!(error, &IceUrlError::InvalidPort("99999".into()));
+            }
+            other => panic!("{:?}", other),
+        }
     }
 }
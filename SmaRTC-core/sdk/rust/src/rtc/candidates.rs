@@ -0,0 +1,181 @@
+// SmaRTC Rust SDK - Which ICE candidates are gathered, sent and accepted
+
+use std::net::IpAddr;
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+use webrtc::ice_transport::ice_protocol::RTCIceProtocol;
+
+/// Which local candidates ICE may use, like `iceTransportPolicy` in browsers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IceTransportPolicy {
+    #[default]
+    All,
+    /// Only TURN relays: the peers never learn each other's addresses
+    Relay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+/// The fields of an `a=candidate` line the filters look at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateInfo {
+    pub kind: RTCIceCandidateType,
+    pub protocol: RTCIceProtocol,
+    /// An IP address, or a `.local` name when hidden by mDNS
+    pub address: String,
+}
+
+impl CandidateInfo {
+    /// Parses `candidate:<foundation> <component> <protocol> <priority> <address> <port> typ <type> ...`,
+    /// with or without the `a=` and `candidate:` prefixes
+    pub fn parse(candidate: &str) -> Option<Self> {
+        let candidate = candidate.trim();
+        let candidate = candidate.strip_prefix("a=").unwrap_or(candidate);
+        let candidate = candidate.strip_prefix("candidate:").unwrap_or(candidate);
+        let fields: Vec<&str> = candidate.split_whitespace().collect();
+        let [_foundation, _component, protocol, _priority, address, _port, "typ", kind, ..] = fields[..] else {
+            return None;
+        };
+        let protocol = RTCIceProtocol::from(protocol.to_ascii_lowercase().as_str());
+        let kind = RTCIceCandidateType::from(kind);
+        if protocol == RTCIceProtocol::Unspecified || kind == RTCIceCandidateType::Unspecified {
+            return None;
+        }
+        Some(Self { kind, protocol, address: address.to_string() })
+    }
+
+    /// Whether the address is an mDNS name rather than an IP
+    pub fn is_mdns(&self) -> bool {
+        self.address.ends_with(".local")
+    }
+
+    /// `None` for mDNS names, whose family is only known once resolved
+    pub fn family(&self) -> Option<IpFamily> {
+        match self.address.trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>().ok()? {
+            IpAddr::V4(_) => Some(IpFamily::V4),
+            IpAddr::V6(_) => Some(IpFamily::V6),
+        }
+    }
+}
+
+/// Restricts the candidates exchanged with peers. The transport policy
+/// applies to local candidates; the type, protocol and family filters to
+/// both local and remote ones. Empty filters allow everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CandidatePolicy {
+    pub transport: IceTransportPolicy,
+    pub types: Vec<RTCIceCandidateType>,
+    pub protocols: Vec<RTCIceProtocol>,
+    pub families: Vec<IpFamily>,
+    /// Hides the addresses of local host candidates behind random `.local`
+    /// names. Remote `.local` candidates are resolved either way.
+    pub mdns: bool,
+}
+
+impl CandidatePolicy {
+    /// Privacy mode: only relay candidates are sent or accepted
+    pub fn relay_only() -> Self {
+        Self {
+            transport: IceTransportPolicy::Relay,
+            types: vec![RTCIceCandidateType::Relay],
+            ..Default::default()
+        }
+    }
+
+    /// Whether a candidate we gathered may be sent to the peer
+    pub fn allows_local(&self, candidate: &str) -> bool {
+        if self.transport == IceTransportPolicy::Relay
+            && CandidateInfo::parse(candidate).is_some_and(|info| info.kind != RTCIceCandidateType::Relay)
+        {
+            return false;
+        }
+        self.allows(candidate)
+    }
+
+    /// Whether a candidate received from the peer may be used
+    pub fn allows_remote(&self, candidate: &str) -> bool {
+        self.allows(candidate)
+    }
+
+    /// The empty end-of-candidates marker always passes; candidates that
+    /// cannot be parsed only pass when nothing is filtered
+    fn allows(&self, candidate: &str) -> bool {
+        if candidate.trim().is_empty() {
+            return true;
+        }
+        let Some(info) = CandidateInfo::parse(candidate) else {
+            return self.is_open();
+        };
+        let family = match info.family() {
+            Some(family) => self.families.is_empty() || self.families.contains(&family),
+            None => info.is_mdns(),
+        };
+        family
+            && (self.types.is_empty() || self.types.contains(&info.kind))
+            && (self.protocols.is_empty() || self.protocols.contains(&info.protocol))
+    }
+
+    fn is_open(&self) -> bool {
+        self.transport == IceTransportPolicy::All
+            && self.types.is_empty()
+            && self.protocols.is_empty()
+            && self.families.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "candidate:1 1 udp 2130706431 192.168.1.20 50000 typ host";
+    const HOST_V6: &str = "a=candidate:2 1 UDP 2130706431 2001:db8::1 50002 typ host";
+    const MDNS: &str = "candidate:3 1 udp 2130706431 0b1c3f1e-8d0f-4f55-9b3b-4a7f8a4d1e2c.local 50004 typ host";
+    const SRFLX: &str = "candidate:4 1 udp 1694498815 203.0.113.7 61000 typ srflx raddr 192.168.1.20 rport 50000";
+    const RELAY: &str = "candidate:5 1 udp 16777215 198.51.100.3 49152 typ relay raddr 203.0.113.7 rport 61000";
+    const TCP: &str = "candidate:6 1 tcp 1518280447 192.168.1.20 9 typ host tcptype active";
+
+    #[test]
+    fn test_candidates_are_parsed() {
+        let info = CandidateInfo::parse(SRFLX).unwrap();
+        assert_eq!(info.kind, RTCIceCandidateType::Srflx);
+        assert_eq!(info.protocol, RTCIceProtocol::Udp);
+        assert_eq!(info.family(), Some(IpFamily::V4));
+        assert_eq!(CandidateInfo::parse(HOST_V6).unwrap().family(), Some(IpFamily::V6));
+        let mdns = CandidateInfo::parse(MDNS).unwrap();
+        assert!(mdns.is_mdns() && mdns.family().is_none());
+        assert_eq!(CandidateInfo::parse(TCP).unwrap().protocol, RTCIceProtocol::Tcp);
+        assert_eq!(CandidateInfo::parse("candidate:1 1 udp 1 10.0.0.1 5000"), None);
+        assert_eq!(CandidateInfo::parse("candidate:1 1 sctp 1 10.0.0.1 5000 typ host"), None);
+    }
+
+    #[test]
+    fn test_policies_filter_candidates() {
+        let all = CandidatePolicy::default();
+        assert!([HOST, HOST_V6, MDNS, SRFLX, RELAY, TCP, "", "garbage"].iter().all(|c| all.allows_local(c)));
+
+        let relay = CandidatePolicy::relay_only();
+        assert!(relay.allows_local(RELAY) && relay.allows_remote(RELAY) && relay.allows_local(""));
+        assert!(![HOST, MDNS, SRFLX, TCP, "garbage"].iter().any(|c| relay.allows_local(c) || relay.allows_remote(c)));
+
+        // The transport policy alone leaves remote candidates alone
+        let gather = CandidatePolicy { transport: IceTransportPolicy::Relay, ..Default::default() };
+        assert!(!gather.allows_local(HOST) && gather.allows_remote(HOST));
+
+        let udp_v4 = CandidatePolicy {
+            protocols: vec![RTCIceProtocol::Udp],
+            families: vec![IpFamily::V4],
+            ..Default::default()
+        };
+        assert!(udp_v4.allows_remote(HOST) && udp_v4.allows_remote(SRFLX) && udp_v4.allows_remote(MDNS));
+        assert!(!udp_v4.allows_remote(HOST_V6) && !udp_v4.allows_remote(TCP));
+
+        let no_host = CandidatePolicy {
+            types: vec![RTCIceCandidateType::Srflx, RTCIceCandidateType::Relay],
+            ..Default::default()
+        };
+        assert!(!no_host.allows_local(HOST) && !no_host.allows_local(MDNS) && no_host.allows_local(SRFLX));
+    }
+}
@@ -0,0 +1,25 @@
+// SmaRTC Rust SDK - Errors reported by the peer connections
+
+use super::signal::SignalError;
+use crate::signaling::HubError;
+use thiserror::Error;
+
+/// Why a peer connection operation failed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RtcError {
+    /// The WebRTC stack rejected the operation, e.g. an invalid SDP
+    #[error("WebRTC error: {0}")]
+    WebRtc(String),
+    /// The signal could not be sent through the hub
+    #[error("Signaling failed: {0}")]
+    Signaling(#[from] HubError),
+    /// A received signal was rejected
+    #[error(transparent)]
+    InvalidSignal(#[from] SignalError),
+}
+
+impl From<webrtc::Error> for RtcError {
+    fn from(e: webrtc::Error) -> Self {
+        RtcError::WebRtc(e.to_string())
+    }
+}
@@ -0,0 +1,113 @@
+// SmaRTC Rust SDK - Peer connection health and recovery
+
+use std::time::Duration;
+
+/// How connections that lost connectivity are recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// How long ICE may stay `disconnected` before restarting it; it often
+    /// recovers by itself, e.g. after a Wi-Fi roam
+    pub disconnected_grace: Duration,
+    /// How long a restart may take before the next attempt
+    pub restart_timeout: Duration,
+    /// ICE restarts before giving up or falling back to TURN
+    pub max_restarts: u32,
+    /// Start over with a connection only using TURN relays once the restarts
+    /// are exhausted
+    pub relay_fallback: bool,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            disconnected_grace: Duration::from_secs(3),
+            restart_timeout: Duration::from_secs(10),
+            max_restarts: 3,
+            relay_fallback: true,
+        }
+    }
+}
+
+/// Health of the connection with one peer, as reported to the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerHealth {
+    /// ICE and DTLS are up
+    Connected,
+    /// Connectivity was lost; it may come back by itself
+    Disconnected,
+    /// An ICE restart was offered through the hub
+    Restarting { attempt: u32 },
+    /// Restarts did not help; a new connection only using TURN relays is
+    /// being negotiated
+    RelayFallback,
+    /// Every attempt failed and the connection was closed
+    Failed,
+}
+
+/// Next step to recover a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    RestartIce { attempt: u32 },
+    FallBackToRelay,
+    GiveUp,
+}
+
+/// Counts the recovery attempts of one connection
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    policy: RestartPolicy,
+    restarts: u32,
+    /// Whether the connection already only uses relays
+    relay_only: bool,
+}
+
+impl Recovery {
+    pub fn new(policy: RestartPolicy, relay_only: bool) -> Self {
+        Self { policy, restarts: 0, relay_only }
+    }
+
+    pub fn policy(&self) -> &RestartPolicy {
+        &self.policy
+    }
+
+    pub fn next_action(&mut self) -> RecoveryAction {
+        if self.restarts < self.policy.max_restarts {
+            self.restarts += 1;
+            return RecoveryAction::RestartIce { attempt: self.restarts };
+        }
+        if self.policy.relay_fallback && !self.relay_only {
+            return RecoveryAction::FallBackToRelay;
+        }
+        RecoveryAction::GiveUp
+    }
+
+    /// The connection is up again: the next failure starts over
+    pub fn recovered(&mut self) {
+        self.restarts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restarts_are_bounded_then_fall_back_to_relays() {
+        let policy = RestartPolicy { max_restarts: 2, ..Default::default() };
+        let mut recovery = Recovery::new(policy, false);
+        assert_eq!(recovery.next_action(), RecoveryAction::RestartIce { attempt: 1 });
+        assert_eq!(recovery.next_action(), RecoveryAction::RestartIce { attempt: 2 });
+        assert_eq!(recovery.next_action(), RecoveryAction::FallBackToRelay);
+
+        // The relayed connection gets its own restarts, then gives up
+        let mut relayed = Recovery::new(policy, true);
+        assert_eq!(relayed.next_action(), RecoveryAction::RestartIce { attempt: 1 });
+        relayed.recovered();
+        assert_eq!(relayed.next_action(), RecoveryAction::RestartIce { attempt: 1 });
+        assert_eq!(relayed.next_action(), RecoveryAction::RestartIce { attempt: 2 });
+        assert_eq!(relayed.next_action(), RecoveryAction::GiveUp);
+
+        let no_fallback = RestartPolicy { max_restarts: 0, relay_fallback: false, ..Default::default() };
+        assert_eq!(Recovery::new(no_fallback, false).next_action(), RecoveryAction::GiveUp);
+    }
+}
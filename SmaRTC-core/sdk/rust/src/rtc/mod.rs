@@ -0,0 +1,21 @@
+// SmaRTC Rust SDK - Native WebRTC peer connections negotiated over the SmaRTC hubs
+
+pub mod candidates;
+pub mod error;
+pub mod health;
+pub mod negotiation;
+pub mod peer;
+pub mod sdp;
+pub mod signal;
+
+pub use candidates::{CandidatePolicy, IceTransportPolicy, IpFamily};
+pub use error::RtcError;
+pub use health::{PeerHealth, RestartPolicy};
+pub use peer::{
+    ice_servers, PeerConfig, PeerConnectionEvent, PeerManager, SignalOptions, SignalSender, DATA_CHANNEL_LABEL,
+    MAX_PENDING_CANDIDATES, PENDING_CANDIDATES_TIMEOUT,
+};
+pub use sdp::{MediaSection, QualityLevel, Sdp, SdpError, VideoCodec};
+pub use signal::{
+    SdpEncoding, SessionDescription, SignalError, SignalFeature, SignalMessage, SignalPayload, SIGNAL_VERSION,
+};
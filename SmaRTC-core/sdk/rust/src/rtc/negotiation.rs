@@ -0,0 +1,107 @@
+// SmaRTC Rust SDK - Perfect negotiation: resolving offers sent by both peers at once
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use webrtc::peer_connection::signaling_state::RTCSignalingState;
+
+/// Whether we yield when both peers offer at the same time. Roles only
+/// depend on the ids, so both sides agree without talking: the peer with the
+/// highest id is polite, the other one is also the one offering on join.
+pub fn is_polite(local_id: &str, remote_id: &str) -> bool {
+    local_id > remote_id
+}
+
+/// DTLS fingerprint of a description. A new one in an offer means that the
+/// peer started over with a new connection.
+pub fn fingerprint(sdp: &str) -> Option<&str> {
+    sdp.lines().find_map(|line| line.trim_end().strip_prefix("a=fingerprint:"))
+}
+
+/// What to do with a remote description
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Apply,
+    /// Our pending offer is dropped before applying the remote one
+    RollbackThenApply,
+    /// The remote offer collides with ours and we are impolite
+    Ignore,
+}
+
+/// Perfect negotiation state of one peer connection, as described in the
+/// WebRTC specification
+#[derive(Debug)]
+pub struct Negotiation {
+    polite: bool,
+    making_offer: AtomicBool,
+    ignore_offer: AtomicBool,
+}
+
+impl Negotiation {
+    pub fn new(local_id: &str, remote_id: &str) -> Self {
+        Self {
+            polite: is_polite(local_id, remote_id),
+            making_offer: AtomicBool::new(false),
+            ignore_offer: AtomicBool::new(false),
+        }
+    }
+
+    pub fn polite(&self) -> bool {
+        self.polite
+    }
+
+    /// Brackets the creation of a local offer until it is sent
+    pub fn set_making_offer(&self, making_offer: bool) {
+        self.making_offer.store(making_offer, Ordering::SeqCst);
+    }
+
+    /// Decides how to handle a remote description received in `state`
+    pub fn resolve(&self, is_offer: bool, state: RTCSignalingState) -> Resolution {
+        let collision = is_offer && (self.making_offer.load(Ordering::SeqCst) || state != RTCSignalingState::Stable);
+        let ignore = collision && !self.polite;
+        self.ignore_offer.store(ignore, Ordering::SeqCst);
+        match (collision, ignore) {
+            (_, true) => Resolution::Ignore,
+            (true, false) if state == RTCSignalingState::HaveLocalOffer => Resolution::RollbackThenApply,
+            _ => Resolution::Apply,
+        }
+    }
+
+    /// Candidates of an ignored offer fail to apply; that is expected
+    pub fn ignoring_offer(&self) -> bool {
+        self.ignore_offer.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colliding_offers_are_resolved_by_role() {
+        let impolite = Negotiation::new("conn-a", "conn-b");
+        let polite = Negotiation::new("conn-b", "conn-a");
+        assert!(!impolite.polite() && polite.polite());
+
+        // Both sent an offer and receive the other one
+        impolite.set_making_offer(true);
+        polite.set_making_offer(true);
+        assert_eq!(impolite.resolve(true, RTCSignalingState::HaveLocalOffer), Resolution::Ignore);
+        assert!(impolite.ignoring_offer());
+        assert_eq!(polite.resolve(true, RTCSignalingState::HaveLocalOffer), Resolution::RollbackThenApply);
+        assert!(!polite.ignoring_offer());
+
+        // The polite answer to the impolite offer goes through
+        impolite.set_making_offer(false);
+        assert_eq!(impolite.resolve(false, RTCSignalingState::HaveLocalOffer), Resolution::Apply);
+        assert!(!impolite.ignoring_offer());
+
+        // Without a collision, offers are applied by both sides
+        assert_eq!(impolite.resolve(true, RTCSignalingState::Stable), Resolution::Apply);
+    }
+
+    #[test]
+    fn test_fingerprint_is_read_from_the_sdp() {
+        let sdp = "v=0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\na=fingerprint:sha-256 AB:CD\r\n";
+        assert_eq!(fingerprint(sdp), Some("sha-256 AB:CD"));
+        assert_eq!(fingerprint("v=0\r\n"), None);
+    }
+}
@@ -0,0 +1,1258 @@
+// SmaRTC Rust SDK - Peer connections exchanging SDP and ICE candidates over the hub
+
+use super::candidates::{CandidatePolicy, IceTransportPolicy};
+use super::health::{PeerHealth, Recovery, RecoveryAction, RestartPolicy};
+use super::negotiation::{self, Negotiation, Resolution};
+use super::signal::{SignalFeature, SignalMessage, SignalPayload};
+use super::RtcError;
+use crate::signaling::{HubError, PeerEvent, SessionClient, SignalTarget};
+use crate::{ICEServer, SmaRTCClient, SmaRTCError};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::{APIBuilder, API};
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::dtls_transport::dtls_transport_state::RTCDtlsTransportState;
+use webrtc::ice::mdns::MulticastDnsMode;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::offer_answer_options::RTCOfferOptions;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::policy::ice_transport_policy::RTCIceTransportPolicy;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::signaling_state::RTCSignalingState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+use webrtc::stats::StatsReport;
+use webrtc::track::track_local::TrackLocal;
+use webrtc::track::track_remote::TrackRemote;
+
+/// Label of the data channel the offerer opens, as in the JavaScript SDK
+pub const DATA_CHANNEL_LABEL: &str = "smartc-signals";
+
+/// Candidates kept for a peer whose description has not arrived; the oldest
+/// ones are dropped beyond
+pub const MAX_PENDING_CANDIDATES: usize = 64;
+
+/// How long candidates wait for the description of their peer
+pub const PENDING_CANDIDATES_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Converts the servers returned by `get_ice_servers`
+pub fn ice_servers(servers: &[ICEServer]) -> Vec<RTCIceServer> {
+    servers
+        .iter()
+        .map(|server| RTCIceServer {
+            urls: server.urls.iter().map(ToString::to_string).collect(),
+            username: server.username.clone().unwrap_or_default(),
+            credential: server.credential.clone().unwrap_or_default(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Settings shared by every peer connection of a [`PeerManager`]
+#[derive(Debug, Clone, Default)]
+pub struct PeerConfig {
+    /// Without servers only host candidates are gathered, which is enough on
+    /// a single machine or LAN
+    pub ice_servers: Vec<RTCIceServer>,
+    pub restart: RestartPolicy,
+    /// Candidates sent to and accepted from peers, e.g.
+    /// [`CandidatePolicy::relay_only`] to keep addresses private
+    pub candidates: CandidatePolicy,
+    pub signals: SignalOptions,
+}
+
+impl PeerConfig {
+    pub fn new(servers: &[ICEServer]) -> Self {
+        Self {
+            ice_servers: ice_servers(servers),
+            ..Default::default()
+        }
+    }
+
+    /// Uses the STUN/TURN servers configured on the SmaRTC API
+    pub async fn from_api(api: &SmaRTCClient) -> Result<Self, SmaRTCError> {
+        Ok(Self::new(&api.get_ice_servers().await?))
+    }
+
+    /// Whether connections only use TURN relays from the start
+    fn relay_only(&self) -> bool {
+        self.candidates.transport == IceTransportPolicy::Relay
+    }
+
+    fn rtc_configuration(&self, relay_only: bool) -> RTCConfiguration {
+        let ice_transport_policy = match relay_only || self.relay_only() {
+            true => RTCIceTransportPolicy::Relay,
+            false => RTCIceTransportPolicy::Unspecified,
+        };
+        RTCConfiguration {
+            ice_servers: self.ice_servers.clone(),
+            ice_transport_policy,
+            ..Default::default()
+        }
+    }
+}
+
+/// How signals are sent to peers. Both extensions are only used towards
+/// peers that advertise them, so browsers get plain signals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalOptions {
+    /// Candidates gathered within this window travel in one signal; zero
+    /// sends each one at once
+    pub candidate_window: Duration,
+    /// Deflates offers and answers
+    pub deflate: bool,
+}
+
+impl Default for SignalOptions {
+    fn default() -> Self {
+        Self { candidate_window: Duration::from_millis(50), deflate: true }
+    }
+}
+
+/// Delivers serialized signals to a single peer, usually through the hub's
+/// `SendSignal`
+pub trait SignalSender: Send + Sync {
+    fn send_signal<'a>(&'a self, peer_id: &'a str, signal: String) -> BoxFuture<'a, Result<(), HubError>>;
+}
+
+impl SignalSender for SessionClient {
+    fn send_signal<'a>(&'a self, peer_id: &'a str, signal: String) -> BoxFuture<'a, Result<(), HubError>> {
+        Box::pin(async move { self.signal(SignalTarget::Peer(peer_id), &signal).await })
+    }
+}
+
+/// What happens on the peer connections of a [`PeerManager`]
+#[derive(Clone)]
+pub enum PeerConnectionEvent {
+    StateChanged { peer_id: String, state: RTCPeerConnectionState },
+    /// The data channel opened locally by the offerer, or announced by the
+    /// remote peer
+    DataChannel { peer_id: String, channel: Arc<RTCDataChannel> },
+    Track { peer_id: String, track: Arc<TrackRemote> },
+    IceStateChanged { peer_id: String, state: RTCIceConnectionState },
+    DtlsStateChanged { peer_id: String, state: RTCDtlsTransportState },
+    /// Connectivity was lost or recovered, see [`RestartPolicy`]
+    Health { peer_id: String, health: PeerHealth },
+    /// A signal or hub event could not be handled by [`PeerManager::follow`]
+    Error { peer_id: String, error: RtcError },
+}
+
+/// A peer connection and its negotiation state
+struct Link {
+    connection: Arc<RTCPeerConnection>,
+    negotiation: Negotiation,
+    /// Data channels opened locally, to open again if the connection restarts
+    channels: std::sync::Mutex<Vec<(String, Option<RTCDataChannelInit>)>>,
+    /// Whether the connection only uses TURN relays
+    relay_only: bool,
+    recovery: std::sync::Mutex<Recovery>,
+    /// Whether ICE is connected, so that recoveries are reported once
+    up: AtomicBool,
+}
+
+impl Link {
+    fn is_up(&self) -> bool {
+        matches!(
+            self.connection.ice_connection_state(),
+            RTCIceConnectionState::Connected | RTCIceConnectionState::Completed
+        )
+    }
+}
+
+/// What a restarted connection takes over from the previous one
+struct Carried {
+    channels: Vec<(String, Option<RTCDataChannelInit>)>,
+    tracks: Vec<Arc<dyn TrackLocal + Send + Sync>>,
+}
+
+impl Carried {
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.tracks.is_empty()
+    }
+}
+
+/// One peer connection per remote peer, negotiated through the hub the same
+/// way as the JavaScript mesh SDK. Offers are made whenever the connection
+/// needs one, e.g. when a track is added mid-call, and collisions are
+/// resolved with perfect negotiation.
+///
+/// ICE and DTLS are watched on every connection. When connectivity is lost,
+/// the impolite peer restarts ICE, then falls back to TURN relays, as set by
+/// [`PeerConfig::restart`]; the polite one reports its health and answers.
+pub struct PeerManager {
+    local_id: String,
+    api: API,
+    config: PeerConfig,
+    signals: Arc<dyn SignalSender>,
+    peers: Mutex<HashMap<String, Arc<Link>>>,
+    /// Candidates received before the remote description they belong to,
+    /// with the arrival of the first one
+    pending_candidates: Mutex<HashMap<String, (Instant, Vec<RTCIceCandidateInit>)>>,
+    /// Local candidates waiting for the end of their coalescing window
+    outgoing_candidates: Mutex<HashMap<String, Vec<RTCIceCandidateInit>>>,
+    /// Signal extensions each peer listed in its last signal
+    features: Mutex<HashMap<String, Vec<SignalFeature>>>,
+    events: mpsc::UnboundedSender<PeerConnectionEvent>,
+    this: Weak<PeerManager>,
+}
+
+impl PeerManager {
+    /// `local_id` is the id other members see, e.g. [`SessionClient::local_peer_id`]
+    pub fn new(
+        local_id: &str,
+        config: PeerConfig,
+        signals: Arc<dyn SignalSender>,
+    ) -> Result<(Arc<Self>, mpsc::UnboundedReceiver<PeerConnectionEvent>), RtcError> {
+        let mut media = MediaEngine::default();
+        media.register_default_codecs()?;
+        let registry = register_default_interceptors(Registry::new(), &mut media)?;
+        let mut settings = SettingEngine::default();
+        settings.set_ice_multicast_dns_mode(match config.candidates.mdns {
+            true => MulticastDnsMode::QueryAndGather,
+            false => MulticastDnsMode::QueryOnly,
+        });
+        let api = APIBuilder::new()
+            .with_media_engine(media)
+            .with_interceptor_registry(registry)
+            .with_setting_engine(settings)
+            .build();
+
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let manager = Arc::new_cyclic(|this| Self {
+            local_id: local_id.to_string(),
+            api,
+            config,
+            signals,
+            peers: Mutex::new(HashMap::new()),
+            pending_candidates: Mutex::new(HashMap::new()),
+            outgoing_candidates: Mutex::new(HashMap::new()),
+            features: Mutex::new(HashMap::new()),
+            events: events_tx,
+            this: this.clone(),
+        });
+        Ok((manager, events))
+    }
+
+    pub fn local_id(&self) -> &str {
+        &self.local_id
+    }
+
+    /// The connection with `peer_id`, if any
+    pub async fn peer(&self, peer_id: &str) -> Option<Arc<RTCPeerConnection>> {
+        self.link(peer_id).await.map(|link| link.connection.clone())
+    }
+
+    /// Whether we yield to `peer_id` when both of us offer at once, see
+    /// [`super::negotiation::is_polite`]
+    pub fn is_polite(&self, peer_id: &str) -> bool {
+        super::negotiation::is_polite(&self.local_id, peer_id)
+    }
+
+    /// Statistics of the connection with `peer_id`, like `getStats` in browsers
+    pub async fn stats(&self, peer_id: &str) -> Option<StatsReport> {
+        Some(self.peer(peer_id).await?.get_stats().await)
+    }
+
+    /// Ids of the peers with an open connection
+    pub async fn peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.peers.lock().await.keys().cloned().collect();
+        peers.sort();
+        peers
+    }
+
+    /// Handles hub events: offers to newcomers, answers their signals and
+    /// closes the connection of members who left. Errors are reported as
+    /// [`PeerConnectionEvent::Error`].
+    pub fn follow(&self, mut events: mpsc::UnboundedReceiver<PeerEvent>) -> JoinHandle<()> {
+        let this = self.this.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let Some(manager) = this.upgrade() else {
+                    break;
+                };
+                if let Err(error) = manager.handle_event(&event).await {
+                    let peer_id = match &event {
+                        PeerEvent::Joined(peer) | PeerEvent::Left(peer) => peer.id.clone(),
+                        PeerEvent::Signal { from, .. } => from.id.clone(),
+                    };
+                    manager.emit(PeerConnectionEvent::Error { peer_id, error });
+                }
+            }
+        })
+    }
+
+    /// The impolite peer, with the lowest id, connects to the other one
+    pub async fn handle_event(&self, event: &PeerEvent) -> Result<(), RtcError> {
+        match event {
+            PeerEvent::Joined(peer) if peer.id != self.local_id && !self.is_polite(&peer.id) => {
+                self.connect(&peer.id).await
+            }
+            PeerEvent::Joined(_) => Ok(()),
+            PeerEvent::Left(peer) => self.close_peer(&peer.id).await,
+            PeerEvent::Signal { from, signal, .. } => self.handle_signal(&from.id, signal).await,
+        }
+    }
+
+    /// Handles a signal received from `peer_id`; signals addressed to other
+    /// peers, as broadcast by session-wide hubs, are ignored
+    pub async fn handle_signal(&self, peer_id: &str, signal: &str) -> Result<(), RtcError> {
+        let signal = SignalMessage::parse(signal)?;
+        if signal.to != self.local_id || peer_id == self.local_id {
+            return Ok(());
+        }
+        self.features.lock().await.insert(peer_id.to_string(), signal.supports);
+        match signal.payload {
+            SignalPayload::Offer(offer) => self.handle_description(peer_id, offer.into()).await,
+            SignalPayload::Answer(answer) => self.handle_description(peer_id, answer.into()).await,
+            SignalPayload::IceCandidate(candidate) => self.handle_ice_candidate(peer_id, candidate).await,
+            SignalPayload::IceCandidates(candidates) => {
+                for candidate in candidates {
+                    self.handle_ice_candidate(peer_id, candidate).await?;
+                }
+                Ok(())
+            }
+            // Relayed media is handled by the relay peers themselves
+            SignalPayload::Relay(_) => Ok(()),
+        }
+    }
+
+    /// Opens the data channel with `peer_id`, which makes the connection
+    /// offer. Calling it on both sides at once is fine.
+    pub async fn connect(&self, peer_id: &str) -> Result<(), RtcError> {
+        let init = RTCDataChannelInit {
+            ordered: Some(true),
+            max_retransmits: Some(3),
+            ..Default::default()
+        };
+        self.create_data_channel(peer_id, DATA_CHANNEL_LABEL, Some(init)).await?;
+        Ok(())
+    }
+
+    /// Opens another data channel with `peer_id`, renegotiating if needed
+    pub async fn create_data_channel(
+        &self,
+        peer_id: &str,
+        label: &str,
+        init: Option<RTCDataChannelInit>,
+    ) -> Result<Arc<RTCDataChannel>, RtcError> {
+        let link = self.link_or_create(peer_id).await?;
+        self.open_channel(peer_id, &link, label, init).await
+    }
+
+    /// Sends a local track to `peer_id`, renegotiating the connection
+    pub async fn add_track(
+        &self,
+        peer_id: &str,
+        track: Arc<dyn TrackLocal + Send + Sync>,
+    ) -> Result<Arc<RTCRtpSender>, RtcError> {
+        let link = self.link_or_create(peer_id).await?;
+        Ok(link.connection.add_track(track).await?)
+    }
+
+    /// Offers an ICE restart to `peer_id`, e.g. after a network change
+    pub async fn restart_ice(&self, peer_id: &str) -> Result<(), RtcError> {
+        self.negotiate(peer_id, true).await
+    }
+
+    /// Starts over with a new connection to `peer_id`, carrying the data
+    /// channels and tracks over; the peer does the same when it receives the
+    /// offer
+    pub async fn reconnect(&self, peer_id: &str) -> Result<(), RtcError> {
+        let Some(link) = self.link(peer_id).await else {
+            return Ok(());
+        };
+        self.start_over(peer_id, &link, link.relay_only).await?;
+        Ok(())
+    }
+
+    /// Applies a remote offer or answer. A colliding offer is ignored by the
+    /// impolite peer, while the polite one drops its own offer and answers.
+    /// Stale answers are ignored.
+    ///
+    /// webrtc-rs cannot roll a local offer back, so the polite peer starts
+    /// over with a new connection: its data channels are opened again, with
+    /// new [`PeerConnectionEvent::DataChannel`] events, and its tracks are
+    /// sent again, which makes the `RTCRtpSender`s returned so far stale.
+    /// A description with a new DTLS fingerprint means that the peer started
+    /// over likewise.
+    pub async fn handle_description(&self, peer_id: &str, description: RTCSessionDescription) -> Result<(), RtcError> {
+        let is_offer = description.sdp_type == RTCSdpType::Offer;
+        let mut link = match is_offer {
+            true => self.link_or_create(peer_id).await?,
+            false => match self.link(peer_id).await {
+                Some(link) => link,
+                None => return Ok(()),
+            },
+        };
+
+        let mut carried = None;
+        if let Some(remote) = link.connection.remote_description().await {
+            if negotiation::fingerprint(&remote.sdp) != negotiation::fingerprint(&description.sdp) {
+                if !is_offer {
+                    // Our offer cannot be answered by a new connection
+                    self.start_over(peer_id, &link, link.relay_only).await?;
+                    return Ok(());
+                }
+                let (replaced, previous) = self.replace_link(peer_id, &link, link.relay_only).await?;
+                link = replaced;
+                carried = Some(previous);
+            }
+        }
+
+        let state = link.connection.signaling_state();
+        match link.negotiation.resolve(is_offer, state) {
+            Resolution::Ignore => return Ok(()),
+            Resolution::RollbackThenApply => {
+                let (replaced, previous) = self.replace_link(peer_id, &link, link.relay_only).await?;
+                link = replaced;
+                carried = Some(previous);
+            }
+            Resolution::Apply if !is_offer && state != RTCSignalingState::HaveLocalOffer => return Ok(()),
+            Resolution::Apply => {}
+        }
+
+        let connection = &link.connection;
+        connection.set_remote_description(description).await?;
+        self.add_pending_candidates(peer_id, &link).await?;
+        if is_offer {
+            let answer = connection.create_answer(None).await?;
+            connection.set_local_description(answer.clone()).await?;
+            self.send(peer_id, SignalPayload::Answer(answer.into())).await?;
+        }
+
+        // Back to stable: whatever the remote offer lacks is offered next
+        if let Some(carried) = carried {
+            self.carry_over(peer_id, &link, carried).await?;
+        }
+        Ok(())
+    }
+
+    /// Candidates may arrive before the description they belong to; they are
+    /// kept until it is applied, up to [`MAX_PENDING_CANDIDATES`] per peer and
+    /// for [`PENDING_CANDIDATES_TIMEOUT`]. Those refused by
+    /// [`PeerConfig::candidates`] are dropped.
+    pub async fn handle_ice_candidate(&self, peer_id: &str, candidate: RTCIceCandidateInit) -> Result<(), RtcError> {
+        if !self.config.candidates.allows_remote(&candidate.candidate) {
+            return Ok(());
+        }
+        if let Some(link) = self.link(peer_id).await {
+            if link.connection.remote_description().await.is_some() {
+                return Self::add_candidate(&link, candidate).await;
+            }
+        }
+        let mut pending = self.pending_candidates.lock().await;
+        pending.retain(|_, (since, _)| since.elapsed() < PENDING_CANDIDATES_TIMEOUT);
+        let (_, candidates) = pending.entry(peer_id.to_string()).or_insert_with(|| (Instant::now(), Vec::new()));
+        if candidates.len() == MAX_PENDING_CANDIDATES {
+            candidates.remove(0);
+        }
+        candidates.push(candidate);
+        Ok(())
+    }
+
+    /// Closes the connection with `peer_id`, if any
+    pub async fn close_peer(&self, peer_id: &str) -> Result<(), RtcError> {
+        self.pending_candidates.lock().await.remove(peer_id);
+        self.outgoing_candidates.lock().await.remove(peer_id);
+        self.features.lock().await.remove(peer_id);
+        let link = self.peers.lock().await.remove(peer_id);
+        match link {
+            Some(link) => Ok(link.connection.close().await?),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes every peer connection
+    pub async fn close(&self) -> Result<(), RtcError> {
+        self.pending_candidates.lock().await.clear();
+        self.outgoing_candidates.lock().await.clear();
+        self.features.lock().await.clear();
+        let peers: Vec<_> = self.peers.lock().await.drain().map(|(_, link)| link).collect();
+        let mut result = Ok(());
+        for link in peers {
+            result = result.and(link.connection.close().await.map_err(RtcError::from));
+        }
+        result
+    }
+
+    async fn link(&self, peer_id: &str) -> Option<Arc<Link>> {
+        self.peers.lock().await.get(peer_id).cloned()
+    }
+
+    /// The connection with `peer_id`, created on first use
+    async fn link_or_create(&self, peer_id: &str) -> Result<Arc<Link>, RtcError> {
+        let mut peers = self.peers.lock().await;
+        if let Some(link) = peers.get(peer_id) {
+            return Ok(link.clone());
+        }
+        let link = self.new_link(peer_id, false).await?;
+        peers.insert(peer_id.to_string(), link.clone());
+        Ok(link)
+    }
+
+    /// The link of `peer_id`, provided its connection is still `connection`
+    async fn current_link(&self, peer_id: &str, connection: &Weak<RTCPeerConnection>) -> Option<Arc<Link>> {
+        let peers = self.peers.lock().await;
+        peers.get(peer_id).filter(|link| Arc::as_ptr(&link.connection) == connection.as_ptr()).cloned()
+    }
+
+    async fn new_link(&self, peer_id: &str, relay_only: bool) -> Result<Arc<Link>, RtcError> {
+        let relay_only = relay_only || self.config.relay_only();
+        let connection = Arc::new(self.api.new_peer_connection(self.config.rtc_configuration(relay_only)).await?);
+        self.attach_handlers(peer_id, &connection);
+        Ok(Arc::new(Link {
+            connection,
+            negotiation: Negotiation::new(&self.local_id, peer_id),
+            channels: std::sync::Mutex::new(Vec::new()),
+            relay_only,
+            recovery: std::sync::Mutex::new(Recovery::new(self.config.restart, relay_only)),
+            up: AtomicBool::new(false),
+        }))
+    }
+
+    /// Replaces the connection with `peer_id` by a new one, quietly closing
+    /// the previous one
+    async fn replace_link(
+        &self,
+        peer_id: &str,
+        previous: &Link,
+        relay_only: bool,
+    ) -> Result<(Arc<Link>, Carried), RtcError> {
+        let link = self.new_link(peer_id, relay_only).await?;
+        self.peers.lock().await.insert(peer_id.to_string(), link.clone());
+
+        let mut tracks = Vec::new();
+        for sender in previous.connection.get_senders().await {
+            tracks.extend(sender.track().await);
+        }
+        let channels = std::mem::take(&mut *previous.channels.lock().unwrap());
+        Self::detach_handlers(&previous.connection);
+        previous.connection.close().await?;
+        Ok((link, Carried { channels, tracks }))
+    }
+
+    /// Opens the carried data channels and sends the carried tracks again
+    async fn carry_over(&self, peer_id: &str, link: &Link, carried: Carried) -> Result<(), RtcError> {
+        for (label, init) in carried.channels {
+            self.open_channel(peer_id, link, &label, init).await?;
+        }
+        for track in carried.tracks {
+            link.connection.add_track(track).await?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the connection with `peer_id` and offers the new one
+    async fn start_over(&self, peer_id: &str, previous: &Link, relay_only: bool) -> Result<Arc<Link>, RtcError> {
+        let (link, carried) = self.replace_link(peer_id, previous, relay_only).await?;
+        let offers_itself = !carried.is_empty();
+        self.carry_over(peer_id, &link, carried).await?;
+        if !offers_itself {
+            self.negotiate(peer_id, false).await?;
+        }
+        Ok(link)
+    }
+
+    async fn open_channel(
+        &self,
+        peer_id: &str,
+        link: &Link,
+        label: &str,
+        init: Option<RTCDataChannelInit>,
+    ) -> Result<Arc<RTCDataChannel>, RtcError> {
+        let channel = link.connection.create_data_channel(label, init.clone()).await?;
+        link.channels.lock().unwrap().push((label.to_string(), init));
+        self.emit(PeerConnectionEvent::DataChannel { peer_id: peer_id.to_string(), channel: channel.clone() });
+        Ok(channel)
+    }
+
+    /// Sends an offer for the current state of the connection
+    async fn negotiate(&self, peer_id: &str, ice_restart: bool) -> Result<(), RtcError> {
+        let Some(link) = self.link(peer_id).await else {
+            return Ok(());
+        };
+        let connection = &link.connection;
+        link.negotiation.set_making_offer(true);
+        let offered = async {
+            let options = ice_restart.then(|| RTCOfferOptions { ice_restart, ..Default::default() });
+            let offer = connection.create_offer(options).await?;
+            // A remote offer was applied meanwhile, our answer covers the changes
+            if connection.signaling_state() != RTCSignalingState::Stable {
+                return Ok(());
+            }
+            connection.set_local_description(offer.clone()).await?;
+            self.send(peer_id, SignalPayload::Offer(offer.into())).await
+        }
+        .await;
+        link.negotiation.set_making_offer(false);
+        offered
+    }
+
+    fn attach_handlers(&self, peer_id: &str, connection: &Arc<RTCPeerConnection>) {
+        let (this, id) = (self.this.clone(), peer_id.to_string());
+        connection.on_ice_candidate(Box::new(move |candidate| {
+            let (this, id) = (this.clone(), id.clone());
+            Box::pin(async move {
+                let Some(manager) = this.upgrade() else {
+                    return;
+                };
+                // The end of the gathering is signaled with an empty candidate
+                let sent = match candidate.map(|candidate| candidate.to_json()) {
+                    None => manager.queue_candidate(&id, RTCIceCandidateInit::default()).await,
+                    Some(Ok(candidate)) if !manager.config.candidates.allows_local(&candidate.candidate) => Ok(()),
+                    Some(Ok(candidate)) => manager.queue_candidate(&id, candidate).await,
+                    Some(Err(e)) => Err(e.into()),
+                };
+                if let Err(error) = sent {
+                    manager.emit(PeerConnectionEvent::Error { peer_id: id, error });
+                }
+            })
+        }));
+
+        let (this, id) = (self.this.clone(), peer_id.to_string());
+        let weak_connection = Arc::downgrade(connection);
+        connection.on_peer_connection_state_change(Box::new(move |state| {
+            let (this, id, weak_connection) = (this.clone(), id.clone(), weak_connection.clone());
+            Box::pin(async move {
+                let Some(manager) = this.upgrade() else {
+                    return;
+                };
+                // Forget closed connections so that the peer can be offered again
+                if state == RTCPeerConnectionState::Closed {
+                    let mut peers = manager.peers.lock().await;
+                    if peers.get(&id).is_some_and(|link| Arc::as_ptr(&link.connection) == weak_connection.as_ptr()) {
+                        peers.remove(&id);
+                    }
+                }
+                manager.emit(PeerConnectionEvent::StateChanged { peer_id: id, state });
+            })
+        }));
+
+        let (this, id) = (self.this.clone(), peer_id.to_string());
+        connection.on_negotiation_needed(Box::new(move || {
+            let (this, id) = (this.clone(), id.clone());
+            // The handler runs in the connection's operation queue, which
+            // creating the offer waits for
+            tokio::spawn(async move {
+                let Some(manager) = this.upgrade() else {
+                    return;
+                };
+                if let Err(error) = manager.negotiate(&id, false).await {
+                    manager.emit(PeerConnectionEvent::Error { peer_id: id, error });
+                }
+            });
+            Box::pin(async {})
+        }));
+
+        let (this, id) = (self.this.clone(), peer_id.to_string());
+        let weak_connection = Arc::downgrade(connection);
+        connection.on_ice_connection_state_change(Box::new(move |state| {
+            let (this, id, weak_connection) = (this.clone(), id.clone(), weak_connection.clone());
+            Box::pin(async move {
+                let Some(manager) = this.upgrade() else {
+                    return;
+                };
+                manager.emit(PeerConnectionEvent::IceStateChanged { peer_id: id.clone(), state });
+                if let Some(link) = manager.current_link(&id, &weak_connection).await {
+                    manager.on_ice_state(&id, &link, state).await;
+                }
+            })
+        }));
+
+        let (this, id) = (self.this.clone(), peer_id.to_string());
+        let weak_connection = Arc::downgrade(connection);
+        connection.sctp().transport().on_state_change(Box::new(move |state| {
+            let (this, id, weak_connection) = (this.clone(), id.clone(), weak_connection.clone());
+            Box::pin(async move {
+                let Some(manager) = this.upgrade() else {
+                    return;
+                };
+                manager.emit(PeerConnectionEvent::DtlsStateChanged { peer_id: id.clone(), state });
+                // Restarting ICE does not help a failed DTLS session
+                if state == RTCDtlsTransportState::Failed && !manager.is_polite(&id) {
+                    if let Some(link) = manager.current_link(&id, &weak_connection).await {
+                        manager.emit(PeerConnectionEvent::Health { peer_id: id.clone(), health: PeerHealth::Failed });
+                        if let Err(error) = manager.start_over(&id, &link, link.relay_only).await {
+                            manager.emit(PeerConnectionEvent::Error { peer_id: id, error });
+                        }
+                    }
+                }
+            })
+        }));
+
+        let (this, id) = (self.this.clone(), peer_id.to_string());
+        connection.on_data_channel(Box::new(move |channel| {
+            if let Some(manager) = this.upgrade() {
+                manager.emit(PeerConnectionEvent::DataChannel { peer_id: id.clone(), channel });
+            }
+            Box::pin(async {})
+        }));
+
+        let (this, id) = (self.this.clone(), peer_id.to_string());
+        connection.on_track(Box::new(move |track, _receiver| {
+            if let (Some(manager), Some(track)) = (this.upgrade(), track) {
+                manager.emit(PeerConnectionEvent::Track { peer_id: id.clone(), track });
+            }
+            Box::pin(async {})
+        }));
+    }
+
+    /// Silences a connection being replaced
+    fn detach_handlers(connection: &RTCPeerConnection) {
+        connection.on_ice_candidate(Box::new(|_| Box::pin(async {})));
+        connection.on_peer_connection_state_change(Box::new(|_| Box::pin(async {})));
+        connection.on_ice_connection_state_change(Box::new(|_| Box::pin(async {})));
+        connection.sctp().transport().on_state_change(Box::new(|_| Box::pin(async {})));
+        connection.on_negotiation_needed(Box::new(|| Box::pin(async {})));
+    }
+
+    async fn on_ice_state(&self, peer_id: &str, link: &Arc<Link>, state: RTCIceConnectionState) {
+        let health = match state {
+            RTCIceConnectionState::Connected | RTCIceConnectionState::Completed => {
+                link.recovery.lock().unwrap().recovered();
+                if link.up.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                PeerHealth::Connected
+            }
+            RTCIceConnectionState::Disconnected => {
+                link.up.store(false, Ordering::SeqCst);
+                let grace = link.recovery.lock().unwrap().policy().disconnected_grace;
+                self.recover_unless_up(peer_id, link, grace);
+                PeerHealth::Disconnected
+            }
+            RTCIceConnectionState::Failed => {
+                link.up.store(false, Ordering::SeqCst);
+                self.recover(peer_id, link).await;
+                return;
+            }
+            _ => return,
+        };
+        self.emit(PeerConnectionEvent::Health { peer_id: peer_id.to_string(), health });
+    }
+
+    /// Recovers the connection with `peer_id` unless it is up again, or was
+    /// replaced, after `delay`
+    fn recover_unless_up(&self, peer_id: &str, link: &Arc<Link>, delay: Duration) {
+        let (this, id, link) = (self.this.clone(), peer_id.to_string(), Arc::downgrade(link));
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let (Some(manager), Some(link)) = (this.upgrade(), link.upgrade()) else {
+                return;
+            };
+            let current = manager.link(&id).await.is_some_and(|current| Arc::ptr_eq(&current, &link));
+            if current && !link.is_up() {
+                manager.recover(&id, &link).await;
+            }
+        });
+    }
+
+    /// Takes the next step of the restart policy. Both sides see the failure,
+    /// only the impolite one acts so that their offers do not collide.
+    async fn recover(&self, peer_id: &str, link: &Arc<Link>) {
+        if self.is_polite(peer_id) {
+            return;
+        }
+        let (action, timeout) = {
+            let mut recovery = link.recovery.lock().unwrap();
+            (recovery.next_action(), recovery.policy().restart_timeout)
+        };
+        let health = |health| PeerConnectionEvent::Health { peer_id: peer_id.to_string(), health };
+        let result = match action {
+            RecoveryAction::RestartIce { attempt } => {
+                self.emit(health(PeerHealth::Restarting { attempt }));
+                self.recover_unless_up(peer_id, link, timeout);
+                self.restart_ice(peer_id).await
+            }
+            RecoveryAction::FallBackToRelay => {
+                self.emit(health(PeerHealth::RelayFallback));
+                match self.start_over(peer_id, link, true).await {
+                    Ok(relayed) => {
+                        self.recover_unless_up(peer_id, &relayed, timeout);
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            RecoveryAction::GiveUp => {
+                self.emit(health(PeerHealth::Failed));
+                self.close_peer(peer_id).await
+            }
+        };
+        if let Err(error) = result {
+            self.emit(PeerConnectionEvent::Error { peer_id: peer_id.to_string(), error });
+        }
+    }
+
+    async fn add_pending_candidates(&self, peer_id: &str, link: &Link) -> Result<(), RtcError> {
+        let pending = self.pending_candidates.lock().await.remove(peer_id);
+        let candidates = pending.map(|(_, candidates)| candidates).unwrap_or_default();
+        for candidate in candidates {
+            Self::add_candidate(link, candidate).await?;
+        }
+        Ok(())
+    }
+
+    /// Candidates of an offer we ignored do not match our description
+    async fn add_candidate(link: &Link, candidate: RTCIceCandidateInit) -> Result<(), RtcError> {
+        match link.connection.add_ice_candidate(candidate).await {
+            Err(_) if link.negotiation.ignoring_offer() => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Sends a local candidate once its coalescing window is over, or at
+    /// once for the empty candidate ending the gathering
+    async fn queue_candidate(&self, peer_id: &str, candidate: RTCIceCandidateInit) -> Result<(), RtcError> {
+        let window = self.config.signals.candidate_window;
+        if window.is_zero() {
+            return self.send(peer_id, SignalPayload::IceCandidate(candidate)).await;
+        }
+        let last = candidate.candidate.is_empty();
+        let first = {
+            let mut outgoing = self.outgoing_candidates.lock().await;
+            let batch = outgoing.entry(peer_id.to_string()).or_default();
+            batch.push(candidate);
+            batch.len() == 1
+        };
+        if last {
+            return self.flush_candidates(peer_id).await;
+        }
+        if first {
+            let (this, id) = (self.this.clone(), peer_id.to_string());
+            tokio::spawn(async move {
+                tokio::time::sleep(window).await;
+                let Some(manager) = this.upgrade() else {
+                    return;
+                };
+                if let Err(error) = manager.flush_candidates(&id).await {
+                    manager.emit(PeerConnectionEvent::Error { peer_id: id, error });
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends the queued candidates in one signal if the peer supports it.
+    /// The queue stays locked meanwhile, so that batches keep their order.
+    async fn flush_candidates(&self, peer_id: &str) -> Result<(), RtcError> {
+        let mut outgoing = self.outgoing_candidates.lock().await;
+        let Some(batch) = outgoing.remove(peer_id) else {
+            return Ok(());
+        };
+        if batch.len() > 1 && self.supports(peer_id, SignalFeature::CandidateBatches).await {
+            return self.send(peer_id, SignalPayload::IceCandidates(batch)).await;
+        }
+        for candidate in batch {
+            self.send(peer_id, SignalPayload::IceCandidate(candidate)).await?;
+        }
+        Ok(())
+    }
+
+    async fn supports(&self, peer_id: &str, feature: SignalFeature) -> bool {
+        self.features.lock().await.get(peer_id).is_some_and(|features| features.contains(&feature))
+    }
+
+    async fn send(&self, peer_id: &str, payload: SignalPayload) -> Result<(), RtcError> {
+        let mut signal = SignalMessage::new(&self.local_id, peer_id, payload);
+        if self.config.signals.deflate && self.supports(peer_id, SignalFeature::Deflate).await {
+            signal = signal.deflated();
+        }
+        self.signals.send_signal(peer_id, signal.encode()).await?;
+        Ok(())
+    }
+
+    fn emit(&self, event: PeerConnectionEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signaling::Peer;
+    use std::time::Duration;
+    use webrtc::api::media_engine::MIME_TYPE_VP8;
+    use webrtc::data_channel::data_channel_message::DataChannelMessage;
+    use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+    use webrtc::ice_transport::ice_gathering_state::RTCIceGatheringState;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+    use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+
+    /// Hands signals straight to the other in-process peer, as the hub would
+    struct Loopback {
+        from: String,
+        hub: mpsc::UnboundedSender<PeerEvent>,
+    }
+
+    impl SignalSender for Loopback {
+        fn send_signal<'a>(&'a self, _peer_id: &'a str, signal: String) -> BoxFuture<'a, Result<(), HubError>> {
+            let from = Peer { id: self.from.clone(), username: None };
+            let sent = self.hub.send(PeerEvent::Signal { from, signal, relayed: false });
+            Box::pin(async move { sent.map_err(|_| HubError::ConnectionClosed) })
+        }
+    }
+
+    /// Two peers whose signals go to each other
+    struct Pair {
+        alice: Arc<PeerManager>,
+        alice_events: mpsc::UnboundedReceiver<PeerConnectionEvent>,
+        to_alice: mpsc::UnboundedSender<PeerEvent>,
+        bob: Arc<PeerManager>,
+        bob_events: mpsc::UnboundedReceiver<PeerConnectionEvent>,
+        to_bob: mpsc::UnboundedSender<PeerEvent>,
+    }
+
+    fn pair() -> Pair {
+        let (to_alice, hub_a) = mpsc::unbounded_channel();
+        let (to_bob, hub_b) = mpsc::unbounded_channel();
+        let sender = |from: &str, hub: &mpsc::UnboundedSender<PeerEvent>| {
+            Arc::new(Loopback { from: from.to_string(), hub: hub.clone() })
+        };
+        let config = PeerConfig::default();
+        let (alice, alice_events) = PeerManager::new("peer-a", config.clone(), sender("peer-a", &to_bob)).unwrap();
+        let (bob, bob_events) = PeerManager::new("peer-b", config, sender("peer-b", &to_alice)).unwrap();
+        alice.follow(hub_a);
+        bob.follow(hub_b);
+        Pair { alice, alice_events, to_alice, bob, bob_events, to_bob }
+    }
+
+    /// Polls until both connections are up and negotiated
+    async fn negotiated(pair: &Pair) {
+        let done = |manager: &Arc<PeerManager>, peer_id: &'static str| {
+            let manager = manager.clone();
+            async move {
+                let Some(connection) = manager.peer(peer_id).await else {
+                    return false;
+                };
+                connection.connection_state() == RTCPeerConnectionState::Connected
+                    && connection.signaling_state() == RTCSignalingState::Stable
+            }
+        };
+        let wait = async {
+            while !(done(&pair.alice, "peer-b").await && done(&pair.bob, "peer-a").await) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), wait).await.unwrap();
+    }
+
+    async fn next_data_channel(events: &mut mpsc::UnboundedReceiver<PeerConnectionEvent>) -> Arc<RTCDataChannel> {
+        loop {
+            match events.recv().await {
+                Some(PeerConnectionEvent::DataChannel { channel, .. }) => return channel,
+                Some(PeerConnectionEvent::Error { error, .. }) => panic!("{}", error),
+                Some(_) => {}
+                None => panic!("events closed"),
+            }
+        }
+    }
+
+    async fn connected(events: &mut mpsc::UnboundedReceiver<PeerConnectionEvent>) -> String {
+        loop {
+            match events.recv().await {
+                Some(PeerConnectionEvent::StateChanged { peer_id, state: RTCPeerConnectionState::Connected }) => {
+                    return peer_id;
+                }
+                Some(PeerConnectionEvent::Error { error, .. }) => panic!("{}", error),
+                Some(_) => {}
+                None => panic!("events closed"),
+            }
+        }
+    }
+
+    async fn health(events: &mut mpsc::UnboundedReceiver<PeerConnectionEvent>) -> PeerHealth {
+        loop {
+            match events.recv().await {
+                Some(PeerConnectionEvent::Health { health, .. }) => return health,
+                Some(PeerConnectionEvent::Error { error, .. }) => panic!("{}", error),
+                Some(_) => {}
+                None => panic!("events closed"),
+            }
+        }
+    }
+
+    fn ice_ufrag(sdp: &str) -> Option<String> {
+        sdp.lines().find_map(|line| line.trim_end().strip_prefix("a=ice-ufrag:")).map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn test_in_process_peers_connect() {
+        let Pair { alice, mut alice_events, to_alice, bob, mut bob_events, to_bob } = pair();
+
+        // Both learn about each other; only the impolite peer connects
+        assert!(!alice.is_polite("peer-b") && bob.is_polite("peer-a"));
+        to_alice.send(PeerEvent::Joined(Peer { id: "peer-b".to_string(), username: None })).unwrap();
+        to_bob.send(PeerEvent::Joined(Peer { id: "peer-a".to_string(), username: None })).unwrap();
+
+        let (received_tx, mut received) = mpsc::unbounded_channel();
+        let exchange = async {
+            let offered = next_data_channel(&mut alice_events).await;
+            assert_eq!(offered.label(), DATA_CHANNEL_LABEL);
+            let (open_tx, open) = tokio::sync::oneshot::channel();
+            offered.on_open(Box::new(move || {
+                let _ = open_tx.send(());
+                Box::pin(async {})
+            }));
+
+            // Messages reaching a channel without a handler are dropped
+            let answered = next_data_channel(&mut bob_events).await;
+            answered.on_message(Box::new(move |message: DataChannelMessage| {
+                let _ = received_tx.send(String::from_utf8_lossy(&message.data).to_string());
+                Box::pin(async {})
+            }));
+
+            assert_eq!(connected(&mut alice_events).await, "peer-b");
+            open.await.unwrap();
+            offered.send_text("ping".to_string()).await.unwrap();
+            received.recv().await
+        };
+        let message = tokio::time::timeout(Duration::from_secs(30), exchange).await.unwrap();
+        assert_eq!(message.as_deref(), Some("ping"));
+        assert_eq!(alice.peers().await, vec!["peer-b"]);
+        let answerer = bob.peer("peer-a").await.unwrap();
+        assert_eq!(answerer.connection_state(), RTCPeerConnectionState::Connected);
+
+        // Leaving closes the connection on the other side
+        to_bob.send(PeerEvent::Left(Peer { id: "peer-a".to_string(), username: None })).unwrap();
+        alice.close().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !bob.peers().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_signals_for_other_peers_are_ignored() {
+        let (hub, _) = mpsc::unbounded_channel();
+        let sender = Arc::new(Loopback { from: "peer-a".to_string(), hub });
+        let (manager, _) = PeerManager::new("peer-a", PeerConfig::default(), sender).unwrap();
+
+        let signal = r#"{"type":"ice-candidate","from":"peer-b","to":"peer-c","data":{"candidate":""}}"#;
+        manager.handle_signal("peer-b", signal).await.unwrap();
+        assert!(manager.pending_candidates.lock().await.is_empty());
+
+        let signal = r#"{"type":"bogus","from":"peer-b","to":"peer-a","data":null}"#;
+        assert!(matches!(manager.handle_signal("peer-b", signal).await, Err(RtcError::InvalidSignal(_))));
+        assert!(matches!(manager.handle_signal("peer-b", "not json").await, Err(RtcError::InvalidSignal(_))));
+    }
+
+    #[tokio::test]
+    async fn test_signals_use_the_extensions_of_the_peer() {
+        let (hub, mut signals) = mpsc::unbounded_channel();
+        let sender = Arc::new(Loopback { from: "peer-a".to_string(), hub });
+        // A window long enough for the end of the gathering to flush it
+        let signal_options = SignalOptions { candidate_window: Duration::from_secs(30), deflate: true };
+        let config = PeerConfig { signals: signal_options, ..Default::default() };
+        let (manager, _) = PeerManager::new("peer-a", config, sender).unwrap();
+
+        let hello = r#"{"type":"ice-candidate","from":"peer-b","to":"peer-a","supports":["deflate","candidate-batches"],
+            "data":{"candidate":""}}"#;
+        manager.handle_signal("peer-b", hello).await.unwrap();
+        // Codecs make the offer long enough to be worth compressing
+        let track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability { mime_type: MIME_TYPE_VP8.to_string(), ..Default::default() },
+            "video".to_string(),
+            "peer-a".to_string(),
+        ));
+        manager.add_track("peer-b", track).await.unwrap();
+
+        async fn next(signals: &mut mpsc::UnboundedReceiver<PeerEvent>) -> String {
+            let Some(PeerEvent::Signal { signal, .. }) = signals.recv().await else {
+                panic!("signals closed");
+            };
+            signal
+        }
+        let offer = tokio::time::timeout(Duration::from_secs(10), next(&mut signals)).await.unwrap();
+        let wire: serde_json::Value = serde_json::from_str(&offer).unwrap();
+        assert_eq!(wire["data"]["encoding"], "deflate");
+        assert!(matches!(SignalMessage::parse(&offer).unwrap().payload, SignalPayload::Offer(_)));
+
+        // Every host candidate and the end marker come in one signal
+        let batch = tokio::time::timeout(Duration::from_secs(10), next(&mut signals)).await.unwrap();
+        let SignalPayload::IceCandidates(candidates) = SignalMessage::parse(&batch).unwrap().payload else {
+            panic!("expected a batch: {}", batch);
+        };
+        assert!(candidates.len() > 1);
+        assert_eq!(candidates.last().unwrap().candidate, "");
+        manager.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_candidates_are_filtered_both_ways() {
+        let (hub, mut signals) = mpsc::unbounded_channel();
+        let sender = Arc::new(Loopback { from: "peer-a".to_string(), hub });
+        let candidates = CandidatePolicy {
+            types: vec![RTCIceCandidateType::Srflx, RTCIceCandidateType::Relay],
+            ..Default::default()
+        };
+        let config = PeerConfig { candidates, ..Default::default() };
+        let (manager, _) = PeerManager::new("peer-a", config, sender).unwrap();
+
+        // Only host candidates are gathered without servers, none is sent
+        manager.connect("peer-b").await.unwrap();
+        let connection = manager.peer("peer-b").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while connection.ice_gathering_state() != RTCIceGatheringState::Complete {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+        manager.close().await.unwrap();
+        let mut offered = false;
+        while let Ok(PeerEvent::Signal { signal, .. }) = signals.try_recv() {
+            match SignalMessage::parse(&signal).unwrap().payload {
+                SignalPayload::IceCandidate(init) => assert_eq!(init.candidate, ""),
+                SignalPayload::Offer(_) => offered = true,
+                _ => {}
+            }
+        }
+        assert!(offered);
+
+        let remote = |candidate: &str| RTCIceCandidateInit { candidate: candidate.to_string(), ..Default::default() };
+        let host = remote("candidate:1 1 udp 2130706431 192.168.1.20 50000 typ host");
+        let srflx = remote("candidate:2 1 udp 1694498815 203.0.113.7 61000 typ srflx raddr 0.0.0.0 rport 0");
+        manager.handle_ice_candidate("peer-c", host).await.unwrap();
+        manager.handle_ice_candidate("peer-c", srflx.clone()).await.unwrap();
+        assert_eq!(manager.pending_candidates.lock().await["peer-c"].1, vec![srflx]);
+    }
+
+    #[tokio::test]
+    async fn test_pending_candidates_are_capped_and_expire() {
+        let (hub, _) = mpsc::unbounded_channel();
+        let sender = Arc::new(Loopback { from: "peer-a".to_string(), hub });
+        let (manager, _) = PeerManager::new("peer-a", PeerConfig::default(), sender).unwrap();
+
+        let candidate = |port: usize| RTCIceCandidateInit {
+            candidate: format!("candidate:1 1 udp 2130706431 192.168.1.20 {port} typ host"),
+            ..Default::default()
+        };
+        for port in 0..MAX_PENDING_CANDIDATES + 2 {
+            manager.handle_ice_candidate("peer-b", candidate(port)).await.unwrap();
+        }
+        {
+            let pending = manager.pending_candidates.lock().await;
+            let candidates = &pending["peer-b"].1;
+            assert_eq!(candidates.len(), MAX_PENDING_CANDIDATES);
+            assert_eq!(candidates[0], candidate(2));
+        }
+
+        // peer-b never sends its description
+        manager.pending_candidates.lock().await.get_mut("peer-b").unwrap().0 -= PENDING_CANDIDATES_TIMEOUT;
+        manager.handle_ice_candidate("peer-c", candidate(0)).await.unwrap();
+        let pending = manager.pending_candidates.lock().await;
+        assert_eq!(pending.keys().collect::<Vec<_>>(), vec!["peer-c"]);
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_offers_are_resolved() {
+        let pair = pair();
+        let (alice, bob) = (pair.alice.clone(), pair.bob.clone());
+        let (a, b) = tokio::join!(alice.connect("peer-b"), bob.connect("peer-a"));
+        a.unwrap();
+        b.unwrap();
+
+        negotiated(&pair).await;
+        assert_eq!(pair.alice.peers().await, vec!["peer-b"]);
+        assert_eq!(pair.bob.peers().await, vec!["peer-a"]);
+    }
+
+    #[tokio::test]
+    async fn test_tracks_added_mid_call_are_negotiated() {
+        let pair = pair();
+        pair.alice.connect("peer-b").await.unwrap();
+        negotiated(&pair).await;
+
+        // The polite peer offers too once connected
+        let track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability { mime_type: MIME_TYPE_VP8.to_string(), ..Default::default() },
+            "video".to_string(),
+            "peer-b".to_string(),
+        ));
+        pair.bob.add_track("peer-a", track).await.unwrap();
+
+        let has_video = async {
+            loop {
+                let connection = pair.alice.peer("peer-b").await.unwrap();
+                let remote = connection.remote_description().await.map(|d| d.sdp).unwrap_or_default();
+                if remote.contains("m=video") && connection.signaling_state() == RTCSignalingState::Stable {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), has_video).await.unwrap();
+        negotiated(&pair).await;
+    }
+
+    #[tokio::test]
+    async fn test_ice_restarts_keep_the_connection() {
+        let mut pair = pair();
+        pair.alice.connect("peer-b").await.unwrap();
+        negotiated(&pair).await;
+        let connected = tokio::time::timeout(Duration::from_secs(30), health(&mut pair.alice_events)).await;
+        assert_eq!(connected.unwrap(), PeerHealth::Connected);
+
+        let answerer = pair.bob.peer("peer-a").await.unwrap();
+        let ufrag = ice_ufrag(&answerer.remote_description().await.unwrap().sdp);
+        pair.alice.restart_ice("peer-b").await.unwrap();
+
+        let restarted = async {
+            while ice_ufrag(&answerer.remote_description().await.unwrap().sdp) == ufrag {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), restarted).await.unwrap();
+
+        // webrtc-rs leaves the connection state at `new` after a restart, ICE tells
+        let reconnected = async {
+            while !(answerer.ice_connection_state() == RTCIceConnectionState::Connected
+                && answerer.signaling_state() == RTCSignalingState::Stable)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), reconnected).await.unwrap();
+        assert!(Arc::ptr_eq(&answerer, &pair.bob.peer("peer-a").await.unwrap()));
+        assert!(pair.alice.stats("peer-b").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reconnecting_replaces_both_connections() {
+        let pair = pair();
+        pair.alice.connect("peer-b").await.unwrap();
+        negotiated(&pair).await;
+
+        let (offerer, answerer) = (pair.alice.peer("peer-b").await.unwrap(), pair.bob.peer("peer-a").await.unwrap());
+        pair.alice.reconnect("peer-b").await.unwrap();
+
+        // The new DTLS fingerprint tells the answerer to start over too
+        let replaced = async {
+            while pair.bob.peer("peer-a").await.is_some_and(|current| Arc::ptr_eq(&current, &answerer)) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), replaced).await.unwrap();
+        negotiated(&pair).await;
+        assert!(!Arc::ptr_eq(&offerer, &pair.alice.peer("peer-b").await.unwrap()));
+        assert_eq!(offerer.connection_state(), RTCPeerConnectionState::Closed);
+    }
+}
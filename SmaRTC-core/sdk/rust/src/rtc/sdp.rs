@@ -0,0 +1,717 @@
+// SmaRTC Rust SDK - SDP parsing and munging: codec preferences, bandwidth caps, simulcast and RTX
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Header extension carrying the simulcast layer of each packet
+pub const RID_EXTENSION: &str = "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id";
+
+/// Payloads that are not media codecs and never get an RTX payload
+const NOT_MEDIA: [&str; 6] = ["rtx", "red", "ulpfec", "flexfec-03", "cn", "telephone-event"];
+
+/// `codec` of the JavaScript `ConnectionConfig`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    Vp8,
+    Vp9,
+    H264,
+}
+
+impl VideoCodec {
+    /// Encoding name used by `a=rtpmap`
+    pub fn encoding_name(self) -> &'static str {
+        match self {
+            VideoCodec::Vp8 => "VP8",
+            VideoCodec::Vp9 => "VP9",
+            VideoCodec::H264 => "H264",
+        }
+    }
+}
+
+/// `QualityLevel` of the JavaScript SDK
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QualityLevel {
+    /// 144p
+    VeryLow,
+    /// 240p
+    Low,
+    /// 360p
+    #[default]
+    Medium,
+    /// 480p
+    High,
+    /// 720p
+    VeryHigh,
+}
+
+impl QualityLevel {
+    /// Video bitrate, in kbps
+    pub fn bitrate_kbps(self) -> u32 {
+        match self {
+            QualityLevel::VeryLow => 50,
+            QualityLevel::Low => 100,
+            QualityLevel::Medium => 200,
+            QualityLevel::High => 400,
+            QualityLevel::VeryHigh => 800,
+        }
+    }
+
+    /// Frame height, in pixels
+    pub fn height(self) -> u32 {
+        match self {
+            QualityLevel::VeryLow => 144,
+            QualityLevel::Low => 240,
+            QualityLevel::Medium => 360,
+            QualityLevel::High => 480,
+            QualityLevel::VeryHigh => 720,
+        }
+    }
+}
+
+/// Why an SDP could not be parsed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid SDP at line {line}: {reason}")]
+pub struct SdpError {
+    /// 1-based
+    pub line: usize,
+    pub reason: String,
+}
+
+/// One `<type>=<value>` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SdpLine {
+    pub kind: char,
+    pub value: String,
+}
+
+impl SdpLine {
+    pub fn new(kind: char, value: impl Into<String>) -> Self {
+        Self { kind, value: value.into() }
+    }
+
+    /// `a=<name>:<value>`, or `a=<name>` without a value
+    pub fn attribute(name: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(value) => Self::new('a', format!("{}:{}", name, value)),
+            None => Self::new('a', name),
+        }
+    }
+
+    /// The name and value of an `a=` line
+    pub fn as_attribute(&self) -> Option<(&str, Option<&str>)> {
+        if self.kind != 'a' {
+            return None;
+        }
+        Some(match self.value.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (self.value.as_str(), None),
+        })
+    }
+
+    /// The value of an `a=<name>:` line
+    fn value_of(&self, name: &str) -> Option<&str> {
+        match self.as_attribute()? {
+            (found, value) if found == name => value,
+            _ => None,
+        }
+    }
+
+    /// The payload type an `a=rtpmap`, `a=fmtp` or `a=rtcp-fb` line is about
+    fn payload_type(&self) -> Option<&str> {
+        let (name, value) = self.as_attribute()?;
+        match name {
+            "rtpmap" | "fmtp" | "rtcp-fb" => value?.split_whitespace().next(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SdpLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.kind, self.value)
+    }
+}
+
+/// A media description: its `m=` line and the lines up to the next one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaSection {
+    /// `audio`, `video` or `application`
+    pub kind: String,
+    /// `<port>[/<count>]`
+    pub port: String,
+    pub protocol: String,
+    /// Payload types for RTP, e.g. `webrtc-datachannel` for SCTP
+    pub formats: Vec<String>,
+    pub lines: Vec<SdpLine>,
+}
+
+impl MediaSection {
+    /// Values of the `a=<name>` lines, empty for flags
+    pub fn attributes<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.lines.iter().filter_map(move |line| match line.as_attribute()? {
+            (found, value) if found == name => Some(value.unwrap_or("")),
+            _ => None,
+        })
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attributes(name).next().is_some()
+    }
+
+    /// `a=mid`
+    pub fn mid(&self) -> Option<&str> {
+        self.attributes("mid").next()
+    }
+
+    /// Encoding name of a payload type, from `a=rtpmap:<pt> <name>/<clock rate>`
+    pub fn codec(&self, payload_type: &str) -> Option<&str> {
+        self.rtpmap(payload_type).map(|(name, _)| name)
+    }
+
+    /// Payload types of a codec, in order of preference. Names are not case
+    /// sensitive.
+    pub fn payload_types(&self, codec: &str) -> Vec<String> {
+        let matches = |pt: &&String| self.codec(pt).is_some_and(|name| name.eq_ignore_ascii_case(codec));
+        self.formats.iter().filter(matches).cloned().collect()
+    }
+
+    /// RTX payload types retransmitting `payload_type`
+    pub fn rtx_payload_types(&self, payload_type: &str) -> Vec<String> {
+        self.payload_types("rtx").into_iter().filter(|rtx| self.rtx_apt(rtx) == Some(payload_type)).collect()
+    }
+
+    /// Moves the codecs named in `codecs` first, in that order. Their RTX
+    /// payloads follow them.
+    pub fn prefer_codecs(&mut self, codecs: &[&str]) {
+        let rank = |pt: &str| {
+            let pt = self.rtx_apt(pt).unwrap_or(pt);
+            let name = self.codec(pt).unwrap_or_default();
+            codecs.iter().position(|codec| codec.eq_ignore_ascii_case(name)).unwrap_or(codecs.len())
+        };
+        let mut formats: Vec<(usize, String)> = self.formats.iter().map(|pt| (rank(pt), pt.clone())).collect();
+        formats.sort_by_key(|(rank, _)| *rank);
+        self.formats = formats.into_iter().map(|(_, pt)| pt).collect();
+    }
+
+    /// Removes the codecs named in `codecs` with their RTX payloads,
+    /// returning how many payload types were removed
+    pub fn strip_codecs(&mut self, codecs: &[&str]) -> usize {
+        let mut removed: HashSet<String> = HashSet::new();
+        for codec in codecs {
+            for pt in self.payload_types(codec) {
+                removed.extend(self.rtx_payload_types(&pt));
+                removed.insert(pt);
+            }
+        }
+        self.remove_payload_types(&removed);
+        removed.len()
+    }
+
+    /// Caps the bandwidth with `b=AS` (kbps) and `b=TIAS` (bps), or removes
+    /// the caps
+    pub fn set_bandwidth(&mut self, kbps: Option<u32>) {
+        self.lines.retain(|line| !(line.kind == 'b' && is_capped(&line.value)));
+        let Some(kbps) = kbps else {
+            return;
+        };
+        // `b=` lines come after `i=` and `c=`, before `k=` and `a=`
+        let at = self.lines.iter().position(|line| !matches!(line.kind, 'i' | 'c' | 'b'));
+        let at = at.unwrap_or(self.lines.len());
+        let caps = [
+            SdpLine::new('b', format!("AS:{}", kbps)),
+            SdpLine::new('b', format!("TIAS:{}", u64::from(kbps) * 1000)),
+        ];
+        self.lines.splice(at..at, caps);
+    }
+
+    /// `b=AS`, in kbps
+    pub fn bandwidth(&self) -> Option<u32> {
+        self.lines.iter().filter(|line| line.kind == 'b').find_map(|line| line.value.strip_prefix("AS:")?.parse().ok())
+    }
+
+    /// Announces simulcast layers with `a=rid` and `a=simulcast`, or removes
+    /// simulcast when `rids` is empty. The RID header extension gets an id
+    /// missing from `taken_extensions` if needed.
+    pub fn set_simulcast(&mut self, rids: &[&str], taken_extensions: &mut HashSet<u16>) {
+        self.lines.retain(|line| {
+            !matches!(line.as_attribute(), Some(("rid" | "simulcast", _)))
+                && !line.value_of("ssrc-group").is_some_and(|group| group.starts_with("SIM "))
+        });
+        if rids.is_empty() {
+            return;
+        }
+        let has_extension = self.attributes("extmap").any(|extmap| extmap.split(' ').nth(1) == Some(RID_EXTENSION));
+        if !has_extension {
+            if let Some(id) = (1..=14).find(|id| !taken_extensions.contains(id)) {
+                taken_extensions.insert(id);
+                self.lines.push(SdpLine::attribute("extmap", Some(&format!("{} {}", id, RID_EXTENSION))));
+            }
+        }
+        for rid in rids {
+            self.lines.push(SdpLine::attribute("rid", Some(&format!("{} send", rid))));
+        }
+        self.lines.push(SdpLine::attribute("simulcast", Some(&format!("send {}", rids.join(";")))));
+    }
+
+    /// The `a=rid` layers announced by `a=simulcast`
+    pub fn simulcast(&self) -> Vec<&str> {
+        self.attributes("rid").filter_map(|rid| rid.split_whitespace().next()).collect()
+    }
+
+    /// Gives every media codec an RTX payload, taking free dynamic payload
+    /// types from `taken`, or removes RTX with its `FID` SSRC groups
+    pub fn set_rtx(&mut self, enabled: bool, taken: &mut HashSet<u8>) {
+        if !enabled {
+            let removed = self.payload_types("rtx").into_iter().collect();
+            self.remove_payload_types(&removed);
+            let repairs: Vec<String> = self
+                .attributes("ssrc-group")
+                .filter_map(|group| group.strip_prefix("FID "))
+                .filter_map(|ssrcs| ssrcs.split_whitespace().nth(1))
+                .map(str::to_string)
+                .collect();
+            self.lines.retain(|line| {
+                let fid = line.value_of("ssrc-group").is_some_and(|group| group.starts_with("FID "));
+                let repair = line.value_of("ssrc").is_some_and(|ssrc| {
+                    repairs.iter().any(|repair| ssrc.split_whitespace().next() == Some(repair.as_str()))
+                });
+                !fid && !repair
+            });
+            return;
+        }
+        let primaries: Vec<(String, String)> = self
+            .formats
+            .iter()
+            .filter_map(|pt| Some((pt.clone(), self.rtpmap(pt)?)))
+            .filter(|(_, (name, _))| !NOT_MEDIA.iter().any(|other| other.eq_ignore_ascii_case(name)))
+            .map(|(pt, (_, clock_rate))| (pt, clock_rate.to_string()))
+            .collect();
+        for (pt, clock_rate) in primaries {
+            if !self.rtx_payload_types(&pt).is_empty() {
+                continue;
+            }
+            let Some(rtx) = (96..=127).find(|free| !taken.contains(free)) else {
+                return;
+            };
+            taken.insert(rtx);
+            let at = self.formats.iter().position(|format| *format == pt).map_or(self.formats.len(), |at| at + 1);
+            self.formats.insert(at, rtx.to_string());
+            self.lines.push(SdpLine::attribute("rtpmap", Some(&format!("{} rtx/{}", rtx, clock_rate))));
+            self.lines.push(SdpLine::attribute("fmtp", Some(&format!("{} apt={}", rtx, pt))));
+        }
+    }
+
+    /// Encoding name and clock rate of a payload type
+    fn rtpmap(&self, payload_type: &str) -> Option<(&str, &str)> {
+        let (name, clock_rate) = self.attributes("rtpmap").find_map(|rtpmap| {
+            let (pt, encoding) = rtpmap.split_once(' ')?;
+            (pt == payload_type).then_some(encoding)
+        })?.split_once('/')?;
+        Some((name, clock_rate.split('/').next().unwrap_or(clock_rate)))
+    }
+
+    /// The payload type an RTX payload type retransmits
+    fn rtx_apt(&self, payload_type: &str) -> Option<&str> {
+        self.attributes("fmtp").find_map(|fmtp| {
+            let (pt, parameters) = fmtp.split_once(' ')?;
+            if pt != payload_type {
+                return None;
+            }
+            parameters.split(';').find_map(|parameter| parameter.trim().strip_prefix("apt="))
+        })
+    }
+
+    fn remove_payload_types(&mut self, removed: &HashSet<String>) {
+        self.formats.retain(|pt| !removed.contains(pt));
+        self.lines.retain(|line| !line.payload_type().is_some_and(|pt| removed.contains(pt)));
+    }
+}
+
+fn is_capped(bandwidth: &str) -> bool {
+    bandwidth.starts_with("AS:") || bandwidth.starts_with("TIAS:")
+}
+
+/// A session description: the session-level lines, from `v=` on, and the
+/// media sections. Parsing then printing gives back the same SDP, with CRLF
+/// line endings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sdp {
+    pub session: Vec<SdpLine>,
+    pub media: Vec<MediaSection>,
+}
+
+impl Sdp {
+    /// Media sections of one kind, e.g. `video`
+    pub fn media_of<'a>(&'a mut self, kind: &'a str) -> impl Iterator<Item = &'a mut MediaSection> + 'a {
+        self.media.iter_mut().filter(move |media| media.kind == kind)
+    }
+
+    /// Moves `codec` first in the video sections, like `codec` in the
+    /// JavaScript `ConnectionConfig`
+    pub fn prefer_codec(&mut self, codec: VideoCodec) {
+        for media in self.media_of("video") {
+            media.prefer_codecs(&[codec.encoding_name()]);
+        }
+    }
+
+    /// Caps the video bitrate to that of `quality`
+    pub fn set_quality(&mut self, quality: QualityLevel) {
+        for media in self.media_of("video") {
+            media.set_bandwidth(Some(quality.bitrate_kbps()));
+        }
+    }
+
+    /// [`MediaSection::set_rtx`] on the video sections; payload types stay
+    /// unique across the bundle
+    pub fn set_rtx(&mut self, enabled: bool) {
+        let mut taken: HashSet<u8> =
+            self.media.iter().flat_map(|media| &media.formats).filter_map(|pt| pt.parse().ok()).collect();
+        for media in self.media_of("video") {
+            media.set_rtx(enabled, &mut taken);
+        }
+    }
+
+    /// [`MediaSection::set_simulcast`] on the video sections; header
+    /// extension ids stay unique across the bundle
+    pub fn set_simulcast(&mut self, rids: &[&str]) {
+        let mut taken: HashSet<u16> = self
+            .media
+            .iter()
+            .flat_map(|media| media.attributes("extmap"))
+            .filter_map(|extmap| extmap.split([' ', '/']).next()?.parse().ok())
+            .collect();
+        for media in self.media_of("video") {
+            media.set_simulcast(rids, &mut taken);
+        }
+    }
+}
+
+impl FromStr for Sdp {
+    type Err = SdpError;
+
+    /// Accepts CRLF and LF line endings
+    fn from_str(sdp: &str) -> Result<Self, SdpError> {
+        let mut session = Vec::new();
+        let mut media: Vec<MediaSection> = Vec::new();
+        for (index, line) in sdp.lines().enumerate() {
+            let error = |reason: &str| SdpError { line: index + 1, reason: reason.to_string() };
+            if line.is_empty() {
+                continue;
+            }
+            let Some((kind, value)) = line.split_once('=') else {
+                return Err(error("expected `<type>=<value>`"));
+            };
+            let mut chars = kind.chars();
+            let (Some(kind), None) = (chars.next(), chars.next()) else {
+                return Err(error("the type must be a single letter"));
+            };
+            if !kind.is_ascii_lowercase() {
+                return Err(error("the type must be a single letter"));
+            }
+            if session.is_empty() && kind != 'v' {
+                return Err(error("an SDP starts with `v=`"));
+            }
+            if kind == 'm' {
+                let mut fields = value.split(' ');
+                let (Some(kind), Some(port), Some(protocol)) = (fields.next(), fields.next(), fields.next()) else {
+                    return Err(error("expected `m=<media> <port> <proto> <fmt> ...`"));
+                };
+                media.push(MediaSection {
+                    kind: kind.to_string(),
+                    port: port.to_string(),
+                    protocol: protocol.to_string(),
+                    formats: fields.map(str::to_string).collect(),
+                    lines: Vec::new(),
+                });
+                continue;
+            }
+            let line = SdpLine::new(kind, value);
+            match media.last_mut() {
+                Some(section) => section.lines.push(line),
+                None => session.push(line),
+            }
+        }
+        if session.is_empty() {
+            return Err(SdpError { line: 1, reason: "empty SDP".to_string() });
+        }
+        Ok(Self { session, media })
+    }
+}
+
+impl fmt::Display for Sdp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.session {
+            write!(f, "{}\r\n", line)?;
+        }
+        for media in &self.media {
+            write!(f, "m={} {} {}", media.kind, media.port, media.protocol)?;
+            for format in &media.formats {
+                write!(f, " {}", format)?;
+            }
+            write!(f, "\r\n")?;
+            for line in &media.lines {
+                write!(f, "{}\r\n", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chrome offer with audio, video and a data channel
+    const CHROME: &str = "v=0
+o=- 4611731400430051336 2 IN IP4 127.0.0.1
+s=-
+t=0 0
+a=group:BUNDLE 0 1 2
+a=extmap-allow-mixed
+a=msid-semantic: WMS stream
+m=audio 9 UDP/TLS/RTP/SAVPF 111 63 9 0 8 13 110 126
+c=IN IP4 0.0.0.0
+a=rtcp:9 IN IP4 0.0.0.0
+a=ice-ufrag:ZZy8
+a=ice-pwd:Lm0K9cGdDlZzBnnR0+Ea2SSk
+a=ice-options:trickle
+a=fingerprint:sha-256 7B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08
+a=setup:actpass
+a=mid:0
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level
+a=extmap:4 urn:ietf:params:rtp-hdrext:sdes:mid
+a=sendrecv
+a=msid:stream audio0
+a=rtcp-mux
+a=rtpmap:111 opus/48000/2
+a=rtcp-fb:111 transport-cc
+a=fmtp:111 minptime=10;useinbandfec=1
+a=rtpmap:63 red/48000/2
+a=fmtp:63 111/111
+a=rtpmap:9 G722/8000
+a=rtpmap:0 PCMU/8000
+a=rtpmap:8 PCMA/8000
+a=rtpmap:13 CN/8000
+a=rtpmap:110 telephone-event/48000
+a=rtpmap:126 telephone-event/8000
+a=ssrc:1001 cname:4TOk42mSjXCkVIa6
+a=ssrc:1001 msid:stream audio0
+m=video 9 UDP/TLS/RTP/SAVPF 96 97 98 99 102 103 45 46
+c=IN IP4 0.0.0.0
+a=rtcp:9 IN IP4 0.0.0.0
+a=ice-ufrag:ZZy8
+a=ice-pwd:Lm0K9cGdDlZzBnnR0+Ea2SSk
+a=ice-options:trickle
+a=fingerprint:sha-256 7B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08
+a=setup:actpass
+a=mid:1
+a=extmap:2 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time
+a=extmap:3 http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01
+a=extmap:4 urn:ietf:params:rtp-hdrext:sdes:mid
+a=sendrecv
+a=msid:stream video0
+a=rtcp-mux
+a=rtcp-rsize
+a=rtpmap:96 VP8/90000
+a=rtcp-fb:96 goog-remb
+a=rtcp-fb:96 transport-cc
+a=rtcp-fb:96 ccm fir
+a=rtcp-fb:96 nack
+a=rtcp-fb:96 nack pli
+a=rtpmap:97 rtx/90000
+a=fmtp:97 apt=96
+a=rtpmap:98 VP9/90000
+a=rtcp-fb:98 transport-cc
+a=rtcp-fb:98 nack
+a=rtcp-fb:98 nack pli
+a=fmtp:98 profile-id=0
+a=rtpmap:99 rtx/90000
+a=fmtp:99 apt=98
+a=rtpmap:102 H264/90000
+a=rtcp-fb:102 transport-cc
+a=rtcp-fb:102 nack
+a=rtcp-fb:102 nack pli
+a=fmtp:102 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f
+a=rtpmap:103 rtx/90000
+a=fmtp:103 apt=102
+a=rtpmap:45 AV1/90000
+a=rtcp-fb:45 nack
+a=rtpmap:46 rtx/90000
+a=fmtp:46 apt=45
+a=ssrc-group:FID 2001 2002
+a=ssrc:2001 cname:4TOk42mSjXCkVIa6
+a=ssrc:2001 msid:stream video0
+a=ssrc:2002 cname:4TOk42mSjXCkVIa6
+a=ssrc:2002 msid:stream video0
+m=application 9 UDP/DTLS/SCTP webrtc-datachannel
+c=IN IP4 0.0.0.0
+a=ice-ufrag:ZZy8
+a=ice-pwd:Lm0K9cGdDlZzBnnR0+Ea2SSk
+a=ice-options:trickle
+a=fingerprint:sha-256 7B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08
+a=setup:actpass
+a=mid:2
+a=sctp-port:5000
+a=max-message-size:262144
+";
+
+    /// Firefox offer sending three simulcast layers under a bandwidth cap
+    const FIREFOX_SIMULCAST: &str = "v=0
+o=mozilla...THIS_IS_SDPARTA-99.0 5197960346399035591 0 IN IP4 0.0.0.0
+s=-
+t=0 0
+a=fingerprint:sha-256 AD:6E:3F:29:9B:50:3C:F1:8C:88:8C:06:0B:91:60:6D:D5:0B:A9:10:0E:0B:A8:5A:08:63:23:A9:8B:5B:5C:3C
+a=group:BUNDLE 0
+a=ice-options:trickle
+a=msid-semantic:WMS *
+m=video 9 UDP/TLS/RTP/SAVPF 120 124 121 125
+c=IN IP4 0.0.0.0
+b=AS:2500
+b=TIAS:2500000
+a=sendonly
+a=extmap:3 urn:ietf:params:rtp-hdrext:sdes:mid
+a=extmap:10 urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id
+a=extmap:11 urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id
+a=fmtp:120 max-fs=12288;max-fr=60
+a=fmtp:124 apt=120
+a=fmtp:121 max-fs=12288;max-fr=60
+a=fmtp:125 apt=121
+a=ice-pwd:3bcb0f1a4c6a2a8d2d1e6f5c3b2a1f0e
+a=ice-ufrag:8d3a2b1c
+a=mid:0
+a=msid:{5a5b5c5d-0000-4000-8000-5e5f60616263} {00112233-4455-4677-8899-aabbccddeeff}
+a=rid:h send
+a=rid:m send
+a=rid:l send
+a=rtcp-fb:120 nack
+a=rtcp-fb:120 nack pli
+a=rtcp-fb:120 ccm fir
+a=rtcp-fb:121 nack
+a=rtcp-fb:121 nack pli
+a=rtcp-mux
+a=rtcp-rsize
+a=rtpmap:120 VP8/90000
+a=rtpmap:124 rtx/90000
+a=rtpmap:121 VP9/90000
+a=rtpmap:125 rtx/90000
+a=setup:actpass
+a=simulcast:send h;m;l
+a=ssrc:3171523251 cname:{b5c2a6de-1e5c-4b5f-9d3a-3c1b0a9f8e7d}
+a=ssrc:1405838573 cname:{b5c2a6de-1e5c-4b5f-9d3a-3c1b0a9f8e7d}
+a=ssrc-group:FID 3171523251 1405838573
+";
+
+    fn parse(sdp: &str) -> Sdp {
+        sdp.replace('\n', "\r\n").parse().unwrap()
+    }
+
+    fn video(sdp: &mut Sdp) -> &mut MediaSection {
+        sdp.media_of("video").next().unwrap()
+    }
+
+    #[test]
+    fn test_real_world_sdps_round_trip() {
+        for sdp in [CHROME, FIREFOX_SIMULCAST] {
+            let crlf = sdp.replace('\n', "\r\n");
+            assert_eq!(parse(sdp).to_string(), crlf);
+            // Line feeds alone are accepted too
+            assert_eq!(sdp.parse::<Sdp>().unwrap().to_string(), crlf);
+        }
+        let mut chrome = parse(CHROME);
+        let kinds: Vec<&str> = chrome.media.iter().map(|media| media.kind.as_str()).collect();
+        assert_eq!(kinds, ["audio", "video", "application"]);
+        assert_eq!(chrome.media[2].formats, ["webrtc-datachannel"]);
+        let video = video(&mut chrome);
+        assert_eq!(video.mid(), Some("1"));
+        assert_eq!(video.codec("98"), Some("VP9"));
+        assert_eq!(video.payload_types("h264"), ["102"]);
+        assert_eq!(video.rtx_payload_types("102"), ["103"]);
+
+        assert_eq!("".parse::<Sdp>().unwrap_err().line, 1);
+        assert_eq!("o=- 0 0 IN IP4 0.0.0.0".parse::<Sdp>().unwrap_err().line, 1);
+        assert_eq!("v=0\r\nm=video\r\n".parse::<Sdp>().unwrap_err().line, 2);
+        assert_eq!("v=0\r\nnot a line\r\n".parse::<Sdp>().unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn test_codecs_are_reordered_and_stripped() {
+        let mut sdp = parse(CHROME);
+        sdp.prefer_codec(VideoCodec::H264);
+        assert_eq!(video(&mut sdp).formats, ["102", "103", "96", "97", "98", "99", "45", "46"]);
+        // Audio is left alone
+        assert_eq!(sdp.media[0].formats[0], "111");
+
+        let video = video(&mut sdp);
+        video.prefer_codecs(&["vp9", "VP8"]);
+        assert_eq!(video.formats, ["98", "99", "96", "97", "102", "103", "45", "46"]);
+
+        assert_eq!(video.strip_codecs(&["H264", "AV1"]), 4);
+        assert_eq!(video.formats, ["98", "99", "96", "97"]);
+        let munged = sdp.to_string();
+        assert!(!munged.contains("a=rtpmap:102") && !munged.contains("a=fmtp:103") && !munged.contains("rtcp-fb:45"));
+        assert!(munged.contains("a=rtcp-fb:96 nack pli\r\n"));
+        assert_eq!(munged.parse::<Sdp>().unwrap(), sdp);
+    }
+
+    #[test]
+    fn test_bandwidth_follows_the_quality_level() {
+        let mut sdp = parse(FIREFOX_SIMULCAST);
+        assert_eq!(video(&mut sdp).bandwidth(), Some(2500));
+        sdp.set_quality(QualityLevel::Low);
+        let capped = video(&mut sdp);
+        assert_eq!(capped.bandwidth(), Some(100));
+        let lines: Vec<String> = capped.lines.iter().take(4).map(ToString::to_string).collect();
+        assert_eq!(lines, ["c=IN IP4 0.0.0.0", "b=AS:100", "b=TIAS:100000", "a=sendonly"]);
+
+        // Chrome puts no cap; only video sections get one
+        let mut sdp = parse(CHROME);
+        sdp.set_quality(QualityLevel::VeryHigh);
+        assert_eq!(sdp.media.iter().map(MediaSection::bandwidth).collect::<Vec<_>>(), [None, Some(800), None]);
+        video(&mut sdp).set_bandwidth(None);
+        assert_eq!(sdp.to_string(), CHROME.replace('\n', "\r\n"));
+
+        let levels = [QualityLevel::VeryLow, QualityLevel::Medium, QualityLevel::VeryHigh];
+        assert_eq!(levels.map(QualityLevel::bitrate_kbps), [50, 200, 800]);
+        assert_eq!(serde_json::to_string(&QualityLevel::VeryLow).unwrap(), r#""veryLow""#);
+        assert_eq!(serde_json::from_str::<VideoCodec>(r#""h264""#).unwrap(), VideoCodec::H264);
+    }
+
+    #[test]
+    fn test_simulcast_and_rtx_are_toggled() {
+        let mut sdp = parse(FIREFOX_SIMULCAST);
+        assert_eq!(video(&mut sdp).simulcast(), ["h", "m", "l"]);
+        sdp.set_simulcast(&[]);
+        sdp.set_rtx(false);
+        let video_section = video(&mut sdp);
+        assert!(video_section.simulcast().is_empty() && !video_section.has_attribute("simulcast"));
+        assert_eq!(video_section.formats, ["120", "121"]);
+        let munged = sdp.to_string();
+        assert!(!munged.contains("rtx") && !munged.contains("FID") && !munged.contains("1405838573"));
+        assert!(munged.contains("a=ssrc:3171523251 cname:"));
+
+        // RTX payloads come back after their codec, with free payload types
+        sdp.set_rtx(true);
+        let video_section = video(&mut sdp);
+        assert_eq!(video_section.formats, ["120", "96", "121", "97"]);
+        assert_eq!(video_section.rtx_payload_types("121"), ["97"]);
+        assert!(video_section.attributes("rtpmap").any(|rtpmap| rtpmap == "97 rtx/90000"));
+
+        // The RID extension is declared once simulcast is on
+        sdp.set_simulcast(&["hi", "lo"]);
+        assert_eq!(video(&mut sdp).attributes("simulcast").collect::<Vec<_>>(), ["send hi;lo"]);
+        let mut chrome = parse(CHROME);
+        chrome.set_simulcast(&["f", "h", "q"]);
+        chrome.set_rtx(true);
+        let video_section = video(&mut chrome);
+        assert_eq!(video_section.simulcast(), ["f", "h", "q"]);
+        assert!(video_section.attributes("extmap").any(|extmap| extmap == format!("5 {}", RID_EXTENSION)));
+        // Every codec already had RTX
+        assert_eq!(video_section.formats.len(), 8);
+        assert_eq!(chrome.to_string().parse::<Sdp>().unwrap(), chrome);
+    }
+}
@@ -0,0 +1,314 @@
+// SmaRTC Rust SDK - WebRTC signals, wire-compatible with the JavaScript mesh SDK
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use thiserror::Error;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+/// Version written by this SDK. The JavaScript SDK does not write one, its
+/// signals are read as version 1.
+pub const SIGNAL_VERSION: u32 = 1;
+
+/// Inflated SDPs larger than this are rejected
+pub const MAX_SDP_SIZE: u64 = 1024 * 1024;
+
+/// `SignalMessage` of the JavaScript SDK: `{ type, from, to, data }`, plus a
+/// `version` and the `supports` list that browsers ignore
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignalMessage {
+    #[serde(default = "first_version")]
+    pub version: u32,
+    pub from: String,
+    pub to: String,
+    /// Extensions the sender can read, none for the JavaScript SDK
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub supports: Vec<SignalFeature>,
+    #[serde(flatten)]
+    pub payload: SignalPayload,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// Optional extensions of the signal format, only used towards peers that
+/// listed them in [`SignalMessage::supports`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignalFeature {
+    /// Offers and answers may carry a deflated SDP
+    Deflate,
+    /// Candidates may be sent together in one `ice-candidates` signal
+    CandidateBatches,
+    /// Listed by a newer SDK
+    #[serde(other)]
+    Unknown,
+}
+
+impl SignalFeature {
+    /// What this SDK reads, advertised on every signal it sends
+    pub const SUPPORTED: [SignalFeature; 2] = [SignalFeature::Deflate, SignalFeature::CandidateBatches];
+}
+
+/// The `type` of a signal and its `data`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "kebab-case")]
+pub enum SignalPayload {
+    Offer(SessionDescription),
+    Answer(SessionDescription),
+    /// `RTCIceCandidate.toJSON()`; an empty candidate ends the gathering
+    IceCandidate(RTCIceCandidateInit),
+    /// Candidates gathered together, see [`SignalFeature::CandidateBatches`]
+    IceCandidates(Vec<RTCIceCandidateInit>),
+    /// Forwarded as is by relay peers
+    Relay(serde_json::Value),
+}
+
+/// `RTCSessionDescriptionInit` as sent by browsers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionDescription {
+    #[serde(rename = "type")]
+    pub sdp_type: RTCSdpType,
+    /// The SDP, or its base64 raw deflate stream when `encoding` is set
+    pub sdp: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<SdpEncoding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SdpEncoding {
+    Deflate,
+}
+
+/// Why a received signal was rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SignalError {
+    /// Not JSON, or not a signal: unknown type, missing field, wrong data...
+    #[error("Malformed signal: {0}")]
+    Malformed(String),
+    /// Written by a newer SDK
+    #[error("Unsupported signal version {0} (up to {SIGNAL_VERSION} is supported)")]
+    UnsupportedVersion(u32),
+    /// The description does not match the signal, e.g. an answer sent as an offer
+    #[error("The {signal} signal carries a description of type {description}")]
+    DescriptionMismatch { signal: &'static str, description: RTCSdpType },
+    /// The SDP could not be parsed
+    #[error("Invalid SDP: {0}")]
+    InvalidSdp(String),
+}
+
+impl SignalMessage {
+    pub fn new(from: &str, to: &str, payload: SignalPayload) -> Self {
+        Self {
+            version: SIGNAL_VERSION,
+            from: from.to_string(),
+            to: to.to_string(),
+            supports: SignalFeature::SUPPORTED.to_vec(),
+            payload,
+        }
+    }
+
+    /// Parses and validates a signal received from the hub; deflated SDPs
+    /// are inflated
+    pub fn parse(signal: &str) -> Result<Self, SignalError> {
+        let mut message: Self = serde_json::from_str(signal).map_err(|e| SignalError::Malformed(e.to_string()))?;
+        if message.version == 0 || message.version > SIGNAL_VERSION {
+            return Err(SignalError::UnsupportedVersion(message.version));
+        }
+        if message.from.is_empty() || message.to.is_empty() {
+            return Err(SignalError::Malformed("`from` and `to` must not be empty".into()));
+        }
+        if let SignalPayload::Offer(description) | SignalPayload::Answer(description) = &mut message.payload {
+            description.inflate()?;
+        }
+        match &message.payload {
+            SignalPayload::Offer(description) => description.validate("offer", &[RTCSdpType::Offer])?,
+            SignalPayload::Answer(description) => {
+                description.validate("answer", &[RTCSdpType::Answer, RTCSdpType::Pranswer])?
+            }
+            SignalPayload::IceCandidate(_) | SignalPayload::IceCandidates(_) | SignalPayload::Relay(_) => {}
+        }
+        Ok(message)
+    }
+
+    /// Deflates the SDP of an offer or answer, for peers supporting
+    /// [`SignalFeature::Deflate`]
+    pub fn deflated(mut self) -> Self {
+        if let SignalPayload::Offer(description) | SignalPayload::Answer(description) = &mut self.payload {
+            description.deflate();
+        }
+        self
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("signals are always serializable")
+    }
+
+    /// The signal's `type`, as written on the wire
+    pub fn kind(&self) -> &'static str {
+        match self.payload {
+            SignalPayload::Offer(_) => "offer",
+            SignalPayload::Answer(_) => "answer",
+            SignalPayload::IceCandidate(_) => "ice-candidate",
+            SignalPayload::IceCandidates(_) => "ice-candidates",
+            SignalPayload::Relay(_) => "relay",
+        }
+    }
+}
+
+impl SessionDescription {
+    /// Compresses the SDP, unless that would not make it shorter
+    pub fn deflate(&mut self) {
+        if self.encoding.is_some() {
+            return;
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+        let deflated = encoder.write_all(self.sdp.as_bytes()).and_then(|_| encoder.finish());
+        if let Ok(deflated) = deflated.map(|bytes| BASE64.encode(bytes)) {
+            if deflated.len() < self.sdp.len() {
+                self.sdp = deflated;
+                self.encoding = Some(SdpEncoding::Deflate);
+            }
+        }
+    }
+
+    /// Restores a deflated SDP
+    pub fn inflate(&mut self) -> Result<(), SignalError> {
+        let Some(SdpEncoding::Deflate) = self.encoding else {
+            return Ok(());
+        };
+        let deflated = BASE64.decode(&self.sdp).map_err(|e| SignalError::InvalidSdp(e.to_string()))?;
+        let mut sdp = String::new();
+        DeflateDecoder::new(deflated.as_slice())
+            .take(MAX_SDP_SIZE + 1)
+            .read_to_string(&mut sdp)
+            .map_err(|e| SignalError::InvalidSdp(e.to_string()))?;
+        if sdp.len() as u64 > MAX_SDP_SIZE {
+            return Err(SignalError::InvalidSdp(format!("inflated SDP larger than {} bytes", MAX_SDP_SIZE)));
+        }
+        self.sdp = sdp;
+        self.encoding = None;
+        Ok(())
+    }
+
+    fn validate(&self, signal: &'static str, expected: &[RTCSdpType]) -> Result<(), SignalError> {
+        if !expected.contains(&self.sdp_type) {
+            return Err(SignalError::DescriptionMismatch { signal, description: self.sdp_type });
+        }
+        RTCSessionDescription::from(self.clone()).unmarshal().map_err(|e| SignalError::InvalidSdp(e.to_string()))?;
+        Ok(())
+    }
+}
+
+impl From<RTCSessionDescription> for SessionDescription {
+    fn from(description: RTCSessionDescription) -> Self {
+        Self { sdp_type: description.sdp_type, sdp: description.sdp, encoding: None }
+    }
+}
+
+impl From<SessionDescription> for RTCSessionDescription {
+    fn from(description: SessionDescription) -> Self {
+        let mut rtc = RTCSessionDescription::default();
+        rtc.sdp_type = description.sdp_type;
+        rtc.sdp = description.sdp;
+        rtc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SDP: &str = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n";
+
+    #[test]
+    fn test_javascript_signals_are_understood() {
+        let offer = format!(
+            r#"{{"type":"offer","from":"conn-a","to":"conn-b","data":{{"type":"offer","sdp":{:?}}}}}"#,
+            SDP
+        );
+        let message = SignalMessage::parse(&offer).unwrap();
+        assert_eq!(message.version, 1);
+        let description = SessionDescription { sdp_type: RTCSdpType::Offer, sdp: SDP.to_string(), encoding: None };
+        assert_eq!(message.payload, SignalPayload::Offer(description));
+
+        let candidate = r#"{"type":"ice-candidate","from":"conn-a","to":"conn-b","data":{
+            "candidate":"candidate:1 1 udp 2130706431 192.0.2.1 5000 typ host","sdpMid":"0","sdpMLineIndex":0}}"#;
+        let SignalPayload::IceCandidate(init) = SignalMessage::parse(candidate).unwrap().payload else {
+            panic!("expected a candidate");
+        };
+        assert_eq!((init.sdp_mid.as_deref(), init.sdp_mline_index), (Some("0"), Some(0)));
+
+        // What we send is what browsers expect
+        let encoded: serde_json::Value = serde_json::from_str(&message.encode()).unwrap();
+        assert_eq!(encoded["type"], "offer");
+        assert_eq!(encoded["data"]["type"], "offer");
+        assert_eq!(encoded["data"]["sdp"], SDP);
+        assert_eq!(SignalMessage::parse(&message.encode()), Ok(message));
+    }
+
+    #[test]
+    fn test_extensions_round_trip() {
+        // A real offer compresses well
+        let sdp = format!("{}{}", SDP, "a=candidate:1 1 udp 2130706431 192.0.2.1 5000 typ host\r\n".repeat(20));
+        let description = SessionDescription { sdp_type: RTCSdpType::Offer, sdp: sdp.clone(), encoding: None };
+        let message = SignalMessage::new("a", "b", SignalPayload::Offer(description));
+        let deflated = message.clone().deflated();
+        let encoded: serde_json::Value = serde_json::from_str(&deflated.encode()).unwrap();
+        assert_eq!(encoded["data"]["encoding"], "deflate");
+        assert!(encoded["data"]["sdp"].as_str().unwrap().len() < sdp.len());
+        assert_eq!(encoded["supports"], serde_json::json!(["deflate", "candidate-batches"]));
+        assert_eq!(SignalMessage::parse(&deflated.encode()), Ok(message));
+
+        // Short SDPs are left alone
+        let short = SessionDescription { sdp_type: RTCSdpType::Offer, sdp: SDP.to_string(), encoding: None };
+        let short = SignalMessage::new("a", "b", SignalPayload::Offer(short));
+        assert_eq!(short.clone().deflated(), short);
+
+        let batch = r#"{"type":"ice-candidates","from":"a","to":"b","supports":["deflate","teleport"],"data":[
+            {"candidate":"candidate:1 1 udp 2130706431 192.0.2.1 5000 typ host","sdpMid":"0"},{"candidate":""}]}"#;
+        let message = SignalMessage::parse(batch).unwrap();
+        assert_eq!(message.supports, vec![SignalFeature::Deflate, SignalFeature::Unknown]);
+        let SignalPayload::IceCandidates(candidates) = message.payload else {
+            panic!("expected candidates");
+        };
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].candidate, "");
+
+        let corrupt = r#"{"type":"offer","from":"a","to":"b",
+            "data":{"type":"offer","sdp":"AAAA","encoding":"deflate"}}"#;
+        assert!(matches!(SignalMessage::parse(corrupt), Err(SignalError::InvalidSdp(_))));
+    }
+
+    #[test]
+    fn test_malformed_signals_are_rejected() {
+        let parse = |signal: &str| SignalMessage::parse(signal).unwrap_err();
+
+        assert!(matches!(parse("offer"), SignalError::Malformed(_)));
+        let unknown = parse(r#"{"type":"hello","from":"a","to":"b","data":null}"#);
+        assert!(unknown.to_string().contains("hello"), "{}", unknown);
+        let anonymous = r#"{"type":"offer","to":"b","data":{"type":"offer","sdp":""}}"#;
+        assert!(matches!(parse(anonymous), SignalError::Malformed(_)));
+
+        let offer = |version: u32, sdp_type: &str| {
+            let data = format!(r#"{{"type":"{}","sdp":{:?}}}"#, sdp_type, SDP);
+            format!(r#"{{"version":{},"type":"offer","from":"a","to":"b","data":{}}}"#, version, data)
+        };
+        assert_eq!(parse(&offer(2, "offer")), SignalError::UnsupportedVersion(2));
+        assert_eq!(
+            parse(&offer(1, "answer")),
+            SignalError::DescriptionMismatch { signal: "offer", description: RTCSdpType::Answer }
+        );
+        assert!(matches!(
+            parse(r#"{"type":"answer","from":"a","to":"b","data":{"type":"answer","sdp":"not sdp"}}"#),
+            SignalError::InvalidSdp(_)
+        ));
+    }
+}
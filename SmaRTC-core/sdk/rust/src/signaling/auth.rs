@@ -0,0 +1,49 @@
+// SmaRTC Rust SDK - Access tokens for authenticated hub connections
+
+use super::HubError;
+use futures_util::future::BoxFuture;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Produces the bearer token of a connection attempt, like the SignalR
+/// clients' `accessTokenFactory`. It is called again before every reconnect
+/// so that an expired token can be replaced.
+pub type AccessTokenFactory = Arc<dyn Fn() -> BoxFuture<'static, Result<String, HubError>> + Send + Sync>;
+
+/// Wraps an async closure as an [`AccessTokenFactory`]
+pub fn access_token_factory<F, Fut>(factory: F) -> AccessTokenFactory
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<String, HubError>> + Send + 'static,
+{
+    Arc::new(move || Box::pin(factory()))
+}
+
+/// Uses the JWT obtained by [`crate::SmaRTCClient::login`]; logging in again
+/// on the same client refreshes the token used by the next reconnect
+pub fn smartc_token(client: Arc<Mutex<crate::SmaRTCClient>>) -> AccessTokenFactory {
+    access_token_factory(move || {
+        let client = client.clone();
+        async move {
+            client
+                .lock()
+                .await
+                .token()
+                .map(str::to_string)
+                .ok_or_else(|| "Not logged in to the SmaRTC API".into())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_smartc_token_requires_login() {
+        let client = Arc::new(Mutex::new(crate::SmaRTCClient::new(None)));
+        let factory = smartc_token(client);
+        assert!(factory().await.is_err());
+    }
+}
@@ -0,0 +1,343 @@
+// SmaRTC Rust SDK - Splitting signals larger than the hub accepts, and putting them back together
+
+use super::dialect::SIGNALR_MAX_MESSAGE_SIZE;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Version of the chunk format, see [`Chunk`]
+pub const CHUNK_VERSION: u32 = 1;
+
+/// Room left in each chunk for its own fields
+const CHUNK_OVERHEAD: usize = 256;
+
+static NEXT_MESSAGE: AtomicU64 = AtomicU64::new(0);
+
+/// How signals are split and reassembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkOptions {
+    /// Largest signal sent as is, as encoded in a JSON invocation. The
+    /// default leaves 2 KB of the 32 KB SignalR limit to the rest of the
+    /// invocation; `SessionClient` lowers it for hubs with a smaller limit.
+    pub max_size: usize,
+    /// Incomplete messages are dropped after this long
+    pub timeout: Duration,
+    /// Larger messages are refused by the receiver
+    pub max_message_size: usize,
+    /// Messages of a single sender reassembled at once; chunks of further
+    /// ones are refused until one completes or expires
+    pub max_pending: usize,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self {
+            max_size: max_size_within(SIGNALR_MAX_MESSAGE_SIZE),
+            timeout: Duration::from_secs(30),
+            max_message_size: 16 * 1024 * 1024,
+            max_pending: 16,
+        }
+    }
+}
+
+/// Why chunks were dropped
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ChunkError {
+    /// A chunk disagrees with the previous ones of its message
+    #[error("Inconsistent chunks for message {id}")]
+    Inconsistent { id: String },
+    #[error("Message {id} is too large ({size} bytes so far)")]
+    TooLarge { id: String, size: usize },
+    /// Some chunks did not arrive in time
+    #[error("Message {id} expired with {received} of {count} chunks")]
+    Expired { id: String, received: usize, count: usize },
+    /// The sender already has [`ChunkOptions::max_pending`] messages being
+    /// reassembled
+    #[error("Too many incomplete messages from {sender}, message {id} dropped")]
+    TooManyPending { id: String, sender: String },
+}
+
+/// One part of a signal too large for the hub, sent in its place. Other
+/// SDKs see an unknown JSON signal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Chunk {
+    pub smartc_chunk: u32,
+    /// Unique per sender
+    pub id: String,
+    pub index: usize,
+    pub count: usize,
+    pub data: String,
+}
+
+impl Chunk {
+    pub fn parse(signal: &str) -> Option<Self> {
+        // Cheap check first, most signals are not chunks
+        if !signal.starts_with(r#"{"smartcChunk":"#) {
+            return None;
+        }
+        serde_json::from_str::<Self>(signal).ok().filter(|chunk| chunk.smartc_chunk == CHUNK_VERSION)
+    }
+}
+
+/// Splits `signal` into chunks that fit in `options.max_size`, or returns it
+/// alone if it already does
+pub fn split(signal: &str, options: &ChunkOptions) -> Vec<String> {
+    if wire_len(signal) <= options.max_size {
+        return vec![signal.to_string()];
+    }
+    let budget = budget(options);
+    let mut parts = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (at, c) in signal.char_indices() {
+        let len = char_wire_len(c);
+        if size + len > budget && at > start {
+            parts.push(&signal[start..at]);
+            (start, size) = (at, 0);
+        }
+        size += len;
+    }
+    parts.push(&signal[start..]);
+
+    let id = message_id();
+    let count = parts.len();
+    let chunk = |(index, data): (usize, &str)| Chunk {
+        smartc_chunk: CHUNK_VERSION,
+        id: id.clone(),
+        index,
+        count,
+        data: data.to_string(),
+    };
+    parts.into_iter().enumerate().map(chunk).map(|chunk| serde_json::to_string(&chunk).unwrap_or_default()).collect()
+}
+
+/// Largest signal that fits in a hub message of `limit` bytes: a quarter of
+/// it, at most 2 KB, is left to the rest of the invocation
+pub(crate) fn max_size_within(limit: usize) -> usize {
+    limit - (limit / 4).min(2 * 1024)
+}
+
+/// Room left for the data of each chunk
+fn budget(options: &ChunkOptions) -> usize {
+    options.max_size.saturating_sub(CHUNK_OVERHEAD).max(1)
+}
+
+/// Length of a signal once written as a JSON string that is itself embedded
+/// in a JSON invocation, i.e. escaped twice
+fn wire_len(signal: &str) -> usize {
+    signal.chars().map(char_wire_len).sum()
+}
+
+fn char_wire_len(c: char) -> usize {
+    match c {
+        // `"` then `\"`
+        '"' | '\\' => 4,
+        // `\n` then `\\n`
+        '\n' | '\r' | '\t' | '\u{08}' | '\u{0c}' => 3,
+        // `\u0001` then `\\u0001`
+        c if (c as u32) < 0x20 => 7,
+        c => c.len_utf8(),
+    }
+}
+
+pub(crate) fn message_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    format!("{:x}-{:x}", nanos, NEXT_MESSAGE.fetch_add(1, Ordering::Relaxed))
+}
+
+struct Partial {
+    count: usize,
+    /// By index, only those received so far
+    parts: BTreeMap<usize, String>,
+    size: usize,
+    started: Instant,
+}
+
+/// Puts chunked signals back together, whatever order their chunks arrive
+/// in. Signals that are not chunks pass through.
+pub struct Reassembler {
+    options: ChunkOptions,
+    /// By sender and message id
+    partial: HashMap<(String, String), Partial>,
+}
+
+impl Reassembler {
+    pub fn new(options: ChunkOptions) -> Self {
+        Self { options, partial: HashMap::new() }
+    }
+
+    /// The whole signal once its last chunk arrived, `None` while chunks are
+    /// missing
+    pub fn push(&mut self, sender: &str, signal: String) -> Result<Option<String>, ChunkError> {
+        let Some(chunk) = Chunk::parse(&signal) else {
+            return Ok(Some(signal));
+        };
+        let id = chunk.id.clone();
+        // Every chunk carries at least one byte. Their size depends on the
+        // `max_size` of the sender and on escaping, so it bounds nothing more.
+        let empty = chunk.data.is_empty() && chunk.count > 1;
+        if chunk.count == 0 || chunk.index >= chunk.count || chunk.count > self.options.max_message_size || empty {
+            return Err(ChunkError::Inconsistent { id });
+        }
+        if chunk.count == 1 {
+            return Ok(Some(chunk.data));
+        }
+
+        let key = (sender.to_string(), chunk.id);
+        if !self.partial.contains_key(&key) {
+            let pending = self.partial.keys().filter(|(from, _)| from == sender).count();
+            if pending >= self.options.max_pending {
+                return Err(ChunkError::TooManyPending { id, sender: key.0 });
+            }
+        }
+        let partial = self.partial.entry(key.clone()).or_insert_with(|| Partial {
+            count: chunk.count,
+            parts: BTreeMap::new(),
+            size: 0,
+            started: Instant::now(),
+        });
+        if partial.count != chunk.count {
+            self.partial.remove(&key);
+            return Err(ChunkError::Inconsistent { id });
+        }
+        // Duplicates are ignored
+        if partial.parts.contains_key(&chunk.index) {
+            return Ok(None);
+        }
+        partial.size += chunk.data.len();
+        if partial.size > self.options.max_message_size {
+            let size = partial.size;
+            self.partial.remove(&key);
+            return Err(ChunkError::TooLarge { id, size });
+        }
+        partial.parts.insert(chunk.index, chunk.data);
+        if partial.parts.len() < partial.count {
+            return Ok(None);
+        }
+        let partial = self.partial.remove(&key).expect("the message is being reassembled");
+        Ok(Some(partial.parts.into_values().collect()))
+    }
+
+    /// Drops the messages still incomplete after [`ChunkOptions::timeout`]
+    pub fn expire(&mut self) -> Vec<ChunkError> {
+        let timeout = self.options.timeout;
+        let expired: Vec<(String, String)> = self
+            .partial
+            .iter()
+            .filter(|(_, partial)| partial.started.elapsed() >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| {
+                let partial = self.partial.remove(&key)?;
+                Some(ChunkError::Expired { id: key.1, received: partial.parts.len(), count: partial.count })
+            })
+            .collect()
+    }
+
+    /// Messages being reassembled
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_signals_are_split_and_reassembled() {
+        let options = ChunkOptions { max_size: 1024, ..Default::default() };
+        assert_eq!(split("small", &options), vec!["small"]);
+
+        // Quotes and multi-byte characters count as written on the wire
+        let signal = format!(r#"{{"sdp":"{}","name":"{}"}}"#, "a=x\r\n".repeat(400), "é\"😀".repeat(300));
+        let mut chunks = split(&signal, &options);
+        assert!(chunks.len() > 3);
+        for chunk in &chunks {
+            let invocation = serde_json::to_string(&[chunk]).unwrap();
+            assert!(invocation.len() <= options.max_size, "{} bytes", invocation.len());
+        }
+
+        // Out of order, duplicated, and mixed with other senders and signals
+        chunks.reverse();
+        let mut reassembler = Reassembler::new(options.clone());
+        let other = split(&signal, &options);
+        assert_eq!(reassembler.push("bob", other[0].clone()), Ok(None));
+        assert_eq!(reassembler.push("alice", "plain".to_string()), Ok(Some("plain".to_string())));
+        let last = chunks.pop().unwrap();
+        for chunk in &chunks {
+            assert_eq!(reassembler.push("alice", chunk.clone()), Ok(None));
+        }
+        assert_eq!(reassembler.push("alice", chunks[0].clone()), Ok(None));
+        assert_eq!(reassembler.push("alice", last), Ok(Some(signal)));
+        assert_eq!(reassembler.pending(), 1);
+
+        // Bob never finished his
+        reassembler.options.timeout = Duration::ZERO;
+        let expired = reassembler.expire();
+        assert!(matches!(&expired[..], [ChunkError::Expired { received: 1, .. }]), "{:?}", expired);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_small_and_escaped_chunks_are_reassembled() {
+        // Quotes take four bytes each on the wire, and the sender splits
+        // finer than the receiver would
+        let signal = format!(r#"{{"sdp":"{}"}}"#, "\"".repeat(150_000));
+        let chunks = split(&signal, &ChunkOptions { max_size: 1024, ..Default::default() });
+        let receiver = ChunkOptions::default();
+        assert!(chunks.len() > receiver.max_message_size / (receiver.max_size - CHUNK_OVERHEAD));
+
+        let mut reassembler = Reassembler::new(receiver);
+        let (last, chunks) = chunks.split_last().unwrap();
+        for chunk in chunks {
+            assert_eq!(reassembler.push("a", chunk.clone()), Ok(None));
+        }
+        assert_eq!(reassembler.push("a", last.clone()), Ok(Some(signal)));
+    }
+
+    #[test]
+    fn test_bad_chunks_are_refused() {
+        let options = ChunkOptions { max_size: 300, max_message_size: 1000, ..Default::default() };
+        let mut reassembler = Reassembler::new(options.clone());
+        let chunk = |index: usize, count: usize| {
+            let chunk = Chunk { smartc_chunk: 1, id: "m".into(), index, count, data: "x".repeat(400) };
+            serde_json::to_string(&chunk).unwrap()
+        };
+        assert!(matches!(reassembler.push("a", chunk(2, 2)), Err(ChunkError::Inconsistent { .. })));
+        assert_eq!(reassembler.push("a", chunk(0, 3)), Ok(None));
+        assert!(matches!(reassembler.push("a", chunk(1, 4)), Err(ChunkError::Inconsistent { .. })));
+        assert_eq!(reassembler.pending(), 0);
+
+        assert_eq!(reassembler.push("a", chunk(0, 3)), Ok(None));
+        assert_eq!(reassembler.push("a", chunk(1, 3)), Ok(None));
+        assert_eq!(reassembler.push("a", chunk(2, 3)), Err(ChunkError::TooLarge { id: "m".into(), size: 1200 }));
+
+        // A tiny chunk cannot announce more chunks than a message has bytes
+        assert!(matches!(reassembler.push("a", chunk(0, 1_000_000)), Err(ChunkError::Inconsistent { .. })));
+        let empty = Chunk { smartc_chunk: 1, id: "e".into(), index: 0, count: 2, data: String::new() };
+        let empty = serde_json::to_string(&empty).unwrap();
+        assert!(matches!(reassembler.push("a", empty), Err(ChunkError::Inconsistent { .. })));
+
+        // Nor can a sender keep an unbounded number of messages open
+        let options = ChunkOptions { max_pending: 2, ..Default::default() };
+        let mut reassembler = Reassembler::new(options);
+        let first = |id: &str| {
+            let chunk = Chunk { smartc_chunk: 1, id: id.into(), index: 0, count: 2, data: "x".into() };
+            serde_json::to_string(&chunk).unwrap()
+        };
+        assert_eq!(reassembler.push("a", first("m1")), Ok(None));
+        assert_eq!(reassembler.push("a", first("m2")), Ok(None));
+        let refused = reassembler.push("a", first("m3"));
+        assert_eq!(refused, Err(ChunkError::TooManyPending { id: "m3".into(), sender: "a".into() }));
+        assert_eq!(reassembler.push("b", first("m3")), Ok(None));
+
+        // Newer chunk formats are left to the application
+        let newer = r#"{"smartcChunk":2,"id":"m","index":0,"count":2,"data":""}"#;
+        assert_eq!(reassembler.push("a", newer.to_string()), Ok(Some(newer.to_string())));
+    }
+}
@@ -0,0 +1,459 @@
+// SmaRTC Rust SDK - SignalR hub connection shared by the hub clients
+
+use super::auth::AccessTokenFactory;
+use super::chunking::ChunkOptions;
+use super::outbound::{OutboundConfig, OutboundMetrics, OutboundQueue, SendError};
+use super::protocol::{HubMessage, HubProtocol, MessageDecoder};
+use super::HubError;
+use futures_util::{SinkExt, StreamExt};
+use rmpv::Value;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// Same limit as the SignalR JS client
+const MAX_NEGOTIATE_REDIRECTS: usize = 100;
+
+/// How long `close` waits for the server to answer the close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Completion = Result<Option<Value>, String>;
+type Outcome = Option<Result<(), HubError>>;
+type PendingInvocations = Arc<Mutex<HashMap<String, oneshot::Sender<Completion>>>>;
+
+/// A server-to-client method call
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub target: String,
+    pub arguments: Vec<Value>,
+}
+
+/// How to open a [`HubConnection`]
+#[derive(Clone, Default)]
+pub struct ConnectOptions {
+    pub protocol: HubProtocol,
+    /// Requested again for every connection attempt
+    pub access_token_factory: Option<AccessTokenFactory>,
+    pub outbound: OutboundConfig,
+    /// How signals larger than the hub accepts are split
+    pub chunking: ChunkOptions,
+}
+
+/// An open SignalR connection: performs the handshake, answers pings and
+/// matches completions with pending invocations.
+pub struct HubConnection {
+    protocol: HubProtocol,
+    outbound: Arc<OutboundQueue>,
+    tasks: [AbortHandle; 3],
+    finished: watch::Receiver<Outcome>,
+    pending: PendingInvocations,
+    next_invocation_id: AtomicU64,
+    /// Id the server gave the connection during negotiate
+    connection_id: Option<String>,
+}
+
+impl HubConnection {
+    /// Connects to the hub; server invocations are delivered on the returned
+    /// receiver.
+    pub async fn connect(
+        hub_url: &str,
+        options: &ConnectOptions,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<Invocation>), HubError> {
+        let protocol = options.protocol;
+        let access_token = match &options.access_token_factory {
+            Some(factory) => Some(factory().await?),
+            None => None,
+        };
+        let (url, connection_id) = negotiate(hub_url, access_token).await?;
+        let (ws_stream, _) = connect_async(url).await?;
+        let (mut write, mut read) = ws_stream.split();
+
+        // Send handshake
+        write.send(Message::Text(protocol.handshake_request())).await?;
+
+        // Wait for handshake response; the server may send it in a binary
+        // frame, followed by the first messages
+        let mut decoder = MessageDecoder::new(protocol);
+        let handshake = loop {
+            let data = match read.next().await {
+                Some(Ok(Message::Text(text))) => text.into_bytes(),
+                Some(Ok(Message::Binary(data))) => data,
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err(HubError::Handshake("Connection closed during the handshake".into()));
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+            };
+            match decoder.handshake(&data) {
+                Ok(Some(response)) => break response,
+                Ok(None) => continue,
+                Err(e) => return Err(HubError::Handshake(format!("Invalid response: {}", e))),
+            }
+        };
+        if let Some(error) = handshake.error {
+            return Err(HubError::Handshake(error));
+        }
+
+        // Outgoing frames are queued for the writer task
+        let outbound = Arc::new(OutboundQueue::new(options.outbound));
+        let (invocations_tx, invocations_rx) = mpsc::unbounded_channel();
+        let pending: PendingInvocations = Arc::new(Mutex::new(HashMap::new()));
+
+        // Spawn task to handle incoming messages
+        let write = Arc::new(Mutex::new(write));
+        let write_clone = write.clone();
+        let pending_clone = pending.clone();
+        let outbound_clone = outbound.clone();
+
+        let reader = tokio::spawn(async move {
+            // Starts with whatever followed the handshake response
+            let mut data = Vec::new();
+            let outcome = 'read: loop {
+                let messages = match decoder.decode(&data) {
+                    Ok(messages) => messages,
+                    Err(e) => break Err(HubError::Protocol(e.to_string())),
+                };
+                for message in messages {
+                    match message {
+                        HubMessage::Invocation { target, arguments, .. } => {
+                            let _ = invocations_tx.send(Invocation { target, arguments });
+                        }
+                        HubMessage::Completion { invocation_id, result, error } => {
+                            if let Some(tx) = pending_clone.lock().await.remove(&invocation_id) {
+                                let _ = tx.send(match error {
+                                    Some(error) => Err(error),
+                                    None => Ok(result),
+                                });
+                            }
+                        }
+                        HubMessage::Ping => {
+                            // Ping - respond with pong
+                            if let Ok(pong) = protocol.write_message(&HubMessage::Ping) {
+                                let mut w = write_clone.lock().await;
+                                let _ = w.send(frame(protocol, pong)).await;
+                            }
+                        }
+                        HubMessage::Close { error: Some(error), .. } => break 'read Err(HubError::ServerClosed(error)),
+                        HubMessage::Close { error: None, .. } => break 'read Ok(()),
+                        _ => {}
+                    }
+                }
+                data = match read.next().await {
+                    Some(Ok(Message::Text(text))) => text.into_bytes(),
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(_))) | None => break Ok(()),
+                    Some(Err(e)) => break Err(HubError::Transport(e.to_string())),
+                    Some(Ok(_)) => Vec::new(),
+                };
+            };
+
+            // Fail every invocation still waiting for a completion
+            pending_clone.lock().await.clear();
+            outbound_clone.close();
+            outcome
+        });
+
+        // Spawn task to handle outgoing messages; it stops once the queue is
+        // closed and flushed, and then closes the socket
+        let outbound_clone = outbound.clone();
+        let writer = tokio::spawn(async move {
+            while let Some(item) = outbound_clone.next().await {
+                let frame = item.frame.clone();
+                let result = write.lock().await.send(frame).await;
+                match result {
+                    Ok(()) => outbound_clone.complete(item, Ok(())),
+                    Err(e) => {
+                        // The socket is unusable: fail the rest of the queue too
+                        outbound_clone.close();
+                        outbound_clone.complete(item, Err(SendError::Transport(e.to_string())));
+                    }
+                }
+            }
+            let _ = write.lock().await.close().await;
+        });
+
+        let (finished_tx, finished) = watch::channel(None);
+        let reader_task = reader.abort_handle();
+        let writer_task = writer.abort_handle();
+        let monitor = tokio::spawn(supervise(reader, writer, outbound.clone(), finished_tx));
+
+        let connection = Self {
+            protocol,
+            outbound,
+            tasks: [reader_task, writer_task, monitor.abort_handle()],
+            finished,
+            pending,
+            next_invocation_id: AtomicU64::new(1),
+            connection_id,
+        };
+        Ok((connection, invocations_rx))
+    }
+
+    pub fn protocol(&self) -> HubProtocol {
+        self.protocol
+    }
+
+    /// The hub's `Context.ConnectionId`, unless negotiation was skipped
+    pub fn connection_id(&self) -> Option<&str> {
+        self.connection_id.as_deref()
+    }
+
+    /// Invokes a hub method without waiting for its completion
+    pub async fn send(&self, target: &str, arguments: Vec<Value>) -> Result<(), HubError> {
+        self.write(HubMessage::Invocation {
+            invocation_id: None,
+            target: target.to_string(),
+            arguments,
+        })
+        .await
+    }
+
+    /// Invokes a hub method and waits for its result (`Nil` for void methods)
+    pub async fn invoke(&self, target: &str, arguments: Vec<Value>) -> Result<Value, HubError> {
+        let invocation_id = self.next_invocation_id.fetch_add(1, Ordering::Relaxed).to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(invocation_id.clone(), tx);
+
+        let message = HubMessage::Invocation {
+            invocation_id: Some(invocation_id.clone()),
+            target: target.to_string(),
+            arguments,
+        };
+        if let Err(e) = self.write(message).await {
+            self.pending.lock().await.remove(&invocation_id);
+            return Err(e);
+        }
+
+        match rx.await {
+            Ok(Ok(result)) => Ok(result.unwrap_or(Value::Nil)),
+            Ok(Err(error)) => Err(HubError::Invocation { target: target.to_string(), error }),
+            Err(_) => Err(HubError::ConnectionClosed),
+        }
+    }
+
+    /// Queue depth and delivery counters of the outbound pipeline
+    pub fn outbound_metrics(&self) -> OutboundMetrics {
+        self.outbound.metrics()
+    }
+
+    /// Stops accepting messages, waits until the queued ones are written,
+    /// sends the close frame and waits for the background tasks to finish
+    pub async fn close(&self) -> Result<(), HubError> {
+        self.outbound.close();
+        self.closed().await
+    }
+
+    /// Waits until the background tasks have stopped; fails with
+    /// [`HubError::TaskFailed`] if one of them panicked, or with the error that
+    /// ended the connection
+    pub async fn closed(&self) -> Result<(), HubError> {
+        let mut finished = self.finished.clone();
+        let outcome = match finished.wait_for(Option::is_some).await {
+            Ok(outcome) => outcome.clone().unwrap_or(Ok(())),
+            // The monitor was aborted along with the tasks
+            Err(_) => Ok(()),
+        };
+        outcome
+    }
+
+    /// Stops the background tasks immediately, without flushing
+    pub fn abort(&self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+
+    /// Resolves once the frame is written to the socket, or with the reason
+    /// it was not
+    async fn write(&self, message: HubMessage) -> Result<(), HubError> {
+        let data = self.protocol.write_message(&message)?;
+        self.outbound.send(frame(self.protocol, data)).await?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NegotiateResponse {
+    connection_id: Option<String>,
+    connection_token: Option<String>,
+    #[serde(default)]
+    negotiate_version: u32,
+    #[serde(default)]
+    available_transports: Vec<AvailableTransport>,
+    /// Redirect to another endpoint, e.g. Azure SignalR Service
+    url: Option<String>,
+    access_token: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AvailableTransport {
+    transport: String,
+}
+
+/// Runs the negotiate request, following redirects, and returns the
+/// WebSocket URL to connect to along with the connection id.
+///
+/// The token is sent as a bearer token to negotiate and as the `access_token`
+/// query parameter of the WebSocket request, which is where ASP.NET Core
+/// looks for it on WebSocket upgrades.
+async fn negotiate(
+    hub_url: &str,
+    mut access_token: Option<String>,
+) -> Result<(url::Url, Option<String>), HubError> {
+    let http = reqwest::Client::new();
+    let mut hub_url = url::Url::parse(hub_url)?;
+
+    for _ in 0..MAX_NEGOTIATE_REDIRECTS {
+        let mut negotiate_url = hub_url.clone();
+        negotiate_url
+            .path_segments_mut()
+            .map_err(|_| HubError::InvalidUrl(hub_url.to_string()))?
+            .pop_if_empty()
+            .push("negotiate");
+        negotiate_url.query_pairs_mut().append_pair("negotiateVersion", "1");
+
+        let mut request = http.post(negotiate_url);
+        if let Some(token) = &access_token {
+            request = request.bearer_auth(token);
+        }
+        let response: NegotiateResponse = request.send().await?.error_for_status()?.json().await?;
+
+        if let Some(error) = response.error {
+            return Err(HubError::Negotiate(error));
+        }
+        if let Some(url) = response.url {
+            hub_url = url::Url::parse(&url)?;
+            access_token = response.access_token.or(access_token);
+            continue;
+        }
+        if !response.available_transports.is_empty()
+            && !response.available_transports.iter().any(|t| t.transport == "WebSockets")
+        {
+            return Err(HubError::Negotiate("The hub does not accept WebSocket connections".into()));
+        }
+
+        // Version 1 identifies the connection by its token, version 0 by its id
+        let id = if response.negotiate_version >= 1 {
+            response.connection_token
+        } else {
+            response.connection_id.clone()
+        };
+
+        // Convert HTTP URL to WebSocket URL
+        let ws_url = hub_url
+            .as_str()
+            .replace("http://", "ws://")
+            .replace("https://", "wss://");
+        let mut ws_url = url::Url::parse(&ws_url)?;
+        {
+            let mut query = ws_url.query_pairs_mut();
+            if let Some(id) = &id {
+                query.append_pair("id", id);
+            }
+            if let Some(token) = &access_token {
+                query.append_pair("access_token", token);
+            }
+        }
+        return Ok((ws_url, response.connection_id));
+    }
+
+    Err(HubError::Negotiate("Redirection limit exceeded".into()))
+}
+
+impl Drop for HubConnection {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+/// Waits for the reader and writer tasks and reports how they ended. When the
+/// socket closes the writer still flushes its queue; when a task panics the
+/// other one is stopped.
+async fn supervise(
+    mut reader: JoinHandle<Result<(), HubError>>,
+    mut writer: JoinHandle<()>,
+    outbound: Arc<OutboundQueue>,
+    finished: watch::Sender<Outcome>,
+) {
+    let failed = |e: tokio::task::JoinError| HubError::TaskFailed(e.to_string());
+    let outcome = tokio::select! {
+        result = &mut reader => match result {
+            Ok(outcome) => {
+                outbound.close();
+                writer.await.map_err(failed).and(outcome)
+            }
+            Err(e) => {
+                writer.abort();
+                Err(failed(e))
+            }
+        },
+        // The writer only stops by itself once the queue is closed
+        result = &mut writer => match result {
+            Ok(()) => match tokio::time::timeout(CLOSE_TIMEOUT, &mut reader).await {
+                Ok(result) => result.map_err(failed).and_then(|outcome| outcome),
+                Err(_) => {
+                    reader.abort();
+                    Ok(())
+                }
+            },
+            Err(e) => {
+                reader.abort();
+                Err(failed(e))
+            }
+        },
+    };
+    let _ = finished.send(Some(outcome));
+}
+
+/// Wraps encoded hub messages in the WebSocket frame type the protocol expects
+fn frame(protocol: HubProtocol, data: Vec<u8>) -> Message {
+    if protocol.is_binary() {
+        Message::Binary(data)
+    } else {
+        Message::Text(String::from_utf8(data).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signaling::testing::MockHub;
+
+    #[tokio::test]
+    async fn test_send_reports_delivery_and_closed_connections() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let (connection, _invocations) = HubConnection::connect(&server.url, &ConnectOptions::default()).await.unwrap();
+
+        for i in 0..3 {
+            connection.send("Echo", vec![Value::from(i)]).await.unwrap();
+        }
+        connection.close().await.unwrap();
+        for i in 0..3 {
+            let HubMessage::Invocation { arguments, .. } = server.recv().await else {
+                panic!("expected an invocation");
+            };
+            assert_eq!(arguments, vec![Value::from(i)]);
+        }
+
+        let error = connection.send("Echo", Vec::new()).await.unwrap_err();
+        assert_eq!(error, HubError::Send(SendError::Closed));
+        assert_eq!(connection.outbound_metrics().sent, 3);
+    }
+
+    #[tokio::test]
+    async fn test_transport_errors_are_not_clean_closes() {
+        let server = MockHub::start(HubProtocol::Json).await;
+        let (connection, _invocations) = HubConnection::connect(&server.url, &ConnectOptions::default()).await.unwrap();
+
+        // The socket goes away without a close frame
+        server.drop_connection();
+        assert!(matches!(connection.closed().await, Err(HubError::Transport(_))));
+    }
+}
@@ -0,0 +1,485 @@
+// SmaRTC Rust SDK - Hub dialects of the SmaRTC signaling servers
+
+use super::connection::{HubConnection, Invocation};
+use super::hubs::{SignalHub, SignalHubProxy, SimpleHub, SimpleHubProxy, ZeroCostHub, ZeroCostHubProxy};
+use super::protocol::{self, Compression, HubProtocol};
+use super::registry;
+use super::zerocost::{PeerInfo, HEARTBEAT_INTERVAL};
+use super::HubError;
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde_bytes::Bytes;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `service` reported by `Program.Optimized.cs`
+pub const ZEROCOST_SERVICE: &str = "SmaRTC-ZeroCost";
+/// Major version of the ZeroCost hub this SDK speaks
+pub const ZEROCOST_MAJOR_VERSION: &str = "2";
+/// Banner of `Program.Simple.cs`
+pub const SIMPLE_BANNER: &str = "SmaRTC Demo Server Running!";
+/// Banner of `Program.cs`
+pub const SIGNALHUB_BANNER: &str = "Hello World!";
+/// Default `MaximumReceiveMessageSize` of SignalR hubs
+pub const SIGNALR_MAX_MESSAGE_SIZE: usize = 32 * 1024;
+/// `MaximumReceiveMessageSize` of `Program.Optimized.cs`
+pub const ZEROCOST_MAX_MESSAGE_SIZE: usize = 1024;
+
+/// The hub implementations shipped with the signal server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialectKind {
+    /// `SignalHub`: JSON, peers known by username, signals relayed to the session
+    SignalHub,
+    /// `ZeroCostWebRtcHub`: MessagePack with LZ4, binary signals between connection ids
+    ZeroCost,
+    /// `SimpleWebRtcHub`: JSON, signals between connection ids
+    Simple,
+}
+
+impl DialectKind {
+    /// Recognizes the server from its `/` endpoint
+    pub fn from_server(info: &ServerInfo) -> Option<Self> {
+        match info.service.as_str() {
+            ZEROCOST_SERVICE => {
+                let major = info.version.as_deref().map(|v| v.split('.').next().unwrap_or_default());
+                (major.is_none() || major == Some(ZEROCOST_MAJOR_VERSION)).then_some(DialectKind::ZeroCost)
+            }
+            SIMPLE_BANNER => Some(DialectKind::Simple),
+            SIGNALHUB_BANNER => Some(DialectKind::SignalHub),
+            _ => None,
+        }
+    }
+
+    pub fn dialect(self) -> Arc<dyn HubDialect> {
+        match self {
+            DialectKind::SignalHub => Arc::new(SignalHubDialect),
+            DialectKind::ZeroCost => Arc::new(ZeroCostDialect),
+            DialectKind::Simple => Arc::new(SimpleDialect),
+        }
+    }
+}
+
+impl fmt::Display for DialectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialectKind::SignalHub => write!(f, "SignalHub"),
+            DialectKind::ZeroCost => write!(f, "ZeroCostWebRtcHub"),
+            DialectKind::Simple => write!(f, "SimpleWebRtcHub"),
+        }
+    }
+}
+
+/// What the server answers on `/`: a JSON object for the ZeroCost profile,
+/// a plain-text banner for the others
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ServerInfo {
+    #[serde(default)]
+    pub service: String,
+    pub version: Option<String>,
+}
+
+impl ServerInfo {
+    pub fn parse(body: &str) -> Self {
+        serde_json::from_str(body).unwrap_or_else(|_| Self {
+            service: body.trim().trim_matches('"').to_string(),
+            version: None,
+        })
+    }
+}
+
+/// Reads the `/` endpoint of the server hosting `hub_url`
+pub async fn server_info(hub_url: &str) -> Result<ServerInfo, HubError> {
+    let url = url::Url::parse(hub_url)?.join("/")?;
+    let body = reqwest::get(url).await?.error_for_status()?.text().await?;
+    Ok(ServerInfo::parse(&body))
+}
+
+/// Picks the dialect of the server hosting `hub_url`
+pub async fn detect(hub_url: &str) -> Result<Arc<dyn HubDialect>, HubError> {
+    let info = server_info(hub_url).await?;
+    match DialectKind::from_server(&info) {
+        Some(kind) => Ok(kind.dialect()),
+        None => Err(HubError::Unsupported(format!(
+            "unknown hub server `{}` (version {})",
+            info.service,
+            info.version.as_deref().unwrap_or("unknown")
+        ))),
+    }
+}
+
+/// A peer as identified by the hub: the connection id, or the username on
+/// `SignalHub` which has no other identity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub id: String,
+    pub username: Option<String>,
+}
+
+impl Peer {
+    fn user(username: String) -> Self {
+        Self { id: username.clone(), username: Some(username) }
+    }
+
+    fn connection(id: String) -> Self {
+        Self { id, username: None }
+    }
+}
+
+impl From<PeerInfo> for Peer {
+    fn from(info: PeerInfo) -> Self {
+        Self { id: info.connection_id, username: Some(info.username) }
+    }
+}
+
+/// Session activity, whatever the hub
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    Joined(Peer),
+    Left(Peer),
+    /// `relayed` when forwarded by a ZeroCost relay node
+    Signal { from: Peer, signal: String, relayed: bool },
+}
+
+/// Recipients of a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalTarget<'a> {
+    /// Every other member of the session
+    Session,
+    /// A single peer, by [`Peer::id`]
+    Peer(&'a str),
+}
+
+/// How to join a session, exchange signals and follow presence on one of the
+/// SmaRTC hubs. The hubs disagree on method names, argument lists and
+/// protocols; a dialect maps them to [`PeerEvent`] and [`SignalTarget`].
+pub trait HubDialect: Send + Sync {
+    fn kind(&self) -> DialectKind;
+
+    /// Hub protocol the server is configured with
+    fn protocol(&self) -> HubProtocol;
+
+    fn join<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>>;
+
+    fn leave<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>>;
+
+    fn signal<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+        target: SignalTarget<'a>,
+        signal: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>>;
+
+    /// Translates a server invocation; `None` for targets of no interest
+    fn decode(&self, invocation: &Invocation) -> Option<PeerEvent>;
+
+    /// Id other members know this client by, i.e. the [`Peer::id`] they see
+    fn local_peer_id(&self, connection_id: Option<&str>, _username: &str) -> Option<String> {
+        connection_id.map(str::to_string)
+    }
+
+    /// Largest message the hub accepts from a client, in bytes; signals are
+    /// chunked to fit
+    fn max_message_size(&self) -> usize {
+        SIGNALR_MAX_MESSAGE_SIZE
+    }
+
+    /// Keep-alive the hub expects on top of SignalR pings, if any
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn heartbeat<'a>(&'a self, _hub: &'a Arc<HubConnection>) -> BoxFuture<'a, Result<(), HubError>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// `SignalHub` cannot address a single peer: every signal goes to the
+/// session and recipients filter on their side
+pub struct SignalHubDialect;
+
+impl HubDialect for SignalHubDialect {
+    fn kind(&self) -> DialectKind {
+        DialectKind::SignalHub
+    }
+
+    fn protocol(&self) -> HubProtocol {
+        HubProtocol::Json
+    }
+
+    fn join<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = SignalHubProxy::new(hub.clone());
+        Box::pin(async move { hub.join_session(session_id, username).await })
+    }
+
+    fn leave<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = SignalHubProxy::new(hub.clone());
+        Box::pin(async move { hub.leave_session(session_id, username).await })
+    }
+
+    fn signal<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+        _target: SignalTarget<'a>,
+        signal: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = SignalHubProxy::new(hub.clone());
+        Box::pin(async move { hub.send_signal_to_session(session_id, signal, username).await })
+    }
+
+    fn decode(&self, invocation: &Invocation) -> Option<PeerEvent> {
+        let args = &invocation.arguments;
+        match invocation.target.to_lowercase().as_str() {
+            "newuserarrived" => {
+                let (user,): (String,) = registry::from_arguments(args).ok()?;
+                Some(PeerEvent::Joined(Peer::user(user)))
+            }
+            "userleft" => {
+                let (user,): (String,) = registry::from_arguments(args).ok()?;
+                Some(PeerEvent::Left(Peer::user(user)))
+            }
+            "sendsignal" => {
+                let (signal, user): (String, String) = registry::from_arguments(args).ok()?;
+                Some(PeerEvent::Signal { from: Peer::user(user), signal, relayed: false })
+            }
+            _ => None,
+        }
+    }
+
+    fn local_peer_id(&self, _connection_id: Option<&str>, username: &str) -> Option<String> {
+        Some(username.to_string())
+    }
+}
+
+/// Signals travel as UTF-8 bytes in the hub's `byte[]` arguments
+pub struct ZeroCostDialect;
+
+impl HubDialect for ZeroCostDialect {
+    fn kind(&self) -> DialectKind {
+        DialectKind::ZeroCost
+    }
+
+    fn protocol(&self) -> HubProtocol {
+        HubProtocol::MessagePack(Compression::Lz4BlockArray)
+    }
+
+    fn join<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = ZeroCostHubProxy::new(hub.clone());
+        Box::pin(async move { hub.join_session(session_id, username).await })
+    }
+
+    fn leave<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        _username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = ZeroCostHubProxy::new(hub.clone());
+        Box::pin(async move { hub.leave_session(session_id).await })
+    }
+
+    fn signal<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        _username: &'a str,
+        target: SignalTarget<'a>,
+        signal: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = ZeroCostHubProxy::new(hub.clone());
+        let data = Bytes::new(signal.as_bytes());
+        Box::pin(async move {
+            match target {
+                SignalTarget::Session => hub.broadcast_signal(session_id, data).await,
+                SignalTarget::Peer(peer) => hub.send_signal(peer, data).await,
+            }
+        })
+    }
+
+    fn decode(&self, invocation: &Invocation) -> Option<PeerEvent> {
+        let args = &invocation.arguments;
+        match invocation.target.to_lowercase().as_str() {
+            "userjoined" => Some(PeerEvent::Joined(PeerInfo::decode(args.first()?)?.into())),
+            "userleft" => Some(PeerEvent::Left(PeerInfo::decode(args.first()?)?.into())),
+            target @ ("receivesignal" | "relaysignal") => {
+                let from = args.first()?.as_str()?.to_string();
+                let signal = String::from_utf8(protocol::as_bytes(args.get(1)?)?).ok()?;
+                Some(PeerEvent::Signal { from: Peer::connection(from), signal, relayed: target == "relaysignal" })
+            }
+            _ => None,
+        }
+    }
+
+    fn max_message_size(&self) -> usize {
+        ZEROCOST_MAX_MESSAGE_SIZE
+    }
+
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        Some(HEARTBEAT_INTERVAL)
+    }
+
+    fn heartbeat<'a>(&'a self, hub: &'a Arc<HubConnection>) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = ZeroCostHubProxy::new(hub.clone());
+        Box::pin(async move { hub.heartbeat().await })
+    }
+}
+
+/// `SimpleWebRtcHub` only sends to single peers; on join it announces every
+/// member already in the session to the newcomer
+pub struct SimpleDialect;
+
+impl HubDialect for SimpleDialect {
+    fn kind(&self) -> DialectKind {
+        DialectKind::Simple
+    }
+
+    fn protocol(&self) -> HubProtocol {
+        HubProtocol::Json
+    }
+
+    fn join<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = SimpleHubProxy::new(hub.clone());
+        Box::pin(async move { hub.join_session(session_id, username).await })
+    }
+
+    fn leave<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        session_id: &'a str,
+        _username: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = SimpleHubProxy::new(hub.clone());
+        Box::pin(async move { hub.leave_session(session_id).await })
+    }
+
+    fn signal<'a>(
+        &'a self,
+        hub: &'a Arc<HubConnection>,
+        _session_id: &'a str,
+        _username: &'a str,
+        target: SignalTarget<'a>,
+        signal: &'a str,
+    ) -> BoxFuture<'a, Result<(), HubError>> {
+        let hub = SimpleHubProxy::new(hub.clone());
+        Box::pin(async move {
+            match target {
+                SignalTarget::Peer(peer) => hub.send_signal(peer, signal).await,
+                SignalTarget::Session => {
+                    Err(HubError::Unsupported("SimpleWebRtcHub only sends signals to single peers".into()))
+                }
+            }
+        })
+    }
+
+    fn decode(&self, invocation: &Invocation) -> Option<PeerEvent> {
+        let args = &invocation.arguments;
+        match invocation.target.to_lowercase().as_str() {
+            "userjoined" => {
+                let (peer,): (PeerInfo,) = registry::from_arguments(args).ok()?;
+                Some(PeerEvent::Joined(peer.into()))
+            }
+            "userleft" => {
+                let (peer,): (PeerInfo,) = registry::from_arguments(args).ok()?;
+                Some(PeerEvent::Left(peer.into()))
+            }
+            "receivesignal" => {
+                let (from, signal): (String, String) = registry::from_arguments(args).ok()?;
+                Some(PeerEvent::Signal { from: Peer::connection(from), signal, relayed: false })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmpv::Value;
+
+    fn invocation(target: &str, arguments: Vec<Value>) -> Invocation {
+        Invocation { target: target.to_string(), arguments }
+    }
+
+    #[test]
+    fn test_server_info_selects_dialect() {
+        let kind = |body: &str| DialectKind::from_server(&ServerInfo::parse(body));
+        assert_eq!(
+            kind(r#"{"service":"SmaRTC-ZeroCost","version":"2.0.0","status":"operational"}"#),
+            Some(DialectKind::ZeroCost)
+        );
+        assert_eq!(kind("SmaRTC Demo Server Running!"), Some(DialectKind::Simple));
+        assert_eq!(kind("Hello World!"), Some(DialectKind::SignalHub));
+
+        // A future major version may change the wire format
+        assert_eq!(kind(r#"{"service":"SmaRTC-ZeroCost","version":"3.0.0"}"#), None);
+        assert_eq!(kind("<html></html>"), None);
+    }
+
+    #[test]
+    fn test_dialects_decode_presence() {
+        let mut dto = Vec::new();
+        let fields = Value::Array(vec![Value::from("alice"), Value::from("conn-a")]);
+        rmpv::encode::write_value(&mut dto, &fields).unwrap();
+        let alice = Peer { id: "conn-a".to_string(), username: Some("alice".to_string()) };
+
+        let joined = invocation("UserJoined", vec![Value::Binary(dto)]);
+        assert_eq!(ZeroCostDialect.decode(&joined), Some(PeerEvent::Joined(alice.clone())));
+
+        let object = Value::Map(vec![
+            (Value::from("username"), Value::from("alice")),
+            (Value::from("connectionId"), Value::from("conn-a")),
+        ]);
+        let left = invocation("UserLeft", vec![object]);
+        assert_eq!(SimpleDialect.decode(&left), Some(PeerEvent::Left(alice)));
+
+        let arrived = invocation("NewUserArrived", vec![Value::from("bob")]);
+        assert_eq!(SignalHubDialect.decode(&arrived), Some(PeerEvent::Joined(Peer::user("bob".to_string()))));
+    }
+
+    #[test]
+    fn test_zerocost_signals_are_utf8_bytes() {
+        let relayed = invocation("RelaySignal", vec![Value::from("conn-b"), Value::Binary(b"{}".to_vec())]);
+        assert_eq!(
+            ZeroCostDialect.decode(&relayed),
+            Some(PeerEvent::Signal {
+                from: Peer::connection("conn-b".to_string()),
+                signal: "{}".to_string(),
+                relayed: true,
+            })
+        );
+    }
+}
@@ -0,0 +1,108 @@
+// SmaRTC Rust SDK - Errors reported by the hub clients
+
+use super::chunking::ChunkError;
+use super::outbound::SendError;
+use super::protocol::ProtocolError;
+use thiserror::Error;
+
+/// Why a hub operation failed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HubError {
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    /// The negotiate request failed or did not offer a WebSocket transport
+    #[error("Negotiate failed: {0}")]
+    Negotiate(String),
+    /// The hub rejected the handshake, or answered with something else
+    #[error("Handshake failed: {0}")]
+    Handshake(String),
+    /// A frame could not be decoded or a message could not be encoded
+    #[error("Hub protocol error: {0}")]
+    Protocol(String),
+    /// The WebSocket failed
+    #[error("WebSocket error: {0}")]
+    Transport(String),
+    /// An HTTP request failed, e.g. negotiate or server detection
+    #[error("HTTP request failed: {0}")]
+    Http(String),
+    /// A message did not reach the socket
+    #[error(transparent)]
+    Send(#[from] SendError),
+    /// The hub method threw
+    #[error("{target} failed: {error}")]
+    Invocation { target: String, error: String },
+    /// Arguments or results that do not match the expected types
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(String),
+    /// The hub closed the connection with an error
+    #[error("Connection closed by the hub: {0}")]
+    ServerClosed(String),
+    /// The connection closed before the invocation completed
+    #[error("Connection closed before the invocation completed")]
+    ConnectionClosed,
+    /// The hub does not support the operation, or is not a known SmaRTC hub
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    #[error("Not connected")]
+    NotConnected,
+    /// No room or session joined, or the last one joined was left
+    #[error("Not in a room")]
+    NotInRoom,
+    /// The outbox already holds [`super::OutboxOptions::capacity`] messages
+    #[error("Outbox is full")]
+    OutboxFull,
+    /// A background task of the connection panicked
+    #[error("Connection task failed: {0}")]
+    TaskFailed(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<ChunkError> for HubError {
+    fn from(e: ChunkError) -> Self {
+        HubError::Protocol(e.to_string())
+    }
+}
+
+impl From<ProtocolError> for HubError {
+    fn from(e: ProtocolError) -> Self {
+        HubError::Protocol(e.to_string())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for HubError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        HubError::Transport(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for HubError {
+    fn from(e: reqwest::Error) -> Self {
+        HubError::Http(e.to_string())
+    }
+}
+
+impl From<url::ParseError> for HubError {
+    fn from(e: url::ParseError) -> Self {
+        HubError::InvalidUrl(e.to_string())
+    }
+}
+
+impl From<rmpv::ext::Error> for HubError {
+    fn from(e: rmpv::ext::Error) -> Self {
+        HubError::InvalidArguments(e.to_string())
+    }
+}
+
+/// For errors of user callbacks, e.g. an [`super::AccessTokenFactory`]
+impl From<String> for HubError {
+    fn from(e: String) -> Self {
+        HubError::Other(e)
+    }
+}
+
+impl From<&str> for HubError {
+    fn from(e: &str) -> Self {
+        HubError::Other(e.to_string())
+    }
+}
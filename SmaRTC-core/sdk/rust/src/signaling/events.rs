@@ -0,0 +1,255 @@
+// SmaRTC Rust SDK - Hub events and client builder
+
+use super::auth::AccessTokenFactory;
+use super::chunking::ChunkOptions;
+use super::connection::ConnectOptions;
+use super::outbound::{OutboundConfig, OutboundPolicy};
+use super::outbox::{OutboxOptions, OutboxStatus};
+use super::registry::HandlerRegistry;
+use super::reliable::ReliableOptions;
+use super::SignalingClient;
+use futures_util::Stream;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Delays between automatic reconnect attempts, as in the SignalR JS client
+pub const DEFAULT_RECONNECT_DELAYS: [Duration; 4] = [
+    Duration::from_secs(0),
+    Duration::from_secs(2),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+/// Events emitted by the hub client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HubEvent {
+    /// A chat message sent by another user; `room` is `None` when the sender
+    /// did not tag it and several rooms are joined
+    Message { room: Option<String>, user: String, message: String },
+    UserJoined { room: String, user: String },
+    UserLeft { room: String, user: String },
+    /// The connection dropped; automatic reconnect is in progress
+    Reconnecting,
+    /// The connection was re-established and the room rejoined
+    Reconnected,
+    /// The connection is closed for good
+    Disconnected,
+    /// A background task of the connection panicked; the client stops and
+    /// does not reconnect
+    Failed { error: String },
+    /// A server invocation whose arguments did not match the expected types
+    InvalidInvocation { target: String, error: String },
+    /// A reliable message that `users` did not acknowledge before
+    /// [`ReliableOptions::timeout`]
+    Undelivered { room: String, message: String, users: Vec<String> },
+    /// A message sent while disconnected was queued, then sent, expired or
+    /// cancelled; see [`OutboxStatus`]
+    Outbox { id: u64, room: String, message: String, status: OutboxStatus },
+    /// This stream fell too far behind and `missed` events were dropped,
+    /// messages included; the next ones follow
+    Lagged { missed: u64 },
+}
+
+pub type MessageCallback = Arc<dyn Fn(String, String) + Send + Sync>;
+pub type UserCallback = Arc<dyn Fn(String) + Send + Sync>;
+
+/// Callbacks registered through [`SignalingClientBuilder`]
+#[derive(Clone, Default)]
+pub(crate) struct Handlers {
+    pub on_signal: Option<MessageCallback>,
+    pub on_user_joined: Option<UserCallback>,
+    pub on_user_left: Option<UserCallback>,
+    pub registry: HandlerRegistry,
+}
+
+impl Handlers {
+    pub fn dispatch(&self, event: &HubEvent) {
+        match event {
+            HubEvent::Message { user, message, .. } => {
+                if let Some(cb) = &self.on_signal {
+                    cb(user.clone(), message.clone());
+                }
+            }
+            HubEvent::UserJoined { user, .. } => {
+                if let Some(cb) = &self.on_user_joined {
+                    cb(user.clone());
+                }
+            }
+            HubEvent::UserLeft { user, .. } => {
+                if let Some(cb) = &self.on_user_left {
+                    cb(user.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Registers handlers before the client connects
+pub struct SignalingClientBuilder {
+    hub_url: String,
+    handlers: Handlers,
+    reconnect_delays: Vec<Duration>,
+    options: ConnectOptions,
+    reliable: Option<ReliableOptions>,
+    outbox: Option<OutboxOptions>,
+}
+
+impl SignalingClientBuilder {
+    pub fn new(hub_url: &str) -> Self {
+        Self {
+            hub_url: hub_url.to_string(),
+            handlers: Handlers::default(),
+            reconnect_delays: DEFAULT_RECONNECT_DELAYS.to_vec(),
+            options: ConnectOptions::default(),
+            reliable: None,
+            outbox: None,
+        }
+    }
+
+    pub fn on_signal(mut self, callback: impl Fn(String, String) + Send + Sync + 'static) -> Self {
+        self.handlers.on_signal = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn on_user_joined(mut self, callback: impl Fn(String) + Send + Sync + 'static) -> Self {
+        self.handlers.on_user_joined = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn on_user_left(mut self, callback: impl Fn(String) + Send + Sync + 'static) -> Self {
+        self.handlers.on_user_left = Some(Arc::new(callback));
+        self
+    }
+
+    /// Registers a typed handler for any server-to-client method, e.g.
+    /// `.on::<(String, String), _>("SendSignal", |(signal, user)| ...)`
+    pub fn on<T, F>(mut self, target: &str, handler: F) -> Self
+    where
+        T: DeserializeOwned,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        self.handlers.registry.on(target, handler);
+        self
+    }
+
+    /// Delays between reconnect attempts; an empty list disables automatic reconnect
+    pub fn reconnect_delays(mut self, delays: Vec<Duration>) -> Self {
+        self.reconnect_delays = delays;
+        self
+    }
+
+    /// Authenticates the connection, e.g. with `auth::smartc_token(client)`;
+    /// the factory is called again before every reconnect attempt
+    pub fn access_token_factory(mut self, factory: AccessTokenFactory) -> Self {
+        self.options.access_token_factory = Some(factory);
+        self
+    }
+
+    /// What happens when messages are sent faster than the socket accepts
+    /// them, and how many may wait; see [`OutboundPolicy`]
+    pub fn outbound_policy(mut self, policy: OutboundPolicy, capacity: usize) -> Self {
+        self.options.outbound = OutboundConfig { policy, capacity };
+        self
+    }
+
+    /// Size limit above which signals are split, see [`ChunkOptions`]
+    pub fn chunking(mut self, chunking: ChunkOptions) -> Self {
+        self.options.chunking = chunking;
+        self
+    }
+
+    /// Room messages are acknowledged by their recipients, retransmitted
+    /// until they are, and delivered once and in order; see [`ReliableOptions`].
+    /// Every member of the room needs this version of the SDK or a later one.
+    pub fn reliable(mut self, options: ReliableOptions) -> Self {
+        self.reliable = Some(options);
+        self
+    }
+
+    /// Room messages sent while the connection is down are queued, then sent
+    /// in order once reconnected, instead of failing; see [`OutboxOptions`]
+    pub fn outbox(mut self, options: OutboxOptions) -> Self {
+        self.outbox = Some(options);
+        self
+    }
+
+    pub fn build(self) -> SignalingClient {
+        SignalingClient::from_builder(
+            self.hub_url,
+            self.handlers,
+            self.reconnect_delays,
+            self.options,
+            self.reliable,
+            self.outbox,
+        )
+    }
+}
+
+/// Turns a broadcast subscription into a stream, reporting the events missed
+/// by slow consumers with `lagged`, e.g. [`HubEvent::Lagged`]
+pub(crate) fn event_stream<T: Clone + Send + 'static>(
+    rx: broadcast::Receiver<T>,
+    lagged: fn(u64) -> T,
+) -> impl Stream<Item = T> {
+    futures_util::stream::unfold(rx, move |mut rx| async move {
+        match rx.recv().await {
+            Ok(event) => Some((event, rx)),
+            Err(broadcast::error::RecvError::Lagged(missed)) => Some((lagged(missed), rx)),
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_handlers_and_stream_see_the_same_events() {
+        let joined = Arc::new(Mutex::new(Vec::new()));
+        let joined_clone = joined.clone();
+        let handlers = Handlers {
+            on_user_joined: Some(Arc::new(move |user| joined_clone.lock().unwrap().push(user))),
+            ..Default::default()
+        };
+
+        let (tx, rx) = broadcast::channel(4);
+        let stream = event_stream(rx, |missed| HubEvent::Lagged { missed });
+        tokio::pin!(stream);
+
+        let event = HubEvent::UserJoined {
+            room: "room".to_string(),
+            user: "bob".to_string(),
+        };
+        handlers.dispatch(&event);
+        tx.send(event.clone()).unwrap();
+        drop(tx);
+
+        assert_eq!(stream.next().await, Some(event));
+        assert_eq!(stream.next().await, None);
+        assert_eq!(*joined.lock().unwrap(), vec!["bob".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_streams_are_told_what_they_missed() {
+        let (tx, rx) = broadcast::channel(2);
+        let stream = event_stream(rx, |missed| HubEvent::Lagged { missed });
+        tokio::pin!(stream);
+
+        for _ in 0..3 {
+            tx.send(HubEvent::Reconnecting).unwrap();
+        }
+        tx.send(HubEvent::Reconnected).unwrap();
+        drop(tx);
+
+        assert_eq!(stream.next().await, Some(HubEvent::Lagged { missed: 2 }));
+        assert_eq!(stream.next().await, Some(HubEvent::Reconnecting));
+        assert_eq!(stream.next().await, Some(HubEvent::Reconnected));
+        assert_eq!(stream.next().await, None);
+    }
+}
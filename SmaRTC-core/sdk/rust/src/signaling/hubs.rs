@@ -0,0 +1,90 @@
+// SmaRTC Rust SDK - Typed descriptions of the SmaRTC hubs
+
+use super::zerocost::{GlobalStats, SessionStats};
+use serde_bytes::Bytes;
+use smartc_macros::hub;
+
+/// `SignalHub` (signal-server/Hubs/SignalHub.cs)
+#[hub]
+pub trait SignalHub {
+    async fn join_session(&self, session_id: &str, username: &str);
+    async fn leave_session(&self, session_id: &str, username: &str);
+    async fn new_user(&self, user: &str);
+    async fn send_signal(&self, signal: &str, user: &str);
+    async fn send_signal_to_session(&self, session_id: &str, signal: &str, user: &str);
+}
+
+/// `ZeroCostWebRtcHub` (signal-server/Hubs/ZeroCostWebRtcHub.cs)
+#[hub]
+pub trait ZeroCostHub {
+    async fn join_session(&self, session_id: &str, username: &str);
+    async fn leave_session(&self, session_id: &str);
+    #[hub(send)]
+    async fn send_signal(&self, target_connection_id: &str, signal_data: &Bytes);
+    #[hub(send)]
+    async fn broadcast_signal(&self, session_id: &str, signal_data: &Bytes);
+    #[hub(send)]
+    async fn heartbeat(&self);
+    async fn get_session_stats(&self, session_id: &str) -> SessionStats;
+    async fn get_global_stats(&self) -> GlobalStats;
+}
+
+/// `SimpleWebRtcHub` (signal-server/Program.Simple.cs)
+#[hub]
+pub trait SimpleHub {
+    async fn join_session(&self, session_id: &str, username: &str);
+    async fn leave_session(&self, session_id: &str);
+    async fn send_signal(&self, target_peer_id: &str, signal: &str);
+    async fn set_relay_capability(&self, can_relay: bool);
+    async fn update_peer_latency(&self, peer_id: &str, latency: i32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signaling::connection::{ConnectOptions, HubConnection};
+    use crate::signaling::protocol::{HubMessage, HubProtocol};
+    use crate::signaling::testing::MockHub;
+    use rmpv::Value;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_proxy_invokes_pascal_case_targets() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let (connection, _invocations) = HubConnection::connect(&server.url, &ConnectOptions::default()).await.unwrap();
+        let hub = SignalHubProxy::new(Arc::new(connection));
+
+        let call = tokio::spawn(async move { hub.send_signal_to_session("room", "hello", "alice").await });
+
+        let HubMessage::Invocation { invocation_id, target, arguments } = server.recv().await else {
+            panic!("expected an invocation");
+        };
+        assert_eq!(target, "SendSignalToSession");
+        assert_eq!(arguments, vec![Value::from("room"), Value::from("hello"), Value::from("alice")]);
+
+        server.complete(&invocation_id.unwrap(), None);
+        call.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_decodes_typed_results() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let (connection, _invocations) = HubConnection::connect(&server.url, &ConnectOptions::default()).await.unwrap();
+        let hub = ZeroCostHubProxy::new(Arc::new(connection));
+
+        let call = tokio::spawn(async move { hub.get_session_stats("room").await });
+
+        let HubMessage::Invocation { invocation_id, .. } = server.recv().await else {
+            panic!("expected an invocation");
+        };
+        let result = Value::Map(vec![
+            (Value::from("sessionId"), Value::from("room")),
+            (Value::from("connectionCount"), Value::from(2)),
+            (Value::from("relayNodeCount"), Value::from(0)),
+        ]);
+        server.complete(&invocation_id.unwrap(), Some(result));
+
+        let stats = call.await.unwrap().unwrap();
+        assert_eq!(stats.connection_count, 2);
+    }
+}
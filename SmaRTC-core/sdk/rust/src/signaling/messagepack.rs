@@ -0,0 +1,339 @@
+// SmaRTC Rust SDK - MessagePack hub protocol with LZ4 payload compression
+
+use super::protocol::{
+    Compression, HubMessage, ProtocolError, CLOSE, COMPLETION, INVOCATION, PING, STREAM_ITEM,
+};
+use rmpv::Value;
+
+// MessagePack-CSharp extension type codes for compressed payloads
+const LZ4_BLOCK_ARRAY: i8 = 98;
+const LZ4_BLOCK: i8 = 99;
+
+// MessagePack-CSharp leaves payloads smaller than this uncompressed
+const COMPRESSION_MIN_LENGTH: usize = 64;
+
+/// Largest payload accepted once decompressed. The lengths announced by LZ4
+/// payloads come from the peer and are checked against it before allocating.
+pub const MAX_DECOMPRESSED_LENGTH: usize = 16 * 1024 * 1024;
+
+// Completion result kinds
+const RESULT_ERROR: i64 = 1;
+const RESULT_VOID: i64 = 2;
+const RESULT_VALUE: i64 = 3;
+
+/// Writes the varint length prefix that precedes every binary message
+pub fn write_length_prefix(length: usize, out: &mut Vec<u8>) {
+    let mut length = length;
+    loop {
+        let mut byte = (length & 0x7f) as u8;
+        length >>= 7;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+/// Reads a varint length prefix; returns `(prefix size, payload length)`,
+/// or `None` when the prefix itself is incomplete.
+pub fn read_length_prefix(input: &[u8]) -> Result<Option<(usize, usize)>, ProtocolError> {
+    let mut length = 0usize;
+    for (i, byte) in input.iter().enumerate().take(5) {
+        length |= ((byte & 0x7f) as usize) << (i * 7);
+        if byte & 0x80 == 0 {
+            if i == 4 && *byte > 0x07 {
+                return Err("MessagePack length prefix exceeds 2GB".into());
+            }
+            return Ok(Some((i + 1, length)));
+        }
+    }
+    if input.len() >= 5 {
+        return Err("MessagePack length prefix is longer than 5 bytes".into());
+    }
+    Ok(None)
+}
+
+pub fn write_message(message: &HubMessage, compression: Compression) -> Result<Vec<u8>, ProtocolError> {
+    let nullable = |s: &Option<String>| s.clone().map(Value::from).unwrap_or(Value::Nil);
+    let headers = Value::Map(Vec::new());
+
+    let array = match message {
+        HubMessage::Invocation { invocation_id, target, arguments } => vec![
+            Value::from(INVOCATION),
+            headers,
+            nullable(invocation_id),
+            Value::from(target.as_str()),
+            Value::Array(
+                arguments
+                    .iter()
+                    .map(|arg| compress(arg, compression))
+                    .collect::<Result<_, _>>()?,
+            ),
+        ],
+        HubMessage::StreamItem { invocation_id, item } => vec![
+            Value::from(STREAM_ITEM),
+            headers,
+            Value::from(invocation_id.as_str()),
+            compress(item, compression)?,
+        ],
+        HubMessage::Completion { invocation_id, result, error } => {
+            let mut array = vec![Value::from(COMPLETION), headers, Value::from(invocation_id.as_str())];
+            match (error, result) {
+                (Some(error), _) => array.extend([Value::from(RESULT_ERROR), Value::from(error.as_str())]),
+                (None, Some(result)) => array.extend([Value::from(RESULT_VALUE), compress(result, compression)?]),
+                (None, None) => array.push(Value::from(RESULT_VOID)),
+            }
+            array
+        }
+        HubMessage::Ping => vec![Value::from(PING)],
+        HubMessage::Close { error, allow_reconnect } => vec![
+            Value::from(CLOSE),
+            nullable(error),
+            Value::Boolean(*allow_reconnect),
+        ],
+    };
+
+    let mut payload = Vec::new();
+    rmpv::encode::write_value(&mut payload, &Value::Array(array))?;
+
+    let mut out = Vec::with_capacity(payload.len() + 5);
+    write_length_prefix(payload.len(), &mut out);
+    out.extend_from_slice(&payload);
+    Ok(out)
+}
+
+/// Parses every length-prefixed message contained in `input`
+pub fn parse_messages(input: &[u8]) -> Result<Vec<HubMessage>, ProtocolError> {
+    let mut messages = Vec::new();
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let Some((prefix, length)) = read_length_prefix(rest)? else {
+            break;
+        };
+        if rest.len() < prefix + length {
+            break;
+        }
+        let payload = &rest[prefix..prefix + length];
+        if let Some(message) = parse_message(payload)? {
+            messages.push(message);
+        }
+        rest = &rest[prefix + length..];
+    }
+
+    Ok(messages)
+}
+
+fn parse_message(payload: &[u8]) -> Result<Option<HubMessage>, ProtocolError> {
+    let value = rmpv::decode::read_value(&mut &payload[..])?;
+    let Value::Array(items) = value else {
+        return Err("MessagePack hub message is not an array".into());
+    };
+
+    let item = |i: usize| items.get(i).cloned().unwrap_or(Value::Nil);
+    let string = |i: usize| items.get(i).and_then(|v| v.as_str()).map(str::to_string);
+    let message_type = items
+        .first()
+        .and_then(|v| v.as_i64())
+        .ok_or("MessagePack hub message has no type")?;
+
+    let message = match message_type {
+        INVOCATION => {
+            let arguments = match item(4) {
+                Value::Array(args) => args.into_iter().map(decompress).collect::<Result<_, _>>()?,
+                _ => Vec::new(),
+            };
+            Some(HubMessage::Invocation {
+                invocation_id: string(2),
+                target: string(3).ok_or("Invocation without target")?,
+                arguments,
+            })
+        }
+        STREAM_ITEM => Some(HubMessage::StreamItem {
+            invocation_id: string(2).ok_or("StreamItem without invocation id")?,
+            item: decompress(item(3))?,
+        }),
+        COMPLETION => {
+            let invocation_id = string(2).ok_or("Completion without invocation id")?;
+            let (result, error) = match item(3).as_i64() {
+                Some(RESULT_ERROR) => (None, string(4)),
+                Some(RESULT_VALUE) => (Some(decompress(item(4))?), None),
+                _ => (None, None),
+            };
+            Some(HubMessage::Completion { invocation_id, result, error })
+        }
+        PING => Some(HubMessage::Ping),
+        CLOSE => Some(HubMessage::Close {
+            error: string(1),
+            allow_reconnect: item(2).as_bool().unwrap_or(false),
+        }),
+        _ => None,
+    };
+
+    Ok(message)
+}
+
+/// Expands an `Lz4BlockArray` or `Lz4Block` payload; other values are
+/// returned unchanged.
+pub fn decompress(value: Value) -> Result<Value, ProtocolError> {
+    match value {
+        Value::Array(items) if matches!(items.first(), Some(Value::Ext(LZ4_BLOCK_ARRAY, _))) => {
+            let Some(Value::Ext(_, header)) = items.first() else {
+                unreachable!()
+            };
+
+            // The extension body lists the uncompressed length of each block
+            let mut lengths = Vec::new();
+            let mut total = 0usize;
+            let mut cursor = &header[..];
+            while !cursor.is_empty() {
+                let length = rmpv::decode::read_value(&mut cursor)?
+                    .as_u64()
+                    .ok_or("Invalid Lz4BlockArray block length")?;
+                let length = decompressed_length(length)?;
+                total = total
+                    .checked_add(length)
+                    .filter(|total| *total <= MAX_DECOMPRESSED_LENGTH)
+                    .ok_or("Lz4BlockArray payload is too large")?;
+                lengths.push(length);
+            }
+            if lengths.len() != items.len() - 1 {
+                return Err("Lz4BlockArray block count mismatch".into());
+            }
+
+            let mut data = Vec::with_capacity(total);
+            for (block, length) in items[1..].iter().zip(lengths) {
+                let Value::Binary(block) = block else {
+                    return Err("Lz4BlockArray block is not binary".into());
+                };
+                data.extend(lz4_flex::block::decompress(block, length)?);
+            }
+            Ok(rmpv::decode::read_value(&mut &data[..])?)
+        }
+        Value::Ext(LZ4_BLOCK, body) => {
+            let mut cursor = &body[..];
+            let length = rmpv::decode::read_value(&mut cursor)?
+                .as_u64()
+                .ok_or("Invalid Lz4Block length")?;
+            let data = lz4_flex::block::decompress(cursor, decompressed_length(length)?)?;
+            Ok(rmpv::decode::read_value(&mut &data[..])?)
+        }
+        other => Ok(other),
+    }
+}
+
+fn decompressed_length(length: u64) -> Result<usize, ProtocolError> {
+    usize::try_from(length)
+        .ok()
+        .filter(|length| *length <= MAX_DECOMPRESSED_LENGTH)
+        .ok_or_else(|| format!("LZ4 payload of {} bytes exceeds {} bytes", length, MAX_DECOMPRESSED_LENGTH).into())
+}
+
+fn compress(value: &Value, compression: Compression) -> Result<Value, ProtocolError> {
+    if compression == Compression::None {
+        return Ok(value.clone());
+    }
+
+    let mut data = Vec::new();
+    rmpv::encode::write_value(&mut data, value)?;
+    if data.len() < COMPRESSION_MIN_LENGTH {
+        return Ok(value.clone());
+    }
+
+    let mut header = Vec::new();
+    rmpv::encode::write_value(&mut header, &Value::from(data.len() as u64))?;
+    Ok(Value::Array(vec![
+        Value::Ext(LZ4_BLOCK_ARRAY, header),
+        Value::Binary(lz4_flex::block::compress(&data)),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_prefix_round_trip() {
+        for length in [0usize, 127, 128, 16_384, 2_147_483_647] {
+            let mut out = Vec::new();
+            write_length_prefix(length, &mut out);
+            assert_eq!(read_length_prefix(&out).unwrap(), Some((out.len(), length)));
+        }
+        assert_eq!(read_length_prefix(&[0x80, 0x80]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_lz4_compressed_invocation_round_trip() {
+        let message = HubMessage::Invocation {
+            invocation_id: None,
+            target: "SendSignal".to_string(),
+            arguments: vec![Value::from("short"), Value::from("x".repeat(500))],
+        };
+
+        let bytes = write_message(&message, Compression::Lz4BlockArray).unwrap();
+        assert!(bytes.len() < 100);
+        assert_eq!(parse_messages(&bytes).unwrap(), vec![message]);
+    }
+
+    #[test]
+    fn test_parses_several_messages_per_frame() {
+        let mut bytes = write_message(&HubMessage::Ping, Compression::None).unwrap();
+        bytes.extend(
+            write_message(
+                &HubMessage::Completion {
+                    invocation_id: "1".to_string(),
+                    result: None,
+                    error: Some("boom".to_string()),
+                },
+                Compression::None,
+            )
+            .unwrap(),
+        );
+
+        let messages = parse_messages(&bytes).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], HubMessage::Ping);
+    }
+
+    #[test]
+    fn test_decompress_lz4_block() {
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &Value::from("y".repeat(200))).unwrap();
+
+        // Lz4Block body: int32 uncompressed length followed by the raw block
+        let mut body = vec![0xd2];
+        body.extend((data.len() as i32).to_be_bytes());
+        body.extend(lz4_flex::block::compress(&data));
+
+        let value = decompress(Value::Ext(LZ4_BLOCK, body)).unwrap();
+        assert_eq!(value, Value::from("y".repeat(200)));
+    }
+
+    #[test]
+    fn test_oversized_lz4_lengths_are_rejected() {
+        let block = |length: u64| {
+            let mut body = Vec::new();
+            rmpv::encode::write_value(&mut body, &Value::from(length)).unwrap();
+            body.extend(lz4_flex::block::compress(b"tiny"));
+            Value::Ext(LZ4_BLOCK, body)
+        };
+        assert!(decompress(block(u64::MAX)).is_err());
+        assert!(decompress(block(MAX_DECOMPRESSED_LENGTH as u64 + 1)).is_err());
+
+        // Blocks each below the limit, but not once added up
+        let mut header = Vec::new();
+        for _ in 0..2 {
+            rmpv::encode::write_value(&mut header, &Value::from(MAX_DECOMPRESSED_LENGTH as u64 / 2 + 1)).unwrap();
+        }
+        let blocks = Value::Array(vec![
+            Value::Ext(LZ4_BLOCK_ARRAY, header),
+            Value::Binary(lz4_flex::block::compress(b"tiny")),
+            Value::Binary(lz4_flex::block::compress(b"tiny")),
+        ]);
+        let error = decompress(blocks).unwrap_err();
+        assert_eq!(error.to_string(), "Lz4BlockArray payload is too large");
+    }
+}
@@ -0,0 +1,1078 @@
+// SmaRTC Rust SDK - Real-time signaling over the SmaRTC SignalR hubs
+
+pub mod auth;
+pub mod chunking;
+pub mod connection;
+pub mod dialect;
+pub mod error;
+pub mod events;
+pub mod hubs;
+pub mod messagepack;
+pub mod outbound;
+pub mod outbox;
+pub mod protocol;
+pub mod registry;
+pub mod reliable;
+pub mod rooms;
+pub mod session;
+pub mod zerocost;
+
+#[cfg(test)]
+mod testing;
+
+use connection::{ConnectOptions, HubConnection, Invocation};
+use events::Handlers;
+use futures_util::Stream;
+use hubs::{SignalHub, SignalHubProxy};
+use outbox::Outbox;
+use chunking::Reassembler;
+use reliable::{Reliability, Undelivered};
+use rooms::{Envelope, EnvelopeKind, Room, Rooms, SharedConnection};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::Interval;
+
+pub use auth::{access_token_factory, AccessTokenFactory};
+pub use chunking::{ChunkError, ChunkOptions};
+pub use dialect::{DialectKind, HubDialect, Peer, PeerEvent, SignalTarget};
+pub use error::HubError;
+pub use events::{HubEvent, MessageCallback, SignalingClientBuilder, UserCallback};
+pub use outbound::{OutboundMetrics, OutboundPolicy, SendError};
+pub use outbox::{OutboxOptions, OutboxStatus, QueuedMessage};
+pub use protocol::{Compression, HubProtocol};
+pub use registry::HandlerRegistry;
+pub use reliable::ReliableOptions;
+pub use rooms::RoomHandle;
+pub use session::SessionClient;
+pub use smartc_macros::hub;
+pub use zerocost::{GlobalStats, PeerInfo, SessionStats, ZeroCostClient, ZeroCostClientBuilder, ZeroCostEvent};
+
+/// How often incomplete chunked signals and queued messages are checked for
+/// expiry, even when nothing else happens
+const HOUSEKEEPING: Duration = Duration::from_secs(1);
+
+pub struct SignalingClient {
+    hub_url: String,
+    rooms: Rooms,
+    /// Room used by `send_message` and `leave_room`, the last one joined
+    room_name: Arc<Mutex<String>>,
+    connection: SharedConnection,
+    handlers: Handlers,
+    reconnect_delays: Vec<Duration>,
+    options: ConnectOptions,
+    reliable: Arc<Reliability>,
+    outbox: Arc<Outbox>,
+    events: broadcast::Sender<HubEvent>,
+    supervisor: std::sync::Mutex<Option<SupervisorTask>>,
+    /// Set by `disconnect` so that the supervisor stops instead of reconnecting
+    closing: watch::Sender<bool>,
+}
+
+impl SignalingClient {
+    pub fn new(hub_url: &str) -> Self {
+        Self::builder(hub_url).build()
+    }
+
+    /// Starts a builder to register handlers before connecting
+    pub fn builder(hub_url: &str) -> SignalingClientBuilder {
+        SignalingClientBuilder::new(hub_url)
+    }
+
+    pub(crate) fn from_builder(
+        hub_url: String,
+        handlers: Handlers,
+        reconnect_delays: Vec<Duration>,
+        options: ConnectOptions,
+        reliable: Option<ReliableOptions>,
+        outbox: Option<OutboxOptions>,
+    ) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            hub_url,
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+            room_name: Arc::new(Mutex::new(String::new())),
+            connection: Arc::new(Mutex::new(None)),
+            handlers,
+            reconnect_delays,
+            options,
+            reliable: Arc::new(Reliability::new(reliable)),
+            outbox: Arc::new(Outbox::new(outbox, events.clone())),
+            events,
+            supervisor: std::sync::Mutex::new(None),
+            closing: watch::channel(false).0,
+        }
+    }
+
+    /// Stream of hub events; each call returns an independent subscription
+    pub fn events(&self) -> impl Stream<Item = HubEvent> {
+        events::event_stream(self.events.subscribe(), |missed| HubEvent::Lagged { missed })
+    }
+
+    pub async fn connect(&self) -> Result<(), HubError> {
+        self.connect_with(HubProtocol::Json).await
+    }
+
+    /// Connects using the given hub protocol, e.g. MessagePack with LZ4 for
+    /// servers started from `Program.Optimized.cs`
+    pub async fn connect_with(&self, protocol: HubProtocol) -> Result<(), HubError> {
+        let options = ConnectOptions {
+            protocol,
+            ..self.options.clone()
+        };
+        let (connection, invocations) = HubConnection::connect(&self.hub_url, &options).await?;
+        if let Some(previous) = self.connection.lock().await.replace(Arc::new(connection)) {
+            previous.abort();
+        }
+
+        self.closing.send_replace(false);
+        let supervisor = Supervisor {
+            hub_url: self.hub_url.clone(),
+            options,
+            rooms: self.rooms.clone(),
+            connection: self.connection.clone(),
+            handlers: self.handlers.clone(),
+            reconnect_delays: self.reconnect_delays.clone(),
+            events: self.events.clone(),
+            closing: self.closing.subscribe(),
+            chunks: Mutex::new(Reassembler::new(self.options.chunking.clone())),
+            reliable: self.reliable.clone(),
+            outbox: self.outbox.clone(),
+        };
+        let supervisor = SupervisorTask::spawn(supervisor.run(invocations), self.events.clone());
+        if let Some(previous) = self.supervisor.lock().unwrap().replace(supervisor) {
+            previous.abort();
+        }
+
+        Ok(())
+    }
+
+    /// Whether a hub connection is open; `false` while reconnecting
+    pub async fn is_connected(&self) -> bool {
+        self.connection.lock().await.is_some()
+    }
+
+    /// Leaves every joined room, flushes queued messages, closes the socket
+    /// and waits for the background tasks. Emits `HubEvent::Disconnected`.
+    pub async fn disconnect(&self) -> Result<(), HubError> {
+        self.closing.send_replace(true);
+        let mut result = Ok(());
+
+        if let Ok(hub) = rooms::signal_hub(&self.connection).await {
+            let joined: Vec<(String, String)> = {
+                let rooms = self.rooms.lock().await;
+                rooms.iter().map(|(name, room)| (name.clone(), room.username.clone())).collect()
+            };
+            for (room, user) in joined {
+                let chunking = &self.options.chunking;
+                let left = match rooms::announce(&hub, chunking, &room, &user, EnvelopeKind::Leave, None).await {
+                    Ok(()) => hub.leave_session(&room, &user).await,
+                    Err(e) => Err(e),
+                };
+                result = result.and(left);
+            }
+        }
+        self.rooms.lock().await.clear();
+        self.room_name.lock().await.clear();
+        self.reliable.clear();
+        self.outbox.cancel(None);
+
+        let connection = self.connection.lock().await.take();
+        if let Some(connection) = connection {
+            result = result.and(connection.close().await);
+        }
+        let supervisor = self.supervisor.lock().unwrap().take();
+        if let Some(supervisor) = supervisor {
+            let _ = supervisor.watcher.await;
+        }
+        result
+    }
+
+    /// Joins a room as `user`; several rooms can be joined on the same
+    /// connection, each with its own identity
+    pub async fn join_room(&self, room: &str, user: &str) -> Result<RoomHandle, HubError> {
+        let hub = rooms::signal_hub(&self.connection).await?;
+        self.rooms.lock().await.insert(
+            room.to_string(),
+            Room {
+                username: user.to_string(),
+                ..Default::default()
+            },
+        );
+
+        if let Err(e) = hub.join_session(room, user).await {
+            self.rooms.lock().await.remove(room);
+            return Err(e);
+        }
+        rooms::announce(&hub, &self.options.chunking, room, user, EnvelopeKind::Join, None).await?;
+        *self.room_name.lock().await = room.to_string();
+
+        Ok(self.room_handle(room, user))
+    }
+
+    /// Handle of a room joined earlier
+    pub async fn room(&self, room: &str) -> Option<RoomHandle> {
+        let rooms = self.rooms.lock().await;
+        let username = &rooms.get(room)?.username;
+        Some(self.room_handle(room, username))
+    }
+
+    fn room_handle(&self, room: &str, username: &str) -> RoomHandle {
+        RoomHandle::new(
+            room,
+            username,
+            self.connection.clone(),
+            self.rooms.clone(),
+            self.options.chunking.clone(),
+            self.reliable.clone(),
+            self.outbox.clone(),
+        )
+    }
+
+    /// Names of the joined rooms
+    pub async fn rooms(&self) -> Vec<String> {
+        let mut rooms: Vec<String> = self.rooms.lock().await.keys().cloned().collect();
+        rooms.sort();
+        rooms
+    }
+
+    /// Sends to the last room joined, see [`RoomHandle::send`]
+    pub async fn send_message(&self, message: &str) -> Result<(), HubError> {
+        self.current_room().await?.send(message).await
+    }
+
+    /// Leaves the last room joined, see [`RoomHandle::leave`]
+    pub async fn leave_room(&self) -> Result<(), HubError> {
+        self.current_room().await?.leave().await?;
+        self.room_name.lock().await.clear();
+        Ok(())
+    }
+
+    async fn current_room(&self) -> Result<RoomHandle, HubError> {
+        let room = self.room_name.lock().await.clone();
+        self.room(&room).await.ok_or(HubError::NotInRoom)
+    }
+
+    /// Messages waiting for the connection to come back, oldest first; see
+    /// `SignalingClientBuilder::outbox`
+    pub fn outbox(&self) -> Vec<QueuedMessage> {
+        self.outbox.messages()
+    }
+
+    /// Outbound queue metrics of the current connection
+    pub async fn outbound_metrics(&self) -> Option<OutboundMetrics> {
+        let connection = self.connection.lock().await.clone();
+        connection.map(|connection| connection.outbound_metrics())
+    }
+
+    pub async fn get_username(&self) -> String {
+        let room = self.room_name.lock().await.clone();
+        let rooms = self.rooms.lock().await;
+        rooms.get(&room).map(|room| room.username.clone()).unwrap_or_default()
+    }
+
+    pub async fn get_room(&self) -> String {
+        self.room_name.lock().await.clone()
+    }
+}
+
+impl Drop for SignalingClient {
+    fn drop(&mut self) {
+        if let Some(supervisor) = self.supervisor.get_mut().unwrap().take() {
+            supervisor.abort();
+        }
+        if let Ok(mut connection) = self.connection.try_lock() {
+            if let Some(connection) = connection.take() {
+                connection.abort();
+            }
+        }
+    }
+}
+
+/// The supervisor task and the one reporting its panics, e.g. from a user
+/// handler, since it can no longer emit events itself
+struct SupervisorTask {
+    run: AbortHandle,
+    watcher: JoinHandle<()>,
+}
+
+impl SupervisorTask {
+    fn spawn(run: impl std::future::Future<Output = ()> + Send + 'static, events: broadcast::Sender<HubEvent>) -> Self {
+        let run = tokio::spawn(run);
+        let abort = run.abort_handle();
+        let watcher = tokio::spawn(async move {
+            if let Err(e) = run.await {
+                if e.is_panic() {
+                    let _ = events.send(HubEvent::Failed { error: e.to_string() });
+                }
+            }
+        });
+        Self { run: abort, watcher }
+    }
+
+    fn abort(&self) {
+        self.run.abort();
+        self.watcher.abort();
+    }
+}
+
+/// Dispatches server invocations as events and reconnects when the
+/// connection drops
+struct Supervisor {
+    hub_url: String,
+    options: ConnectOptions,
+    rooms: Rooms,
+    connection: SharedConnection,
+    handlers: Handlers,
+    reconnect_delays: Vec<Duration>,
+    events: broadcast::Sender<HubEvent>,
+    closing: watch::Receiver<bool>,
+    /// Chunked signals being received
+    chunks: Mutex<Reassembler>,
+    reliable: Arc<Reliability>,
+    outbox: Arc<Outbox>,
+}
+
+impl Supervisor {
+    async fn run(self, mut invocations: mpsc::UnboundedReceiver<Invocation>) {
+        let period = self.reliable.options.retransmit;
+        let mut retransmits = self.reliable.is_sending().then(|| tokio::time::interval(period));
+        let mut housekeeping = tokio::time::interval(HOUSEKEEPING);
+        loop {
+            loop {
+                let invocation = tokio::select! {
+                    invocation = invocations.recv() => invocation,
+                    _ = tick(&mut retransmits) => {
+                        self.retransmit().await;
+                        continue;
+                    }
+                    _ = housekeeping.tick() => {
+                        self.expire_chunks().await;
+                        self.expire_previous_members().await;
+                        self.outbox.expire();
+                        continue;
+                    }
+                };
+                let Some(invocation) = invocation else {
+                    break;
+                };
+                self.expire_chunks().await;
+                if let Err(e) = self.handlers.registry.dispatch(&invocation.target, &invocation.arguments) {
+                    self.emit(HubEvent::InvalidInvocation {
+                        target: invocation.target.clone(),
+                        error: e.to_string(),
+                    });
+                }
+                match self.to_event(&invocation).await {
+                    Ok(Some(event)) => self.emit(event),
+                    Ok(None) => {}
+                    Err(e) => self.emit(HubEvent::InvalidInvocation {
+                        target: invocation.target,
+                        error: e.to_string(),
+                    }),
+                }
+            }
+
+            // A panicked task is a bug, not a network failure: stop for good
+            let connection = self.connection.lock().await.take();
+            if let Some(connection) = connection {
+                if let Err(e @ HubError::TaskFailed(_)) = connection.closed().await {
+                    self.emit(HubEvent::Failed { error: e.to_string() });
+                    break;
+                }
+            }
+            if *self.closing.borrow() {
+                self.emit(HubEvent::Disconnected);
+                break;
+            }
+            match self.reconnect().await {
+                Some(next) => invocations = next,
+                None => {
+                    self.emit(HubEvent::Disconnected);
+                    break;
+                }
+            }
+        }
+        // Nothing will rejoin the rooms now
+        self.outbox.cancel(None);
+    }
+
+    async fn to_event(&self, invocation: &Invocation) -> Result<Option<HubEvent>, HubError> {
+        let args = &invocation.arguments;
+        let event = match invocation.target.to_lowercase().as_str() {
+            // SendSignalToSession relays `(signal, user)` to the other members
+            "sendsignal" => {
+                let (signal, user): (String, String) = registry::from_arguments(args)?;
+                let Some(signal) = self.chunks.lock().await.push(&user, signal)? else {
+                    return Ok(None);
+                };
+                match Envelope::parse(&signal) {
+                    Some(envelope) => self.route(envelope, user).await,
+                    None => self.untagged_message(signal, user).await,
+                }
+            }
+            // Presence events carry no session id; they can only be attributed
+            // while a single room is joined, otherwise envelopes take over
+            "newuserarrived" => {
+                let (user,): (String,) = registry::from_arguments(args)?;
+                let room = self.single_room().await;
+                match room {
+                    Some(room) => self.update_members(&room, &user, true).await,
+                    None => None,
+                }
+            }
+            "userleft" => {
+                let (user,): (String,) = registry::from_arguments(args)?;
+                let room = self.single_room().await;
+                match room {
+                    Some(room) => self.update_members(&room, &user, false).await,
+                    None => None,
+                }
+            }
+            _ => None,
+        };
+        Ok(event)
+    }
+
+    async fn route(&self, envelope: Envelope, user: String) -> Option<HubEvent> {
+        let username = {
+            let rooms = self.rooms.lock().await;
+            let room = rooms.get(&envelope.room)?;
+            if room.username == user {
+                return None;
+            }
+            room.username.clone()
+        };
+
+        match envelope.kind {
+            EnvelopeKind::Message => {
+                let (room, message) = (envelope.room, envelope.body.unwrap_or_default());
+                let Some(seq) = envelope.seq else {
+                    return Some(HubEvent::Message { room: Some(room), user, message });
+                };
+                // Reliable messages may complete earlier ones: they are all
+                // emitted here, in order
+                let received = self.reliable.receive(&room, &user, &seq, message)?;
+                let acks = received.acks.iter().map(|&n| reliable::ack(&room, &seq.stream, n)).collect();
+                self.reply(acks, username);
+                for message in received.messages {
+                    self.emit(HubEvent::Message { room: Some(room.clone()), user: user.clone(), message });
+                }
+                None
+            }
+            EnvelopeKind::Join => {
+                // Let the newcomer know we are here
+                self.reply(vec![Envelope::new(&envelope.room, EnvelopeKind::Present, None)], username);
+                self.update_members(&envelope.room, &user, true).await
+            }
+            EnvelopeKind::Present => self.update_members(&envelope.room, &user, true).await,
+            EnvelopeKind::Leave => {
+                self.reliable.forget(&envelope.room, &user);
+                self.update_members(&envelope.room, &user, false).await
+            }
+            EnvelopeKind::Ack => {
+                if let Some(seq) = envelope.seq {
+                    self.reliable.acknowledged(&envelope.room, &user, &seq);
+                }
+                None
+            }
+            EnvelopeKind::Unknown => None,
+        }
+    }
+
+    /// Sends envelopes in the background and in order, so that invocations
+    /// keep being dispatched meanwhile
+    fn reply(&self, envelopes: Vec<Envelope>, username: String) {
+        if envelopes.is_empty() {
+            return;
+        }
+        let (connection, chunking) = (self.connection.clone(), self.options.chunking.clone());
+        tokio::spawn(async move {
+            if let Ok(hub) = rooms::signal_hub(&connection).await {
+                for envelope in envelopes {
+                    let _ = rooms::send_envelope(&hub, &chunking, &envelope, &username).await;
+                }
+            }
+        });
+    }
+
+    /// Retransmits the unacknowledged reliable messages that are due, and
+    /// reports those given up
+    async fn retransmit(&self) {
+        let (due, undelivered) = self.reliable.due(Instant::now());
+        for Undelivered { room, message, users } in undelivered {
+            self.emit(HubEvent::Undelivered { room, message, users });
+        }
+        if due.is_empty() {
+            return;
+        }
+        let usernames: HashMap<String, String> = {
+            let rooms = self.rooms.lock().await;
+            rooms.iter().map(|(name, room)| (name.clone(), room.username.clone())).collect()
+        };
+        let (connection, chunking) = (self.connection.clone(), self.options.chunking.clone());
+        tokio::spawn(async move {
+            let Ok(hub) = rooms::signal_hub(&connection).await else {
+                return;
+            };
+            for envelope in due {
+                if let Some(username) = usernames.get(&envelope.room) {
+                    let _ = rooms::send_envelope(&hub, &chunking, &envelope, username).await;
+                }
+            }
+        });
+    }
+
+    /// Drops the chunked signals still incomplete after their timeout
+    async fn expire_chunks(&self) {
+        let expired = self.chunks.lock().await.expire();
+        for error in expired {
+            let target = "SendSignal".to_string();
+            self.emit(HubEvent::InvalidInvocation { target, error: error.to_string() });
+        }
+    }
+
+    /// Signals sent without an envelope, e.g. by the JavaScript SDK
+    async fn untagged_message(&self, message: String, user: String) -> Option<HubEvent> {
+        let rooms = self.rooms.lock().await;
+        if rooms.values().any(|room| room.username == user) {
+            return None;
+        }
+        let room = (rooms.len() == 1).then(|| rooms.keys().next().cloned()).flatten();
+        Some(HubEvent::Message { room, user, message })
+    }
+
+    async fn single_room(&self) -> Option<String> {
+        let rooms = self.rooms.lock().await;
+        (rooms.len() == 1).then(|| rooms.keys().next().cloned()).flatten()
+    }
+
+    /// Emits presence changes only, so duplicate notifications are ignored
+    async fn update_members(&self, room: &str, user: &str, joined: bool) -> Option<HubEvent> {
+        let mut rooms = self.rooms.lock().await;
+        let state = rooms.get_mut(room)?;
+        if state.username == user {
+            return None;
+        }
+
+        let room = room.to_string();
+        let user = user.to_string();
+        let previous = state.previous.remove(&user);
+        if joined {
+            state.members.insert(user.clone()).then_some(HubEvent::UserJoined { room, user })
+        } else {
+            (state.members.remove(&user) || previous).then_some(HubEvent::UserLeft { room, user })
+        }
+    }
+
+    /// Gives up on the members that did not answer the rejoin in time
+    async fn expire_previous_members(&self) {
+        let now = Instant::now();
+        let mut left = Vec::new();
+        for (name, room) in self.rooms.lock().await.iter_mut() {
+            if room.previous_until.is_some_and(|until| until <= now) {
+                room.previous_until = None;
+                left.extend(std::mem::take(&mut room.previous).into_iter().map(|user| (name.clone(), user)));
+            }
+        }
+        for (room, user) in left {
+            self.emit(HubEvent::UserLeft { room, user });
+        }
+    }
+
+    async fn reconnect(&self) -> Option<mpsc::UnboundedReceiver<Invocation>> {
+        if self.reconnect_delays.is_empty() {
+            return None;
+        }
+        self.emit(HubEvent::Reconnecting);
+
+        let mut closing = self.closing.clone();
+        let mut housekeeping = tokio::time::interval(HOUSEKEEPING);
+        'attempts: for delay in &self.reconnect_delays {
+            let sleep = tokio::time::sleep(*delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    _ = housekeeping.tick() => self.outbox.expire(),
+                    _ = closing.wait_for(|closing| *closing) => return None,
+                }
+            }
+            self.outbox.expire();
+            // A fresh token for every attempt, the previous one may have expired
+            let Ok((connection, invocations)) = HubConnection::connect(&self.hub_url, &self.options).await else {
+                continue;
+            };
+
+            // Rejoin the rooms we were in before the connection dropped. The
+            // members answer again; until then, or until the reliable timeout,
+            // messages keep waiting for them.
+            let connection = Arc::new(connection);
+            let hub = SignalHubProxy::new(connection.clone());
+            let previous_until = Instant::now() + self.reliable.options.timeout;
+            let rooms: Vec<(String, String)> = {
+                let mut rooms = self.rooms.lock().await;
+                rooms
+                    .iter_mut()
+                    .map(|(name, room)| {
+                        let mut members = std::mem::take(&mut room.members);
+                        room.previous.append(&mut members);
+                        room.previous_until = Some(previous_until);
+                        (name.clone(), room.username.clone())
+                    })
+                    .collect()
+            };
+            for (room, user) in &rooms {
+                let chunking = &self.options.chunking;
+                if hub.join_session(room, user).await.is_err()
+                    || rooms::announce(&hub, chunking, room, user, EnvelopeKind::Join, None).await.is_err()
+                {
+                    continue 'attempts;
+                }
+            }
+
+            if *self.closing.borrow() {
+                let _ = connection.close().await;
+                return None;
+            }
+            *self.connection.lock().await = Some(connection);
+            self.reliable.retransmit_now();
+            self.emit(HubEvent::Reconnected);
+
+            let (connection, rooms) = (self.connection.clone(), self.rooms.clone());
+            let (chunking, reliable, outbox) =
+                (self.options.chunking.clone(), self.reliable.clone(), self.outbox.clone());
+            tokio::spawn(async move { rooms::flush_outbox(&connection, &rooms, &chunking, &reliable, &outbox).await });
+            return Some(invocations);
+        }
+        None
+    }
+
+    fn emit(&self, event: HubEvent) {
+        self.handlers.dispatch(&event);
+        let _ = self.events.send(event);
+    }
+}
+
+/// Waits for the next tick, forever without an interval
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use protocol::HubMessage;
+    use rmpv::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use testing::MockHub;
+
+    /// Completes the next client invocation and returns its target and arguments
+    async fn answer(server: &mut MockHub) -> (String, Vec<Value>) {
+        let HubMessage::Invocation { invocation_id, target, arguments } = server.recv().await else {
+            panic!("expected an invocation");
+        };
+        server.complete(&invocation_id.unwrap(), None);
+        (target, arguments)
+    }
+
+    /// Answers invocations until an envelope of `kind` is sent
+    async fn next_envelope(server: &mut MockHub, kind: EnvelopeKind) -> Envelope {
+        loop {
+            let (_, arguments) = answer(server).await;
+            let envelope = Envelope::parse(arguments[1].as_str().unwrap()).unwrap();
+            if envelope.kind == kind {
+                return envelope;
+            }
+        }
+    }
+
+    fn send_signal(server: &MockHub, signal: &str, user: &str) {
+        server.send(HubMessage::Invocation {
+            invocation_id: None,
+            target: "SendSignal".to_string(),
+            arguments: vec![Value::from(signal), Value::from(user)],
+        });
+    }
+
+    #[tokio::test]
+    async fn test_access_token_is_refreshed_on_reconnect() {
+        let server = MockHub::start(HubProtocol::Json).await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let factory = access_token_factory(move || {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Ok(format!("jwt-{}", n)) }
+        });
+        let client = SignalingClient::builder(&server.url)
+            .access_token_factory(factory)
+            .reconnect_delays(vec![Duration::ZERO])
+            .build();
+        let events = client.events();
+        tokio::pin!(events);
+
+        client.connect().await.unwrap();
+        server.drop_connection();
+        assert_eq!(events.next().await, Some(HubEvent::Reconnecting));
+        assert_eq!(events.next().await, Some(HubEvent::Reconnected));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        for (attempt, pair) in requests.chunks(2).enumerate() {
+            let token = format!("jwt-{}", attempt + 1);
+            assert_eq!(pair[0].uri, "/signalhub/negotiate?negotiateVersion=1");
+            assert_eq!(pair[0].authorization, Some(format!("Bearer {}", token)));
+            assert_eq!(pair[1].uri, format!("/signalhub?id=token-1&access_token={}", token));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rooms_are_routed_by_envelope() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let client = Arc::new(SignalingClient::builder(&server.url).reconnect_delays(Vec::new()).build());
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        // One connection, one identity per room
+        for (room, user) in [("sales", "alice"), ("support", "agent-7")] {
+            let joining = client.clone();
+            let join = tokio::spawn(async move { joining.join_room(room, user).await });
+            let (target, arguments) = answer(&mut server).await;
+            assert_eq!(target, "JoinSession");
+            assert_eq!(arguments, vec![Value::from(room), Value::from(user)]);
+            let (target, arguments) = answer(&mut server).await;
+            assert_eq!(target, "SendSignalToSession");
+            assert_eq!(arguments[1], Value::from(Envelope::new(room, EnvelopeKind::Join, None).encode()));
+            join.await.unwrap().unwrap();
+        }
+
+        // bob joins support and is answered by agent-7 only
+        send_signal(&server, &Envelope::new("support", EnvelopeKind::Join, None).encode(), "bob");
+        let joined = HubEvent::UserJoined {
+            room: "support".to_string(),
+            user: "bob".to_string(),
+        };
+        assert_eq!(events.next().await, Some(joined));
+        let (_, arguments) = answer(&mut server).await;
+        let present = Envelope::new("support", EnvelopeKind::Present, None).encode();
+        assert_eq!(arguments, vec![Value::from("support"), Value::from(present), Value::from("agent-7")]);
+
+        send_signal(&server, &Envelope::new("support", EnvelopeKind::Message, Some("hi")).encode(), "bob");
+        let message = HubEvent::Message {
+            room: Some("support".to_string()),
+            user: "bob".to_string(),
+            message: "hi".to_string(),
+        };
+        assert_eq!(events.next().await, Some(message));
+
+        // Untagged signals cannot be attributed to one of several rooms
+        send_signal(&server, "plain", "carol");
+        let message = HubEvent::Message {
+            room: None,
+            user: "carol".to_string(),
+            message: "plain".to_string(),
+        };
+        assert_eq!(events.next().await, Some(message));
+
+        let support = client.room("support").await.unwrap();
+        assert_eq!(support.members().await, vec!["agent-7", "bob"]);
+        assert_eq!(client.room("sales").await.unwrap().members().await, vec!["alice"]);
+
+        let send = tokio::spawn(async move { support.send("hello").await });
+        let (_, arguments) = answer(&mut server).await;
+        let tagged = Envelope::new("support", EnvelopeKind::Message, Some("hello")).encode();
+        assert_eq!(arguments, vec![Value::from("support"), Value::from(tagged), Value::from("agent-7")]);
+        send.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_large_messages_travel_in_chunks() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let chunking = ChunkOptions { max_size: 4096, ..Default::default() };
+        let client = Arc::new(SignalingClient::builder(&server.url).chunking(chunking.clone()).build());
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        let joining = client.clone();
+        let join = tokio::spawn(async move { joining.join_room("room", "alice").await });
+        answer(&mut server).await;
+        answer(&mut server).await;
+        join.await.unwrap().unwrap();
+
+        let message = "{\"file\":\"report.pdf\"}".repeat(1000);
+        let sending = client.clone();
+        let sent_message = message.clone();
+        let send = tokio::spawn(async move { sending.send_message(&sent_message).await });
+        let mut chunks: Vec<String> = Vec::new();
+        let last = |chunks: &[String]| chunks.last().and_then(|chunk| chunking::Chunk::parse(chunk));
+        while last(&chunks).is_none_or(|chunk| chunk.index + 1 < chunk.count) {
+            let (target, arguments) = answer(&mut server).await;
+            assert_eq!(target, "SendSignalToSession");
+            let chunk = arguments[1].as_str().unwrap().to_string();
+            assert!(serde_json::to_string(&chunk).unwrap().len() <= chunking.max_size);
+            chunks.push(chunk);
+        }
+        send.await.unwrap().unwrap();
+        assert!(chunks.len() > 5);
+
+        // The hub relays them to bob's client, which sees a single message
+        for chunk in chunks.iter().rev() {
+            send_signal(&server, chunk, "bob");
+        }
+        let received = HubEvent::Message {
+            room: Some("room".to_string()),
+            user: "bob".to_string(),
+            message,
+        };
+        assert_eq!(events.next().await, Some(received));
+    }
+
+    #[tokio::test]
+    async fn test_reliable_messages_are_acknowledged_and_ordered() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let reliable = ReliableOptions { retransmit: Duration::from_millis(20), ..Default::default() };
+        let client = Arc::new(SignalingClient::builder(&server.url).reliable(reliable).build());
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        let joining = client.clone();
+        let join = tokio::spawn(async move { joining.join_room("room", "alice").await });
+        answer(&mut server).await;
+        answer(&mut server).await;
+        join.await.unwrap().unwrap();
+        send_signal(&server, &Envelope::new("room", EnvelopeKind::Join, None).encode(), "bob");
+        assert!(matches!(events.next().await, Some(HubEvent::UserJoined { .. })));
+        next_envelope(&mut server, EnvelopeKind::Present).await;
+
+        // Sent again and again until bob acknowledges it
+        let sending = client.clone();
+        let send = tokio::spawn(async move { sending.send_message("mute").await });
+        let mute = next_envelope(&mut server, EnvelopeKind::Message).await;
+        send.await.unwrap().unwrap();
+        assert_eq!(next_envelope(&mut server, EnvelopeKind::Message).await, mute);
+        let seq = mute.seq.clone().unwrap();
+        assert_eq!((seq.n, seq.since, mute.body.as_deref()), (1, Some(1), Some("mute")));
+        send_signal(&server, &reliable::ack("room", &seq.stream, seq.n).encode(), "bob");
+
+        // Once the ack is in, "mute" is no longer retransmitted
+        let sending = client.clone();
+        let send = tokio::spawn(async move { sending.send_message("end call").await });
+        loop {
+            let seq = next_envelope(&mut server, EnvelopeKind::Message).await.seq.unwrap();
+            if seq.since == Some(2) {
+                assert_eq!(seq.n, 2);
+                break;
+            }
+        }
+        send.await.unwrap().unwrap();
+
+        // bob's messages arrive out of order and twice, each one acknowledged
+        // once delivered
+        let reliable = |n: u64, body: &str| Envelope {
+            seq: Some(reliable::Sequence { stream: "bob-1".into(), n, since: Some(1) }),
+            ..Envelope::new("room", EnvelopeKind::Message, Some(body))
+        };
+        for (n, body, acks) in [(2, "second", &[][..]), (1, "first", &[1, 2]), (1, "first", &[1])] {
+            send_signal(&server, &reliable(n, body).encode(), "bob");
+            for &n in acks {
+                let ack = next_envelope(&mut server, EnvelopeKind::Ack).await.seq.unwrap();
+                assert_eq!((ack.stream.as_str(), ack.n), ("bob-1", n));
+            }
+        }
+        send_signal(&server, &reliable(3, "third").encode(), "bob");
+        for message in ["first", "second", "third"] {
+            let received = HubEvent::Message {
+                room: Some("room".to_string()),
+                user: "bob".to_string(),
+                message: message.to_string(),
+            };
+            assert_eq!(events.next().await, Some(received));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_sent_offline_are_flushed_after_rejoin() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let client = Arc::new(
+            SignalingClient::builder(&server.url)
+                .reconnect_delays(vec![Duration::from_millis(200)])
+                .outbox(OutboxOptions::default())
+                .build(),
+        );
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        let joining = client.clone();
+        let join = tokio::spawn(async move { joining.join_room("room", "alice").await });
+        answer(&mut server).await;
+        answer(&mut server).await;
+        join.await.unwrap().unwrap();
+
+        server.drop_connection();
+        assert_eq!(events.next().await, Some(HubEvent::Reconnecting));
+        client.send_message("one").await.unwrap();
+        client.send_message("two").await.unwrap();
+        let queued: Vec<String> = client.outbox().into_iter().map(|queued| queued.message).collect();
+        assert_eq!(queued, ["one", "two"]);
+        let status = |id: u64, message: &str, status: OutboxStatus| HubEvent::Outbox {
+            id,
+            room: "room".to_string(),
+            message: message.to_string(),
+            status,
+        };
+        assert_eq!(events.next().await, Some(status(1, "one", OutboxStatus::Queued)));
+        assert_eq!(events.next().await, Some(status(2, "two", OutboxStatus::Queued)));
+
+        // Sent in order once the room is rejoined
+        let (target, _) = answer(&mut server).await;
+        assert_eq!(target, "JoinSession");
+        next_envelope(&mut server, EnvelopeKind::Join).await;
+        for message in ["one", "two"] {
+            let envelope = next_envelope(&mut server, EnvelopeKind::Message).await;
+            assert_eq!(envelope.body.as_deref(), Some(message));
+        }
+        assert_eq!(events.next().await, Some(HubEvent::Reconnected));
+        assert_eq!(events.next().await, Some(status(1, "one", OutboxStatus::Sent)));
+        assert_eq!(events.next().await, Some(status(2, "two", OutboxStatus::Sent)));
+        assert!(client.outbox().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_messages_flushed_after_rejoin_stay_reliable() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let client = Arc::new(
+            SignalingClient::builder(&server.url)
+                .reconnect_delays(vec![Duration::from_millis(200)])
+                .reliable(ReliableOptions { retransmit: Duration::from_millis(20), ..Default::default() })
+                .outbox(OutboxOptions::default())
+                .build(),
+        );
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        let joining = client.clone();
+        let join = tokio::spawn(async move { joining.join_room("room", "alice").await });
+        answer(&mut server).await;
+        answer(&mut server).await;
+        join.await.unwrap().unwrap();
+        send_signal(&server, &Envelope::new("room", EnvelopeKind::Join, None).encode(), "bob");
+        assert!(matches!(events.next().await, Some(HubEvent::UserJoined { .. })));
+        next_envelope(&mut server, EnvelopeKind::Present).await;
+
+        server.drop_connection();
+        assert_eq!(events.next().await, Some(HubEvent::Reconnecting));
+        client.send_message("one").await.unwrap();
+        let (target, _) = answer(&mut server).await;
+        assert_eq!(target, "JoinSession");
+
+        // bob has not answered the rejoin yet, and misses the first copy:
+        // it is still waited for, and sent again until acknowledged
+        let first = next_envelope(&mut server, EnvelopeKind::Message).await;
+        assert_eq!(first.body.as_deref(), Some("one"));
+        assert_eq!(next_envelope(&mut server, EnvelopeKind::Message).await, first);
+        assert_eq!(client.room("room").await.unwrap().members().await, vec!["alice", "bob"]);
+        let seq = first.seq.unwrap();
+        send_signal(&server, &reliable::ack("room", &seq.stream, seq.n).encode(), "bob");
+
+        // Once the ack is in, "one" is no longer retransmitted
+        let sending = client.clone();
+        let send = tokio::spawn(async move { sending.send_message("two").await });
+        loop {
+            let envelope = next_envelope(&mut server, EnvelopeKind::Message).await;
+            if envelope.seq.unwrap().since == Some(2) {
+                assert_eq!(envelope.body.as_deref(), Some("two"));
+                break;
+            }
+        }
+        send.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queued_messages_expire_between_reconnect_attempts() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let client = Arc::new(
+            SignalingClient::builder(&server.url)
+                .reconnect_delays(vec![Duration::from_secs(60)])
+                .outbox(OutboxOptions { ttl: Duration::from_millis(100), ..Default::default() })
+                .build(),
+        );
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        let joining = client.clone();
+        let join = tokio::spawn(async move { joining.join_room("room", "alice").await });
+        answer(&mut server).await;
+        answer(&mut server).await;
+        join.await.unwrap().unwrap();
+
+        server.drop_connection();
+        assert_eq!(events.next().await, Some(HubEvent::Reconnecting));
+        client.send_message("one").await.unwrap();
+        assert!(matches!(events.next().await, Some(HubEvent::Outbox { status: OutboxStatus::Queued, .. })));
+
+        // Long before the next attempt
+        let expired = tokio::time::timeout(Duration::from_secs(5), events.next()).await.unwrap();
+        assert!(matches!(expired, Some(HubEvent::Outbox { status: OutboxStatus::Expired, .. })), "{:?}", expired);
+        assert!(client.outbox().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_leaves_rooms_and_stops() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        let client = Arc::new(SignalingClient::new(&server.url));
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        let joining = client.clone();
+        let join = tokio::spawn(async move { joining.join_room("room", "alice").await });
+        answer(&mut server).await;
+        answer(&mut server).await;
+        join.await.unwrap().unwrap();
+
+        let leaving = client.clone();
+        let disconnect = tokio::spawn(async move { leaving.disconnect().await });
+        let (target, _) = answer(&mut server).await;
+        assert_eq!(target, "SendSignalToSession");
+        let (target, arguments) = answer(&mut server).await;
+        assert_eq!(target, "LeaveSession");
+        assert_eq!(arguments, vec![Value::from("room"), Value::from("alice")]);
+        disconnect.await.unwrap().unwrap();
+
+        // No reconnect attempt after an explicit disconnect
+        assert_eq!(events.next().await, Some(HubEvent::Disconnected));
+        assert!(client.outbound_metrics().await.is_none());
+        assert!(client.send_message("hello").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_is_a_terminal_event() {
+        let server = MockHub::start(HubProtocol::Json).await;
+        let client = SignalingClient::builder(&server.url)
+            .on::<(String,), _>("Boom", |_| panic!("handler failed"))
+            .build();
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect().await.unwrap();
+
+        server.send(HubMessage::Invocation {
+            invocation_id: None,
+            target: "Boom".to_string(),
+            arguments: vec![Value::from("now")],
+        });
+        assert!(matches!(events.next().await, Some(HubEvent::Failed { error }) if error.contains("handler failed")));
+    }
+}
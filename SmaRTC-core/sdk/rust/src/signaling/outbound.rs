@@ -0,0 +1,258 @@
+// SmaRTC Rust SDK - Outbound frame queue with backpressure
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use thiserror::Error;
+use tokio::sync::{oneshot, Notify};
+use tokio_tungstenite::tungstenite::Message;
+
+pub const DEFAULT_OUTBOUND_CAPACITY: usize = 100;
+
+/// What to do with a new frame when the outbound queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutboundPolicy {
+    /// Wait until the writer makes room
+    #[default]
+    Block,
+    /// Discard the oldest queued frame; its sender gets [`SendError::Dropped`]
+    DropOldest,
+    /// Fail the new frame with [`SendError::QueueFull`]
+    ErrorWhenFull,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundConfig {
+    pub policy: OutboundPolicy,
+    pub capacity: usize,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            policy: OutboundPolicy::default(),
+            capacity: DEFAULT_OUTBOUND_CAPACITY,
+        }
+    }
+}
+
+/// Why a frame did not reach the socket
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SendError {
+    #[error("Outbound queue is full")]
+    QueueFull,
+    #[error("Dropped from the outbound queue by a newer message")]
+    Dropped,
+    #[error("Connection closed")]
+    Closed,
+    #[error("WebSocket send failed: {0}")]
+    Transport(String),
+}
+
+/// Snapshot of the outbound queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutboundMetrics {
+    /// Frames waiting for the writer
+    pub depth: usize,
+    /// Highest depth seen since the connection opened
+    pub max_depth: usize,
+    pub sent: u64,
+    pub dropped: u64,
+    pub rejected: u64,
+    pub failed: u64,
+}
+
+pub(crate) struct Outgoing {
+    pub frame: Message,
+    delivered: oneshot::Sender<Result<(), SendError>>,
+}
+
+impl Outgoing {
+    fn complete(self, result: Result<(), SendError>) {
+        let _ = self.delivered.send(result);
+    }
+}
+
+struct State {
+    queue: VecDeque<Outgoing>,
+    closed: bool,
+    metrics: OutboundMetrics,
+}
+
+/// Frames queued by senders and drained, in order, by the connection's writer
+/// task. Each sender waits until its frame is written to the socket.
+pub(crate) struct OutboundQueue {
+    config: OutboundConfig,
+    state: Mutex<State>,
+    frame_ready: Notify,
+    space_ready: Notify,
+}
+
+impl OutboundQueue {
+    pub fn new(config: OutboundConfig) -> Self {
+        Self {
+            config: OutboundConfig {
+                capacity: config.capacity.max(1),
+                ..config
+            },
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                closed: false,
+                metrics: OutboundMetrics::default(),
+            }),
+            frame_ready: Notify::new(),
+            space_ready: Notify::new(),
+        }
+    }
+
+    /// Queues a frame and waits until the writer has sent it
+    pub async fn send(&self, frame: Message) -> Result<(), SendError> {
+        let (delivered, result) = oneshot::channel();
+        self.push(Outgoing { frame, delivered }).await?;
+        result.await.unwrap_or(Err(SendError::Closed))
+    }
+
+    async fn push(&self, item: Outgoing) -> Result<(), SendError> {
+        loop {
+            let space_ready = self.space_ready.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Err(SendError::Closed);
+                }
+                if state.queue.len() >= self.config.capacity {
+                    match self.config.policy {
+                        OutboundPolicy::Block => {}
+                        OutboundPolicy::DropOldest => {
+                            if let Some(oldest) = state.queue.pop_front() {
+                                state.metrics.dropped += 1;
+                                oldest.complete(Err(SendError::Dropped));
+                            }
+                        }
+                        OutboundPolicy::ErrorWhenFull => {
+                            state.metrics.rejected += 1;
+                            return Err(SendError::QueueFull);
+                        }
+                    }
+                }
+                if state.queue.len() < self.config.capacity {
+                    state.queue.push_back(item);
+                    state.metrics.max_depth = state.metrics.max_depth.max(state.queue.len());
+                    self.frame_ready.notify_one();
+                    return Ok(());
+                }
+            }
+            space_ready.await;
+        }
+    }
+
+    /// Next frame for the writer; `None` once the queue is closed and drained
+    pub async fn next(&self) -> Option<Outgoing> {
+        loop {
+            let frame_ready = self.frame_ready.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(item) = state.queue.pop_front() {
+                    self.space_ready.notify_one();
+                    return Some(item);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            frame_ready.await;
+        }
+    }
+
+    /// Reports the outcome of a frame returned by [`Self::next`]
+    pub fn complete(&self, item: Outgoing, result: Result<(), SendError>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            match result {
+                Ok(()) => state.metrics.sent += 1,
+                Err(_) => state.metrics.failed += 1,
+            }
+        }
+        item.complete(result);
+    }
+
+    /// Rejects new frames; queued ones are still handed to the writer
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.frame_ready.notify_waiters();
+        self.space_ready.notify_waiters();
+    }
+
+    pub fn metrics(&self) -> OutboundMetrics {
+        let state = self.state.lock().unwrap();
+        OutboundMetrics {
+            depth: state.queue.len(),
+            ..state.metrics
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn queue(policy: OutboundPolicy) -> Arc<OutboundQueue> {
+        Arc::new(OutboundQueue::new(OutboundConfig { policy, capacity: 1 }))
+    }
+
+    #[tokio::test]
+    async fn test_error_when_full_rejects_new_frames() {
+        let queue = queue(OutboundPolicy::ErrorWhenFull);
+        let first = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.send(Message::Text("1".into())).await }
+        });
+        tokio::task::yield_now().await;
+
+        assert_eq!(queue.send(Message::Text("2".into())).await, Err(SendError::QueueFull));
+        let item = queue.next().await.unwrap();
+        queue.complete(item, Ok(()));
+        assert_eq!(first.await.unwrap(), Ok(()));
+
+        let metrics = queue.metrics();
+        assert_eq!((metrics.sent, metrics.rejected, metrics.depth), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_fails_the_dropped_sender() {
+        let queue = queue(OutboundPolicy::DropOldest);
+        let first = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.send(Message::Text("1".into())).await }
+        });
+        tokio::task::yield_now().await;
+        let second = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.send(Message::Text("2".into())).await }
+        });
+
+        assert_eq!(first.await.unwrap(), Err(SendError::Dropped));
+        let item = queue.next().await.unwrap();
+        assert_eq!(item.frame, Message::Text("2".into()));
+        queue.complete(item, Ok(()));
+        assert_eq!(second.await.unwrap(), Ok(()));
+        assert_eq!(queue.metrics().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_close_drains_queued_frames_first() {
+        let queue = queue(OutboundPolicy::Block);
+        let first = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.send(Message::Text("1".into())).await }
+        });
+        tokio::task::yield_now().await;
+        queue.close();
+
+        assert_eq!(queue.send(Message::Text("2".into())).await, Err(SendError::Closed));
+        let item = queue.next().await.unwrap();
+        queue.complete(item, Ok(()));
+        assert!(queue.next().await.is_none());
+        assert_eq!(first.await.unwrap(), Ok(()));
+    }
+}
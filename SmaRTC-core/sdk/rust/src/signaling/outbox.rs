@@ -0,0 +1,213 @@
+// SmaRTC Rust SDK - Room messages kept while the hub connection is down
+
+use super::events::HubEvent;
+use super::outbound::SendError;
+use super::HubError;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// How many messages the outbox keeps, and for how long. The outbox lives in
+/// memory: it survives reconnects, not a restart of the process, so messages
+/// still queued when the client is dropped are lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxOptions {
+    /// Further messages are refused while this many are queued
+    pub capacity: usize,
+    /// Messages still queued after this long are dropped
+    pub ttl: Duration,
+}
+
+impl Default for OutboxOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            ttl: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// What became of a message sent while disconnected, reported by
+/// `HubEvent::Outbox`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxStatus {
+    /// Waiting for the connection to come back
+    Queued,
+    /// Delivered to the hub once reconnected and the room rejoined
+    Sent,
+    /// Still queued after [`OutboxOptions::ttl`]
+    Expired,
+    /// The room was left, or the client disconnected, before it could be
+    /// sent
+    Cancelled,
+}
+
+/// A message waiting in the outbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedMessage {
+    /// Unique per client, as in `HubEvent::Outbox`
+    pub id: u64,
+    pub room: String,
+    pub message: String,
+    pub queued_at: Instant,
+}
+
+/// Room messages sent while disconnected, flushed in order once the rooms
+/// are rejoined. Kept across reconnects, dropped by `disconnect`.
+pub(crate) struct Outbox {
+    options: Option<OutboxOptions>,
+    queue: Mutex<VecDeque<QueuedMessage>>,
+    next_id: AtomicU64,
+    /// Held while flushing, so that messages leave one at a time and in order
+    flushing: tokio::sync::Mutex<()>,
+    events: broadcast::Sender<HubEvent>,
+}
+
+impl Outbox {
+    pub fn new(options: Option<OutboxOptions>, events: broadcast::Sender<HubEvent>) -> Self {
+        Self {
+            options,
+            queue: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(1),
+            flushing: tokio::sync::Mutex::new(()),
+            events,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.options.is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+
+    pub fn messages(&self) -> Vec<QueuedMessage> {
+        self.queue.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Queues a message for `room`, unless the outbox is full
+    pub fn push(&self, room: &str, message: &str) -> Result<(), HubError> {
+        let Some(options) = &self.options else {
+            return Err(HubError::NotConnected);
+        };
+        self.expire();
+        let queued = {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len() >= options.capacity {
+                return Err(HubError::OutboxFull);
+            }
+            let queued = QueuedMessage {
+                id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                room: room.to_string(),
+                message: message.to_string(),
+                queued_at: Instant::now(),
+            };
+            queue.push_back(queued.clone());
+            queued
+        };
+        self.report(queued, OutboxStatus::Queued);
+        Ok(())
+    }
+
+    /// The oldest message, left in the queue until [`Outbox::sent`] so that
+    /// newer ones keep queuing behind it
+    pub fn front(&self) -> Option<QueuedMessage> {
+        self.expire();
+        self.queue.lock().unwrap().front().cloned()
+    }
+
+    pub fn sent(&self, id: u64) {
+        self.remove(|queued| queued.id == id, OutboxStatus::Sent);
+    }
+
+    /// Drops the messages older than the TTL
+    pub fn expire(&self) {
+        let Some(options) = &self.options else {
+            return;
+        };
+        self.remove(|queued| queued.queued_at.elapsed() >= options.ttl, OutboxStatus::Expired);
+    }
+
+    /// Drops the messages of `room`, or all of them
+    pub fn cancel(&self, room: Option<&str>) {
+        self.remove(|queued| room.is_none_or(|room| queued.room == room), OutboxStatus::Cancelled);
+    }
+
+    pub async fn flushing(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.flushing.lock().await
+    }
+
+    fn remove(&self, matches: impl Fn(&QueuedMessage) -> bool, status: OutboxStatus) {
+        let mut removed = Vec::new();
+        self.queue.lock().unwrap().retain(|queued| {
+            let remove = matches(queued);
+            if remove {
+                removed.push(queued.clone());
+            }
+            !remove
+        });
+        for queued in removed {
+            self.report(queued, status);
+        }
+    }
+
+    fn report(&self, queued: QueuedMessage, status: OutboxStatus) {
+        let QueuedMessage { id, room, message, .. } = queued;
+        let _ = self.events.send(HubEvent::Outbox { id, room, message, status });
+    }
+}
+
+/// Errors meaning the connection is down, rather than the message refused
+pub(crate) fn is_offline(error: &HubError) -> bool {
+    matches!(
+        error,
+        HubError::NotConnected
+            | HubError::ConnectionClosed
+            | HubError::Transport(_)
+            | HubError::Send(SendError::Closed | SendError::Transport(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outbox_reports_every_message() {
+        let (events, mut statuses) = broadcast::channel(16);
+        let options = OutboxOptions { capacity: 2, ttl: Duration::from_millis(50) };
+        let outbox = Outbox::new(Some(options), events);
+        let mut next = move || match statuses.try_recv() {
+            Ok(HubEvent::Outbox { id, status, .. }) => Some((id, status)),
+            _ => None,
+        };
+
+        outbox.push("room", "one").unwrap();
+        outbox.push("other", "two").unwrap();
+        assert_eq!(outbox.push("room", "three"), Err(HubError::OutboxFull));
+        assert_eq!(next(), Some((1, OutboxStatus::Queued)));
+        assert_eq!(next(), Some((2, OutboxStatus::Queued)));
+        assert_eq!(next(), None);
+
+        // Sent messages leave from the front only once delivered
+        assert_eq!(outbox.front().map(|queued| queued.message), Some("one".to_string()));
+        assert_eq!(outbox.messages().len(), 2);
+        outbox.sent(1);
+        assert_eq!(next(), Some((1, OutboxStatus::Sent)));
+        outbox.cancel(Some("other"));
+        assert_eq!(next(), Some((2, OutboxStatus::Cancelled)));
+        assert!(outbox.is_empty());
+
+        outbox.push("room", "late").unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(next(), Some((3, OutboxStatus::Queued)));
+        assert_eq!(outbox.front(), None);
+        assert_eq!(next(), Some((3, OutboxStatus::Expired)));
+
+        assert!(is_offline(&HubError::Send(SendError::Closed)));
+        assert!(!is_offline(&HubError::Invocation { target: "SendSignalToSession".into(), error: "boom".into() }));
+    }
+}
@@ -0,0 +1,438 @@
+// SmaRTC Rust SDK - SignalR hub protocols (JSON and MessagePack)
+
+use super::messagepack;
+use base64::Engine;
+use rmpv::Value;
+use serde::Deserialize;
+use serde_json::{json, Map};
+
+pub const RECORD_SEPARATOR: char = '\u{1e}';
+
+/// Longest record buffered while waiting for its end, as announced by a
+/// MessagePack length prefix or before a JSON record separator
+pub const MAX_RECORD_LENGTH: usize = 16 * 1024 * 1024;
+
+pub type ProtocolError = Box<dyn std::error::Error + Send + Sync>;
+
+// SignalR message type identifiers, shared by every hub protocol
+pub const INVOCATION: i64 = 1;
+pub const STREAM_ITEM: i64 = 2;
+pub const COMPLETION: i64 = 3;
+pub const PING: i64 = 6;
+pub const CLOSE: i64 = 7;
+
+/// Payload compression applied by the server's MessagePack serializer
+/// (`MessagePackCompression` on the C# side).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// `MessagePackCompression.Lz4BlockArray`, as used by `Program.Optimized.cs`
+    Lz4BlockArray,
+}
+
+/// Hub protocol negotiated during the SignalR handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HubProtocol {
+    #[default]
+    Json,
+    MessagePack(Compression),
+}
+
+/// A protocol-independent SignalR hub message.
+///
+/// Arguments and results are carried as MessagePack values so that binary
+/// payloads (`byte[]` on the server) survive both protocols.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HubMessage {
+    Invocation {
+        invocation_id: Option<String>,
+        target: String,
+        arguments: Vec<Value>,
+    },
+    StreamItem {
+        invocation_id: String,
+        item: Value,
+    },
+    Completion {
+        invocation_id: String,
+        result: Option<Value>,
+        error: Option<String>,
+    },
+    Ping,
+    Close {
+        error: Option<String>,
+        allow_reconnect: bool,
+    },
+}
+
+impl HubProtocol {
+    /// Protocol name sent in the handshake request
+    pub fn name(&self) -> &'static str {
+        match self {
+            HubProtocol::Json => "json",
+            HubProtocol::MessagePack(_) => "messagepack",
+        }
+    }
+
+    /// Whether messages travel in binary WebSocket frames
+    pub fn is_binary(&self) -> bool {
+        matches!(self, HubProtocol::MessagePack(_))
+    }
+
+    /// Handshake request; always JSON, whatever the negotiated protocol
+    pub fn handshake_request(&self) -> String {
+        format!(
+            "{}{}",
+            json!({ "protocol": self.name(), "version": 1 }),
+            RECORD_SEPARATOR
+        )
+    }
+
+    /// Serializes a message, including its record separator or length prefix
+    pub fn write_message(&self, message: &HubMessage) -> Result<Vec<u8>, ProtocolError> {
+        match self {
+            HubProtocol::Json => {
+                let mut text = write_json(message).to_string();
+                text.push(RECORD_SEPARATOR);
+                Ok(text.into_bytes())
+            }
+            HubProtocol::MessagePack(compression) => messagepack::write_message(message, *compression),
+        }
+    }
+
+    /// Parses every complete message contained in a WebSocket frame
+    pub fn parse_messages(&self, input: &[u8]) -> Result<Vec<HubMessage>, ProtocolError> {
+        match self {
+            HubProtocol::Json => {
+                let text = std::str::from_utf8(input)?;
+                let mut messages = Vec::new();
+                for part in text.split(RECORD_SEPARATOR) {
+                    if part.is_empty() {
+                        continue;
+                    }
+                    if let Some(message) = parse_json(serde_json::from_str(part)?) {
+                        messages.push(message);
+                    }
+                }
+                Ok(messages)
+            }
+            HubProtocol::MessagePack(_) => messagepack::parse_messages(input),
+        }
+    }
+}
+
+/// Response to the handshake request, `{}` when the protocol is accepted
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct HandshakeResponse {
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Reassembles hub messages from WebSocket frames.
+///
+/// A record may be split across several frames and a frame may carry several
+/// records, so incomplete records stay buffered until the rest arrives. Text
+/// and binary frames feed the same buffer.
+#[derive(Debug)]
+pub struct MessageDecoder {
+    protocol: HubProtocol,
+    buffer: Vec<u8>,
+}
+
+impl MessageDecoder {
+    pub fn new(protocol: HubProtocol) -> Self {
+        Self { protocol, buffer: Vec::new() }
+    }
+
+    /// Buffers `data` until the handshake response is complete. It is always
+    /// a JSON record; whatever follows it stays buffered for [`Self::decode`].
+    pub fn handshake(&mut self, data: &[u8]) -> Result<Option<HandshakeResponse>, ProtocolError> {
+        self.buffer.extend_from_slice(data);
+        let Some(end) = self.buffer.iter().position(|b| *b == RECORD_SEPARATOR as u8) else {
+            return self.check_buffered(0).map(|_| None);
+        };
+        let record: Vec<u8> = self.buffer.drain(..=end).collect();
+        Ok(Some(serde_json::from_slice(&record[..end])?))
+    }
+
+    /// Appends `data` and returns every message completed by it
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<HubMessage>, ProtocolError> {
+        self.buffer.extend_from_slice(data);
+        let mut messages = Vec::new();
+        let mut consumed = 0;
+
+        loop {
+            let rest = &self.buffer[consumed..];
+            let end = match self.protocol {
+                HubProtocol::Json => match rest.iter().position(|b| *b == RECORD_SEPARATOR as u8) {
+                    Some(end) => end + 1,
+                    None => break,
+                },
+                HubProtocol::MessagePack(_) => match messagepack::read_length_prefix(rest)? {
+                    Some((_, length)) if length > MAX_RECORD_LENGTH => {
+                        return Err(too_long(length));
+                    }
+                    Some((prefix, length)) if rest.len() >= prefix + length => prefix + length,
+                    _ => break,
+                },
+            };
+            messages.extend(self.protocol.parse_messages(&rest[..end])?);
+            consumed += end;
+        }
+
+        self.check_buffered(consumed)?;
+        self.buffer.drain(..consumed);
+        Ok(messages)
+    }
+
+    /// Fails once the record that starts at `start` outgrows
+    /// [`MAX_RECORD_LENGTH`] without ending
+    fn check_buffered(&self, start: usize) -> Result<(), ProtocolError> {
+        match self.buffer.len() - start {
+            length if length > MAX_RECORD_LENGTH => Err(too_long(length)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Bytes of the record still waiting for its end
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+fn too_long(length: usize) -> ProtocolError {
+    format!("Record of {} bytes exceeds {} bytes", length, MAX_RECORD_LENGTH).into()
+}
+
+fn write_json(message: &HubMessage) -> serde_json::Value {
+    match message {
+        HubMessage::Invocation { invocation_id, target, arguments } => {
+            let mut msg = json!({
+                "type": INVOCATION,
+                "target": target,
+                "arguments": arguments.iter().map(to_json).collect::<Vec<_>>(),
+            });
+            if let Some(id) = invocation_id {
+                msg["invocationId"] = json!(id);
+            }
+            msg
+        }
+        HubMessage::StreamItem { invocation_id, item } => json!({
+            "type": STREAM_ITEM,
+            "invocationId": invocation_id,
+            "item": to_json(item),
+        }),
+        HubMessage::Completion { invocation_id, result, error } => {
+            let mut msg = json!({ "type": COMPLETION, "invocationId": invocation_id });
+            if let Some(result) = result {
+                msg["result"] = to_json(result);
+            }
+            if let Some(error) = error {
+                msg["error"] = json!(error);
+            }
+            msg
+        }
+        HubMessage::Ping => json!({ "type": PING }),
+        HubMessage::Close { error, allow_reconnect } => {
+            let mut msg = json!({ "type": CLOSE, "allowReconnect": allow_reconnect });
+            if let Some(error) = error {
+                msg["error"] = json!(error);
+            }
+            msg
+        }
+    }
+}
+
+fn parse_json(value: serde_json::Value) -> Option<HubMessage> {
+    let string = |key: &str| value.get(key).and_then(|v| v.as_str()).map(str::to_string);
+
+    match value.get("type")?.as_i64()? {
+        INVOCATION => Some(HubMessage::Invocation {
+            invocation_id: string("invocationId"),
+            target: string("target")?,
+            arguments: value
+                .get("arguments")
+                .and_then(|a| a.as_array())
+                .map(|args| args.iter().cloned().map(from_json).collect())
+                .unwrap_or_default(),
+        }),
+        STREAM_ITEM => Some(HubMessage::StreamItem {
+            invocation_id: string("invocationId")?,
+            item: value.get("item").cloned().map(from_json).unwrap_or(Value::Nil),
+        }),
+        COMPLETION => Some(HubMessage::Completion {
+            invocation_id: string("invocationId")?,
+            result: value.get("result").cloned().map(from_json),
+            error: string("error"),
+        }),
+        PING => Some(HubMessage::Ping),
+        CLOSE => Some(HubMessage::Close {
+            error: string("error"),
+            allow_reconnect: value
+                .get("allowReconnect")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        }),
+        _ => None,
+    }
+}
+
+/// Converts a hub value to JSON; binary data becomes base64, like `byte[]`
+/// in System.Text.Json.
+pub fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Nil | Value::Ext(..) => serde_json::Value::Null,
+        Value::Boolean(b) => json!(b),
+        Value::Integer(i) => match i.as_i64() {
+            Some(n) => json!(n),
+            None => json!(i.as_u64()),
+        },
+        Value::F32(f) => json!(f),
+        Value::F64(f) => json!(f),
+        Value::String(s) => json!(s.as_str().unwrap_or_default()),
+        Value::Binary(bytes) => json!(base64::engine::general_purpose::STANDARD.encode(bytes)),
+        Value::Array(items) => serde_json::Value::Array(items.iter().map(to_json).collect()),
+        Value::Map(entries) => {
+            let mut map = Map::new();
+            for (key, value) in entries {
+                let key = match key.as_str() {
+                    Some(k) => k.to_string(),
+                    None => key.to_string(),
+                };
+                map.insert(key, to_json(value));
+            }
+            serde_json::Value::Object(map)
+        }
+    }
+}
+
+/// Reads a `byte[]` argument: MessagePack `bin`, or base64 text with the
+/// JSON protocol
+pub fn as_bytes(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Binary(bytes) => Some(bytes.clone()),
+        Value::String(s) => base64::engine::general_purpose::STANDARD
+            .decode(s.as_str()?)
+            .ok(),
+        _ => None,
+    }
+}
+
+/// Converts a JSON value to a hub value
+pub fn from_json(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(b) => Value::Boolean(b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::from(i)
+            } else if let Some(u) = n.as_u64() {
+                Value::from(u)
+            } else {
+                Value::F64(n.as_f64().unwrap_or_default())
+            }
+        }
+        serde_json::Value::String(s) => Value::from(s),
+        serde_json::Value::Array(items) => Value::Array(items.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(map) => Value::Map(
+            map.into_iter()
+                .map(|(k, v)| (Value::from(k), from_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_invocation_round_trip() {
+        let message = HubMessage::Invocation {
+            invocation_id: Some("7".to_string()),
+            target: "JoinSession".to_string(),
+            arguments: vec![Value::from("room"), Value::from("alice")],
+        };
+
+        let bytes = HubProtocol::Json.write_message(&message).unwrap();
+        assert_eq!(*bytes.last().unwrap(), RECORD_SEPARATOR as u8);
+        assert_eq!(HubProtocol::Json.parse_messages(&bytes).unwrap(), vec![message]);
+    }
+
+    #[test]
+    fn test_decoder_reassembles_split_and_batched_records() {
+        let ping = HubProtocol::Json.write_message(&HubMessage::Ping).unwrap();
+        let close = HubMessage::Close { error: None, allow_reconnect: false };
+        let mut data = ping.clone();
+        data.extend(ping.clone());
+        data.extend(HubProtocol::Json.write_message(&close).unwrap());
+
+        let mut decoder = MessageDecoder::new(HubProtocol::Json);
+        assert_eq!(decoder.decode(&data[..3]).unwrap(), vec![]);
+        assert_eq!(decoder.decode(&data[3..ping.len() + 2]).unwrap(), vec![HubMessage::Ping]);
+        assert_eq!(decoder.decode(&data[ping.len() + 2..]).unwrap(), vec![HubMessage::Ping, close]);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_decoder_reassembles_binary_records() {
+        let protocol = HubProtocol::MessagePack(Compression::None);
+        let message = HubMessage::Invocation {
+            invocation_id: None,
+            target: "SendSignal".to_string(),
+            arguments: vec![Value::Binary(vec![7; 300])],
+        };
+        let mut data = protocol.write_message(&message).unwrap();
+        data.extend(protocol.write_message(&HubMessage::Ping).unwrap());
+
+        // The length prefix itself is split
+        let mut decoder = MessageDecoder::new(protocol);
+        assert_eq!(decoder.decode(&data[..1]).unwrap(), vec![]);
+        assert_eq!(decoder.decode(&data[1..100]).unwrap(), vec![]);
+        assert_eq!(decoder.decode(&data[100..]).unwrap(), vec![message, HubMessage::Ping]);
+    }
+
+    #[test]
+    fn test_decoder_refuses_endless_records() {
+        // A length prefix announcing almost 2 GB
+        let mut decoder = MessageDecoder::new(HubProtocol::MessagePack(Compression::None));
+        let error = decoder.decode(&[0xff, 0xff, 0xff, 0xff, 0x07]).unwrap_err();
+        assert!(error.to_string().starts_with("Record of 2147483647 bytes"), "{}", error);
+
+        // JSON that never reaches its record separator
+        let mut decoder = MessageDecoder::new(HubProtocol::Json);
+        let frame = vec![b' '; MAX_RECORD_LENGTH / 2];
+        assert_eq!(decoder.decode(&frame).unwrap(), vec![]);
+        assert_eq!(decoder.decode(&frame).unwrap(), vec![]);
+        assert!(decoder.decode(b"{").is_err());
+    }
+
+    #[test]
+    fn test_handshake_is_parsed_as_json() {
+        let mut decoder = MessageDecoder::new(HubProtocol::Json);
+        // A payload mentioning "error" is not a failed handshake
+        let signal = HubMessage::Invocation {
+            invocation_id: None,
+            target: "SendSignal".to_string(),
+            arguments: vec![Value::from("error"), Value::from("bob")],
+        };
+        let mut data = b"{}\x1e".to_vec();
+        data.extend(HubProtocol::Json.write_message(&signal).unwrap());
+
+        assert_eq!(decoder.handshake(&data[..1]).unwrap(), None);
+        assert_eq!(decoder.handshake(&data[1..]).unwrap(), Some(HandshakeResponse::default()));
+        assert_eq!(decoder.decode(&[]).unwrap(), vec![signal]);
+
+        let mut decoder = MessageDecoder::new(HubProtocol::Json);
+        let response = decoder.handshake(b"{\"error\":\"Unsupported protocol\"}\x1e").unwrap();
+        assert_eq!(response.unwrap().error.as_deref(), Some("Unsupported protocol"));
+        assert!(decoder.handshake(b"not json\x1e").is_err());
+    }
+
+    #[test]
+    fn test_handshake_request_names_protocol() {
+        let handshake = HubProtocol::MessagePack(Compression::Lz4BlockArray).handshake_request();
+        assert_eq!(handshake, "{\"protocol\":\"messagepack\",\"version\":1}\u{1e}");
+    }
+}
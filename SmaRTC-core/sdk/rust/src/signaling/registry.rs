@@ -0,0 +1,90 @@
+// SmaRTC Rust SDK - Typed hub method registry
+
+use super::HubError;
+use rmpv::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type Handler = Arc<dyn Fn(&[Value]) -> Result<(), HubError> + Send + Sync>;
+
+/// Server-to-client methods and their typed handlers.
+///
+/// Targets are matched case-insensitively, like the SignalR clients do.
+#[derive(Clone, Default)]
+pub struct HandlerRegistry {
+    handlers: HashMap<String, Vec<Handler>>,
+}
+
+impl HandlerRegistry {
+    /// Registers a handler whose arguments are deserialized as `T`, usually a
+    /// tuple such as `(String, String)`
+    pub fn on<T, F>(&mut self, target: &str, handler: F)
+    where
+        T: DeserializeOwned,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let handler: Handler = Arc::new(move |arguments| {
+            handler(from_arguments(arguments)?);
+            Ok(())
+        });
+        self.handlers.entry(target.to_lowercase()).or_default().push(handler);
+    }
+
+    pub fn contains(&self, target: &str) -> bool {
+        self.handlers.contains_key(&target.to_lowercase())
+    }
+
+    /// Calls every handler registered for `target`
+    pub fn dispatch(&self, target: &str, arguments: &[Value]) -> Result<(), HubError> {
+        for handler in self.handlers.get(&target.to_lowercase()).into_iter().flatten() {
+            handler(arguments)?;
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes invocation arguments into `T`
+pub fn from_arguments<T: DeserializeOwned>(arguments: &[Value]) -> Result<T, HubError> {
+    Ok(rmpv::ext::from_value(Value::Array(arguments.to_vec()))?)
+}
+
+/// Serializes a hub method argument
+pub fn to_argument<T: Serialize + ?Sized>(value: &T) -> Result<Value, HubError> {
+    Ok(rmpv::ext::to_value(value)?)
+}
+
+/// Deserializes a hub method result
+pub fn from_result<T: DeserializeOwned>(value: Value) -> Result<T, HubError> {
+    Ok(rmpv::ext::from_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_typed_handler_receives_arguments() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+
+        let mut registry = HandlerRegistry::default();
+        registry.on::<(String, u32), _>("PeerLatency", move |(peer, latency)| {
+            received_clone.lock().unwrap().push((peer, latency));
+        });
+
+        registry.dispatch("peerLatency", &[Value::from("peer-1"), Value::from(42)]).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![("peer-1".to_string(), 42)]);
+    }
+
+    #[test]
+    fn test_mismatched_arguments_are_reported() {
+        let mut registry = HandlerRegistry::default();
+        registry.on::<(String, String), _>("SendSignal", |_| {});
+
+        assert!(registry.dispatch("SendSignal", &[Value::from(1)]).is_err());
+        assert!(registry.dispatch("Unknown", &[]).is_ok());
+    }
+}
@@ -0,0 +1,395 @@
+// SmaRTC Rust SDK - Acknowledged, ordered delivery of room messages over the hub
+
+use super::chunking;
+use super::rooms::{Envelope, EnvelopeKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Retransmission and reordering settings of reliable messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReliableOptions {
+    /// Delay before the first retransmission, doubled after each one
+    pub retransmit: Duration,
+    pub max_retransmit: Duration,
+    /// Messages still unacknowledged after this long are reported with
+    /// `HubEvent::Undelivered`. Covers the default reconnect delays.
+    pub timeout: Duration,
+    /// Messages received ahead of a gap and kept until it is filled, per
+    /// sender
+    pub window: u64,
+}
+
+impl Default for ReliableOptions {
+    fn default() -> Self {
+        Self {
+            retransmit: Duration::from_millis(500),
+            max_retransmit: Duration::from_secs(8),
+            timeout: Duration::from_secs(120),
+            window: 1024,
+        }
+    }
+}
+
+/// Position of a message in the stream of its sender, carried by the
+/// envelope of reliable messages and of their acknowledgements
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Sequence {
+    /// Unique per client, so that receivers notice a sender that restarted
+    pub stream: String,
+    /// From 1, per room
+    pub n: u64,
+    /// Oldest message the sender still retransmits; receivers stop waiting
+    /// for earlier ones. Absent from acknowledgements.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+}
+
+/// A reliable message that some members have not acknowledged yet
+struct Pending {
+    body: String,
+    waiting: BTreeSet<String>,
+    first_sent: Instant,
+    next_retransmit: Instant,
+    retransmits: u32,
+}
+
+/// Messages received from one sender in one room
+struct Incoming {
+    stream: String,
+    expected: u64,
+    /// Received ahead of `expected`
+    ahead: BTreeMap<u64, String>,
+}
+
+/// What a numbered message made deliverable
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Received {
+    /// In order
+    pub messages: Vec<String>,
+    /// Sequence numbers to acknowledge: the messages delivered now, or the
+    /// message itself if it was delivered before. Messages kept ahead of a
+    /// gap are only acknowledged once it is filled, so that their sender
+    /// keeps retransmitting them meanwhile.
+    pub acks: Vec<u64>,
+}
+
+/// A message given up after [`ReliableOptions::timeout`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Undelivered {
+    pub room: String,
+    pub message: String,
+    /// Members that never acknowledged it
+    pub users: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Last sequence number used, per room
+    sent: HashMap<String, u64>,
+    /// By room then sequence number
+    pending: HashMap<String, BTreeMap<u64, Pending>>,
+    /// By room and sender
+    received: HashMap<(String, String), Incoming>,
+}
+
+/// Sequence numbers, acknowledgements, retransmissions and reordering of the
+/// room messages of a [`super::SignalingClient`].
+///
+/// Messages are numbered per room. Each one is retransmitted, with backoff,
+/// until every member known when it was sent acknowledges it, leaves the
+/// room, or the timeout expires; members that are reconnecting are waited
+/// for. Receivers acknowledge every numbered message, drop duplicates and
+/// deliver them in order. Receiving is always on, sending only when enabled
+/// with `SignalingClientBuilder::reliable`.
+pub(crate) struct Reliability {
+    pub options: ReliableOptions,
+    sending: bool,
+    stream: String,
+    state: Mutex<State>,
+}
+
+impl Reliability {
+    pub fn new(options: Option<ReliableOptions>) -> Self {
+        Self {
+            sending: options.is_some(),
+            options: options.unwrap_or_default(),
+            stream: chunking::message_id(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn is_sending(&self) -> bool {
+        self.sending
+    }
+
+    /// Numbers a message for `members`, the other members of `room`. It is
+    /// sent once only when there are none.
+    pub fn send(&self, room: &str, members: BTreeSet<String>, body: &str) -> Envelope {
+        let mut state = self.state.lock().unwrap();
+        let sent = state.sent.entry(room.to_string()).or_default();
+        *sent += 1;
+        let n = *sent;
+        let pending = state.pending.entry(room.to_string()).or_default();
+        if !members.is_empty() {
+            let now = Instant::now();
+            let message = Pending {
+                body: body.to_string(),
+                waiting: members,
+                first_sent: now,
+                next_retransmit: now + self.options.retransmit,
+                retransmits: 0,
+            };
+            pending.insert(n, message);
+        }
+        self.envelope(room, pending, n, body)
+    }
+
+    fn envelope(&self, room: &str, pending: &BTreeMap<u64, Pending>, n: u64, body: &str) -> Envelope {
+        let since = pending.keys().next().map_or(n, |oldest| n.min(*oldest));
+        Envelope {
+            seq: Some(Sequence { stream: self.stream.clone(), n, since: Some(since) }),
+            ..Envelope::new(room, EnvelopeKind::Message, Some(body))
+        }
+    }
+
+    /// `user` acknowledged a message of `room`
+    pub fn acknowledged(&self, room: &str, user: &str, seq: &Sequence) {
+        if seq.stream != self.stream {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let Some(pending) = state.pending.get_mut(room) else {
+            return;
+        };
+        if let Some(message) = pending.get_mut(&seq.n) {
+            message.waiting.remove(user);
+            if message.waiting.is_empty() {
+                pending.remove(&seq.n);
+            }
+        }
+    }
+
+    /// `user` left `room` on purpose: its acknowledgements are no longer
+    /// waited for
+    pub fn forget(&self, room: &str, user: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pending) = state.pending.get_mut(room) {
+            pending.retain(|_, message| {
+                message.waiting.remove(user);
+                !message.waiting.is_empty()
+            });
+        }
+        state.received.remove(&(room.to_string(), user.to_string()));
+    }
+
+    /// This client left `room`: its messages there are no longer sent, nor
+    /// those received there kept
+    pub fn leave(&self, room: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(room);
+        state.received.retain(|(from, _), _| from != room);
+    }
+
+    /// Messages to retransmit now in order, and those given up
+    pub fn due(&self, now: Instant) -> (Vec<Envelope>, Vec<Undelivered>) {
+        let mut state = self.state.lock().unwrap();
+        let (mut due, mut undelivered) = (Vec::new(), Vec::new());
+        for (room, pending) in state.pending.iter_mut() {
+            pending.retain(|_, message| {
+                if now.duration_since(message.first_sent) < self.options.timeout {
+                    return true;
+                }
+                let users = message.waiting.iter().cloned().collect();
+                undelivered.push(Undelivered { room: room.clone(), message: message.body.clone(), users });
+                false
+            });
+            let mut retransmit = Vec::new();
+            for (n, message) in pending.iter_mut().filter(|(_, message)| message.next_retransmit <= now) {
+                message.retransmits += 1;
+                let delay = self.options.retransmit.saturating_mul(1 << message.retransmits.min(16));
+                message.next_retransmit = now + delay.min(self.options.max_retransmit);
+                retransmit.push((*n, message.body.clone()));
+            }
+            due.extend(retransmit.iter().map(|(n, body)| self.envelope(room, pending, *n, body)));
+        }
+        state.pending.retain(|_, pending| !pending.is_empty());
+        (due, undelivered)
+    }
+
+    /// Retransmits everything on the next tick, e.g. once reconnected
+    pub fn retransmit_now(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        for message in state.pending.values_mut().flat_map(|pending| pending.values_mut()) {
+            message.next_retransmit = now;
+        }
+    }
+
+    /// A numbered message from `user`: the messages now deliverable in order,
+    /// or `None` if it is too far ahead to be kept
+    pub fn receive(&self, room: &str, user: &str, seq: &Sequence, body: String) -> Option<Received> {
+        let since = seq.since.unwrap_or(seq.n).min(seq.n);
+        let mut state = self.state.lock().unwrap();
+        let incoming = state
+            .received
+            .entry((room.to_string(), user.to_string()))
+            .or_insert_with(|| Incoming { stream: seq.stream.clone(), expected: since, ahead: BTreeMap::new() });
+        if incoming.stream != seq.stream {
+            // The sender restarted
+            *incoming = Incoming { stream: seq.stream.clone(), expected: since, ahead: BTreeMap::new() };
+        }
+
+        let mut delivered = Vec::new();
+        if since > incoming.expected {
+            // The messages in between were not meant for us, or were given up
+            let ahead = incoming.ahead.split_off(&since);
+            delivered.extend(std::mem::replace(&mut incoming.ahead, ahead));
+            incoming.expected = since;
+        }
+        if seq.n >= incoming.expected.saturating_add(self.options.window) {
+            return None;
+        }
+        let duplicate = seq.n < incoming.expected;
+        if !duplicate {
+            incoming.ahead.entry(seq.n).or_insert(body);
+        }
+        while let Some(body) = incoming.ahead.remove(&incoming.expected) {
+            delivered.push((incoming.expected, body));
+            incoming.expected += 1;
+        }
+
+        let (mut acks, messages): (Vec<u64>, Vec<String>) = delivered.into_iter().unzip();
+        if duplicate {
+            // Its acknowledgement was lost
+            acks.push(seq.n);
+        }
+        Some(Received { messages, acks })
+    }
+
+    /// Forgets everything, e.g. on disconnect
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.received.clear();
+    }
+}
+
+/// Acknowledgement of message `n` of `stream`, sent to its room
+pub(crate) fn ack(room: &str, stream: &str, n: u64) -> Envelope {
+    Envelope {
+        seq: Some(Sequence { stream: stream.to_string(), n, since: None }),
+        ..Envelope::new(room, EnvelopeKind::Ack, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(users: &[&str]) -> BTreeSet<String> {
+        users.iter().map(|user| user.to_string()).collect()
+    }
+
+    #[test]
+    fn test_messages_are_retransmitted_until_acknowledged() {
+        let options = ReliableOptions {
+            retransmit: Duration::from_millis(100),
+            max_retransmit: Duration::from_millis(300),
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let reliable = Reliability::new(Some(options));
+        let first = reliable.send("room", members(&["bob", "carol"]), "mute");
+        let seq = first.seq.clone().unwrap();
+        assert_eq!((seq.n, seq.since), (1, Some(1)));
+        // Nobody to wait for
+        let alone = reliable.send("other", BTreeSet::new(), "hello").seq.unwrap();
+        assert_eq!((alone.n, alone.since), (1, Some(1)));
+
+        let start = Instant::now();
+        assert!(reliable.due(start).0.is_empty());
+        let (due, _) = reliable.due(start + Duration::from_millis(100));
+        assert_eq!(due, vec![first.clone()]);
+        // Backoff: 200 ms, then capped at 300 ms
+        assert!(reliable.due(start + Duration::from_millis(250)).0.is_empty());
+        assert_eq!(reliable.due(start + Duration::from_millis(300)).0.len(), 1);
+        assert!(reliable.due(start + Duration::from_millis(550)).0.is_empty());
+        assert_eq!(reliable.due(start + Duration::from_millis(600)).0.len(), 1);
+
+        // Later messages tell receivers what is still retransmitted
+        reliable.acknowledged("room", "bob", &seq);
+        let second = reliable.send("room", members(&["bob"]), "unmute").seq.unwrap();
+        assert_eq!((second.n, second.since), (2, Some(1)));
+        reliable.forget("room", "carol");
+        reliable.acknowledged("room", "bob", &Sequence { stream: "other".into(), ..second.clone() });
+        let (due, _) = reliable.due(start + Duration::from_millis(900));
+        assert_eq!(due.iter().map(|envelope| envelope.seq.clone().unwrap().since).collect::<Vec<_>>(), [Some(2)]);
+
+        let (due, undelivered) = reliable.due(start + Duration::from_secs(2));
+        assert!(due.is_empty());
+        let unmute = Undelivered { room: "room".into(), message: "unmute".into(), users: vec!["bob".into()] };
+        assert_eq!(undelivered, vec![unmute]);
+    }
+
+    #[test]
+    fn test_messages_are_delivered_once_and_in_order() {
+        let reliable = Reliability::new(None);
+        let seq = |stream: &str, n: u64, since: u64| Sequence { stream: stream.into(), n, since: Some(since) };
+        let receive = |seq: Sequence, body: &str| {
+            let received = reliable.receive("room", "bob", &seq, body.to_string())?;
+            Some((received.messages, received.acks))
+        };
+        let messages = |messages: &[&str]| messages.iter().map(|message| message.to_string()).collect::<Vec<_>>();
+
+        // Messages ahead of a gap are acknowledged once delivered
+        assert_eq!(receive(seq("a", 3, 2), "3"), Some((vec![], vec![])));
+        assert_eq!(receive(seq("a", 2, 2), "2"), Some((messages(&["2", "3"]), vec![2, 3])));
+        assert_eq!(receive(seq("a", 3, 2), "3"), Some((vec![], vec![3])));
+        assert_eq!(receive(seq("a", 5, 4), "5"), Some((vec![], vec![])));
+        // The sender gave up on 4
+        assert_eq!(receive(seq("a", 6, 5), "6"), Some((messages(&["5", "6"]), vec![5, 6])));
+        assert_eq!(receive(seq("a", 5000, 7), "far"), None);
+
+        // Other senders have their own streams, restarted ones start over
+        let carol = reliable.receive("room", "carol", &seq("c", 9, 9), "9".into()).unwrap();
+        assert_eq!((carol.messages, carol.acks), (messages(&["9"]), vec![9]));
+        assert_eq!(receive(seq("b", 1, 1), "restart"), Some((messages(&["restart"]), vec![1])));
+
+        // Nothing is kept for members that left
+        reliable.forget("room", "bob");
+        assert_eq!(receive(seq("b", 1, 1), "again"), Some((messages(&["again"]), vec![1])));
+        reliable.leave("room");
+        assert_eq!(receive(seq("b", 1, 1), "rejoined"), Some((messages(&["rejoined"]), vec![1])));
+    }
+
+    #[test]
+    fn test_late_joiners_acknowledge_only_delivered_messages() {
+        let options = ReliableOptions { retransmit: Duration::from_millis(100), ..Default::default() };
+        let (sender, receiver) = (Reliability::new(Some(options)), Reliability::new(None));
+
+        // dave joins while carol has yet to acknowledge the first message
+        let first = sender.send("room", members(&["carol"]), "first").seq.unwrap();
+        let second = sender.send("room", members(&["carol", "dave"]), "second");
+        let seq = second.seq.clone().unwrap();
+        assert_eq!((seq.n, seq.since), (2, Some(1)));
+
+        // dave waits for the first message, and must not stop its sender
+        // from retransmitting the second one
+        let received = receiver.receive("room", "alice", &seq, "second".into()).unwrap();
+        assert_eq!(received, Received::default());
+        sender.acknowledged("room", "carol", &first);
+        sender.acknowledged("room", "carol", &seq);
+        let (due, _) = sender.due(Instant::now() + Duration::from_millis(100));
+        assert_eq!(due.len(), 1);
+
+        // Once carol is done, the retransmission lets dave skip the first one
+        let seq = due[0].seq.clone().unwrap();
+        assert_eq!((seq.n, seq.since), (2, Some(2)));
+        let received = receiver.receive("room", "alice", &seq, "second".into()).unwrap();
+        assert_eq!(received, Received { messages: vec!["second".into()], acks: vec![2] });
+        sender.acknowledged("room", "dave", &seq);
+        assert_eq!(sender.due(Instant::now() + Duration::from_secs(10)), (vec![], vec![]));
+    }
+}
@@ -0,0 +1,287 @@
+// SmaRTC Rust SDK - Rooms joined on a shared hub connection
+
+use super::chunking::{self, ChunkOptions};
+use super::connection::HubConnection;
+use super::hubs::{SignalHub, SignalHubProxy};
+use super::outbox::{self, Outbox};
+use super::reliable::{Reliability, Sequence};
+use super::HubError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+/// Version of the room envelope, see [`Envelope`]
+pub const ENVELOPE_VERSION: u32 = 1;
+
+pub(crate) type SharedConnection = Arc<Mutex<Option<Arc<HubConnection>>>>;
+pub(crate) type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+
+/// Identity and known members of a joined room
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Room {
+    pub username: String,
+    pub members: BTreeSet<String>,
+    /// Members known before the connection dropped that have not answered
+    /// the rejoin yet, until `previous_until`
+    pub previous: BTreeSet<String>,
+    pub previous_until: Option<Instant>,
+}
+
+impl Room {
+    /// Members seen since the last rejoin, and those still expected back
+    pub fn recipients(&self) -> BTreeSet<String> {
+        self.members.union(&self.previous).cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum EnvelopeKind {
+    Message,
+    /// Sent after `JoinSession`; members answer with `Present`
+    Join,
+    Present,
+    Leave,
+    /// Acknowledges a numbered message, see [`super::reliable`]
+    Ack,
+    /// Sent by newer SDKs, ignored
+    #[serde(other)]
+    Unknown,
+}
+
+/// Room-tagging envelope carried as the `signal` of `SendSignalToSession`.
+///
+/// The hub relays `SendSignal(signal, user)` without the session id, so a
+/// client in several rooms could not tell them apart otherwise. Presence is
+/// announced the same way since `NewUserArrived` and `UserLeft` have no
+/// session id either. Signals that are not envelopes come from other SDKs and
+/// are passed through unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    pub smartc: u32,
+    pub room: String,
+    pub kind: EnvelopeKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Set on reliable messages and their acknowledgements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<Sequence>,
+}
+
+impl Envelope {
+    pub fn new(room: &str, kind: EnvelopeKind, body: Option<&str>) -> Self {
+        Self {
+            smartc: ENVELOPE_VERSION,
+            room: room.to_string(),
+            kind,
+            body: body.map(str::to_string),
+            seq: None,
+        }
+    }
+
+    pub fn parse(signal: &str) -> Option<Self> {
+        serde_json::from_str::<Self>(signal)
+            .ok()
+            .filter(|envelope| envelope.smartc == ENVELOPE_VERSION)
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+pub(crate) async fn signal_hub(connection: &SharedConnection) -> Result<SignalHubProxy, HubError> {
+    let connection = connection.lock().await.clone();
+    connection.map(SignalHubProxy::new).ok_or(HubError::NotConnected)
+}
+
+/// Sends an envelope to the other members of `room`, in chunks if too large
+pub(crate) async fn announce(
+    hub: &SignalHubProxy,
+    chunking: &ChunkOptions,
+    room: &str,
+    username: &str,
+    kind: EnvelopeKind,
+    body: Option<&str>,
+) -> Result<(), HubError> {
+    send_envelope(hub, chunking, &Envelope::new(room, kind, body), username).await
+}
+
+pub(crate) async fn send_envelope(
+    hub: &SignalHubProxy,
+    chunking: &ChunkOptions,
+    envelope: &Envelope,
+    username: &str,
+) -> Result<(), HubError> {
+    let (room, signal) = (&envelope.room, envelope.encode());
+    for part in chunking::split(&signal, chunking) {
+        hub.send_signal_to_session(room, &part, username).await?;
+    }
+    Ok(())
+}
+
+/// A room joined by [`super::SignalingClient::join_room`], with the identity used
+/// in that room
+#[derive(Clone)]
+pub struct RoomHandle {
+    room: String,
+    username: String,
+    connection: SharedConnection,
+    rooms: Rooms,
+    chunking: ChunkOptions,
+    reliable: Arc<Reliability>,
+    outbox: Arc<Outbox>,
+}
+
+impl RoomHandle {
+    pub(crate) fn new(
+        room: &str,
+        username: &str,
+        connection: SharedConnection,
+        rooms: Rooms,
+        chunking: ChunkOptions,
+        reliable: Arc<Reliability>,
+        outbox: Arc<Outbox>,
+    ) -> Self {
+        Self {
+            room: room.to_string(),
+            username: username.to_string(),
+            connection,
+            rooms,
+            chunking,
+            reliable,
+            outbox,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.room
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Sends a chat message to the other members of this room. Messages of
+    /// any size are accepted: those above the hub limit travel in chunks.
+    ///
+    /// With `SignalingClientBuilder::reliable`, the message is retransmitted
+    /// until the current members acknowledge it, even if they or this client
+    /// reconnect in the meantime; see [`HubEvent::Undelivered`].
+    ///
+    /// With `SignalingClientBuilder::outbox`, messages sent while the
+    /// connection is down are queued and sent once the room is rejoined; see
+    /// [`HubEvent::Outbox`].
+    ///
+    /// [`HubEvent::Undelivered`]: super::HubEvent::Undelivered
+    /// [`HubEvent::Outbox`]: super::HubEvent::Outbox
+    pub async fn send(&self, message: &str) -> Result<(), HubError> {
+        if !self.outbox.is_enabled() {
+            return self.transmit(message).await;
+        }
+        // Behind the messages already queued, to keep the order. Rooms left
+        // or never joined have nothing to wait for.
+        let joined = self.rooms.lock().await.contains_key(&self.room);
+        if self.outbox.is_empty() || !joined {
+            match self.transmit(message).await {
+                Err(e) if joined && outbox::is_offline(&e) => {}
+                result => return result,
+            }
+        }
+        self.outbox.push(&self.room, message)?;
+        let (connection, rooms) = (self.connection.clone(), self.rooms.clone());
+        let (chunking, reliable, outbox) = (self.chunking.clone(), self.reliable.clone(), self.outbox.clone());
+        tokio::spawn(async move { flush_outbox(&connection, &rooms, &chunking, &reliable, &outbox).await });
+        Ok(())
+    }
+
+    async fn transmit(&self, message: &str) -> Result<(), HubError> {
+        let hub = signal_hub(&self.connection).await?;
+        if !self.reliable.is_sending() {
+            return announce(&hub, &self.chunking, &self.room, &self.username, EnvelopeKind::Message, Some(message))
+                .await;
+        }
+        let members = {
+            let rooms = self.rooms.lock().await;
+            rooms.get(&self.room).map(Room::recipients).unwrap_or_default()
+        };
+        let waiting = !members.is_empty();
+        let envelope = self.reliable.send(&self.room, members, message);
+        match send_envelope(&hub, &self.chunking, &envelope, &self.username).await {
+            // Retransmitted anyway: reporting the error would get it sent twice
+            Err(_) if waiting => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Leaves the room; the other rooms of the connection are unaffected
+    pub async fn leave(&self) -> Result<(), HubError> {
+        let hub = signal_hub(&self.connection).await?;
+        announce(&hub, &self.chunking, &self.room, &self.username, EnvelopeKind::Leave, None).await?;
+        hub.leave_session(&self.room, &self.username).await?;
+        self.rooms.lock().await.remove(&self.room);
+        self.reliable.leave(&self.room);
+        self.outbox.cancel(Some(&self.room));
+        Ok(())
+    }
+
+    /// Members seen in this room, or still expected back after a reconnect,
+    /// this client's identity included
+    pub async fn members(&self) -> Vec<String> {
+        let rooms = self.rooms.lock().await;
+        let mut members = rooms.get(&self.room).map(Room::recipients).unwrap_or_default();
+        members.insert(self.username.clone());
+        members.into_iter().collect()
+    }
+}
+
+/// Sends the messages queued in the outbox in order, until the connection
+/// fails again; those of rooms no longer joined are cancelled
+pub(crate) async fn flush_outbox(
+    connection: &SharedConnection,
+    rooms: &Rooms,
+    chunking: &ChunkOptions,
+    reliable: &Arc<Reliability>,
+    outbox: &Arc<Outbox>,
+) {
+    let _flushing = outbox.flushing().await;
+    while let Some(queued) = outbox.front() {
+        let username = rooms.lock().await.get(&queued.room).map(|room| room.username.clone());
+        let Some(username) = username else {
+            outbox.cancel(Some(&queued.room));
+            continue;
+        };
+        let room = RoomHandle::new(
+            &queued.room,
+            &username,
+            connection.clone(),
+            rooms.clone(),
+            chunking.clone(),
+            reliable.clone(),
+            outbox.clone(),
+        );
+        if room.transmit(&queued.message).await.is_err() {
+            break;
+        }
+        outbox.sent(queued.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let envelope = Envelope::new("support-1", EnvelopeKind::Message, Some("hello"));
+        let signal = envelope.encode();
+        assert_eq!(signal, r#"{"smartc":1,"room":"support-1","kind":"message","body":"hello"}"#);
+        assert_eq!(Envelope::parse(&signal), Some(envelope));
+
+        // Plain chat messages, even JSON ones, are not envelopes
+        assert_eq!(Envelope::parse("hello"), None);
+        assert_eq!(Envelope::parse(r#"{"room":"support-1"}"#), None);
+    }
+}
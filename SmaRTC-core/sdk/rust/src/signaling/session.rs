@@ -0,0 +1,237 @@
+// SmaRTC Rust SDK - Session client working with every SmaRTC hub
+
+use super::chunking::{self, ChunkOptions, Reassembler};
+use super::connection::{ConnectOptions, HubConnection};
+use super::dialect::{self, HubDialect, PeerEvent, SignalTarget};
+use super::HubError;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::AbortHandle;
+
+/// Joins a session and exchanges signals through whichever hub the server
+/// runs, see [`HubDialect`]. Unlike [`super::SignalingClient`] it does not
+/// reconnect. Signals above the hub limit are sent in chunks and put back
+/// together on reception; incomplete ones are dropped.
+pub struct SessionClient {
+    dialect: Arc<dyn HubDialect>,
+    connection: Arc<HubConnection>,
+    chunking: ChunkOptions,
+    /// `(session id, username)` once joined
+    joined: Mutex<Option<(String, String)>>,
+    tasks: Vec<AbortHandle>,
+}
+
+impl SessionClient {
+    /// Detects the hub behind `hub_url` and connects with its protocol;
+    /// `options.protocol` is ignored
+    pub async fn connect(
+        hub_url: &str,
+        options: ConnectOptions,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<PeerEvent>), HubError> {
+        let dialect = dialect::detect(hub_url).await?;
+        Self::connect_with(hub_url, dialect, options).await
+    }
+
+    /// Connects to a hub whose dialect is already known
+    pub async fn connect_with(
+        hub_url: &str,
+        dialect: Arc<dyn HubDialect>,
+        options: ConnectOptions,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<PeerEvent>), HubError> {
+        let options = ConnectOptions { protocol: dialect.protocol(), ..options };
+        let (connection, mut invocations) = HubConnection::connect(hub_url, &options).await?;
+        let connection = Arc::new(connection);
+        let (events_tx, events) = mpsc::unbounded_channel();
+
+        let decoder = dialect.clone();
+        let mut chunks = Reassembler::new(options.chunking.clone());
+        let mut tasks = vec![tokio::spawn(async move {
+            let mut expiry = tokio::time::interval(super::HOUSEKEEPING);
+            loop {
+                let invocation = tokio::select! {
+                    invocation = invocations.recv() => invocation,
+                    _ = expiry.tick() => {
+                        chunks.expire();
+                        continue;
+                    }
+                };
+                let Some(invocation) = invocation else {
+                    break;
+                };
+                chunks.expire();
+                let event = match decoder.decode(&invocation) {
+                    Some(PeerEvent::Signal { from, signal, relayed }) => match chunks.push(&from.id, signal) {
+                        Ok(Some(signal)) => PeerEvent::Signal { from, signal, relayed },
+                        Ok(None) | Err(_) => continue,
+                    },
+                    Some(event) => event,
+                    None => continue,
+                };
+                let _ = events_tx.send(event);
+            }
+        })
+        .abort_handle()];
+
+        if let Some(interval) = dialect.heartbeat_interval() {
+            let dialect = dialect.clone();
+            let connection = connection.clone();
+            tasks.push(
+                tokio::spawn(async move {
+                    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+                    loop {
+                        ticks.tick().await;
+                        if dialect.heartbeat(&connection).await.is_err() {
+                            break;
+                        }
+                    }
+                })
+                .abort_handle(),
+            );
+        }
+
+        let client = Self {
+            chunking: chunk_options(dialect.as_ref(), options.chunking),
+            dialect,
+            connection,
+            joined: Mutex::new(None),
+            tasks,
+        };
+        Ok((client, events))
+    }
+
+    pub fn dialect(&self) -> &dyn HubDialect {
+        self.dialect.as_ref()
+    }
+
+    /// Joins `session_id`, leaving the previous session if any
+    pub async fn join(&self, session_id: &str, username: &str) -> Result<(), HubError> {
+        let mut joined = self.joined.lock().await;
+        if let Some((session_id, username)) = joined.take() {
+            self.dialect.leave(&self.connection, &session_id, &username).await?;
+        }
+        self.dialect.join(&self.connection, session_id, username).await?;
+        *joined = Some((session_id.to_string(), username.to_string()));
+        Ok(())
+    }
+
+    /// Id the other members see in [`PeerEvent`]s from this client, once joined
+    pub async fn local_peer_id(&self) -> Option<String> {
+        let joined = self.joined.lock().await;
+        let (_, username) = joined.as_ref()?;
+        self.dialect.local_peer_id(self.connection.connection_id(), username)
+    }
+
+    pub async fn leave(&self) -> Result<(), HubError> {
+        let Some((session_id, username)) = self.joined.lock().await.take() else {
+            return Ok(());
+        };
+        self.dialect.leave(&self.connection, &session_id, &username).await
+    }
+
+    /// Sends a signal within the joined session, whatever its size
+    pub async fn signal(&self, target: SignalTarget<'_>, signal: &str) -> Result<(), HubError> {
+        let (session_id, username) = self
+            .joined
+            .lock()
+            .await
+            .clone()
+            .ok_or(HubError::NotInRoom)?;
+        for part in chunking::split(signal, &self.chunking) {
+            self.dialect.signal(&self.connection, &session_id, &username, target, &part).await?;
+        }
+        Ok(())
+    }
+
+    /// Leaves the session and closes the connection
+    pub async fn close(&self) -> Result<(), HubError> {
+        let left = self.leave().await;
+        for task in &self.tasks {
+            task.abort();
+        }
+        left.and(self.connection.close().await)
+    }
+}
+
+impl Drop for SessionClient {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Chunks small enough for the hub of `dialect`
+fn chunk_options(dialect: &dyn HubDialect, options: ChunkOptions) -> ChunkOptions {
+    let max_size = options.max_size.min(chunking::max_size_within(dialect.max_message_size()));
+    ChunkOptions { max_size, ..options }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signaling::dialect::{DialectKind, Peer};
+    use crate::signaling::protocol::{HubMessage, HubProtocol};
+    use crate::signaling::testing::MockHub;
+    use rmpv::Value;
+
+    #[tokio::test]
+    async fn test_simple_hub_is_detected_and_spoken() {
+        let mut server = MockHub::start(HubProtocol::Json).await;
+        server.set_info("SmaRTC Demo Server Running!");
+
+        let (client, mut events) = SessionClient::connect(&server.url, ConnectOptions::default()).await.unwrap();
+        assert_eq!(client.dialect().kind(), DialectKind::Simple);
+
+        let join = tokio::spawn(async move {
+            client.join("room", "alice").await.unwrap();
+            client
+        });
+        let HubMessage::Invocation { invocation_id, target, arguments } = server.recv().await else {
+            panic!("expected an invocation");
+        };
+        assert_eq!((target.as_str(), arguments.len()), ("JoinSession", 2));
+        server.complete(&invocation_id.unwrap(), None);
+        let client = join.await.unwrap();
+        assert_eq!(client.local_peer_id().await.as_deref(), Some("conn-1"));
+
+        // Existing members are announced to the newcomer
+        server.send(HubMessage::Invocation {
+            invocation_id: None,
+            target: "UserJoined".to_string(),
+            arguments: vec![Value::Map(vec![
+                (Value::from("username"), Value::from("Existing User")),
+                (Value::from("connectionId"), Value::from("conn-b")),
+            ])],
+        });
+        let bob = Peer { id: "conn-b".to_string(), username: Some("Existing User".to_string()) };
+        assert_eq!(events.recv().await, Some(PeerEvent::Joined(bob)));
+
+        // Simple hubs cannot broadcast
+        assert!(matches!(client.signal(SignalTarget::Session, "{}").await, Err(HubError::Unsupported(_))));
+
+        let signal = tokio::spawn(async move { client.signal(SignalTarget::Peer("conn-b"), "offer").await });
+        let HubMessage::Invocation { invocation_id, target, arguments } = server.recv().await else {
+            panic!("expected an invocation");
+        };
+        assert_eq!(target, "SendSignal");
+        assert_eq!(arguments, vec![Value::from("conn-b"), Value::from("offer")]);
+        server.complete(&invocation_id.unwrap(), None);
+        signal.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_signals_fit_the_limit_of_the_hub() {
+        use crate::signaling::dialect::{SignalHubDialect, ZeroCostDialect};
+
+        assert_eq!(chunk_options(&SignalHubDialect, ChunkOptions::default()), ChunkOptions::default());
+        let zerocost = chunk_options(&ZeroCostDialect, ChunkOptions::default());
+        assert_eq!(zerocost.max_size, 768);
+        let offer = format!(r#"{{"type":"offer","sdp":"{}"}}"#, "a=candidate:1 1 udp 2122260223\r\n".repeat(100));
+        let chunks = chunking::split(&offer, &zerocost);
+        assert!(chunks.len() > 1);
+        for chunk in chunks {
+            let invocation = serde_json::to_string(&[chunk]).unwrap();
+            assert!(invocation.len() <= zerocost.max_size, "{} bytes", invocation.len());
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// SmaRTC Rust SDK - In-process SignalR hub used by the SDK tests
+
+use super::protocol::{HubMessage, HubProtocol, RECORD_SEPARATOR};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+
+const NEGOTIATE_RESPONSE: &str = r#"{"negotiateVersion":1,"connectionId":"conn-1","connectionToken":"token-1","availableTransports":[{"transport":"WebSockets","transferFormats":["Text","Binary"]}]}"#;
+
+/// An HTTP request received by the mock hub, negotiate or WebSocket upgrade
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    pub uri: String,
+    pub authorization: Option<String>,
+}
+
+/// Accepts hub connections on a loopback port; the test drives the server
+/// side of the most recent connection.
+pub struct MockHub {
+    pub url: String,
+    protocol: HubProtocol,
+    /// Body served on `/`, see [`Self::set_info`]
+    info: Arc<Mutex<String>>,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    incoming: mpsc::UnboundedReceiver<HubMessage>,
+    outgoing: mpsc::UnboundedSender<Option<Message>>,
+}
+
+impl MockHub {
+    pub async fn start(protocol: HubProtocol) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/signalhub", listener.local_addr().unwrap());
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<Option<Message>>();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        let info = Arc::new(Mutex::new("Hello World!".to_string()));
+        let banner = info.clone();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut method = [0; 6];
+                if stream.peek(&mut method).await.is_err() {
+                    continue;
+                }
+                if method.starts_with(b"POST") {
+                    let (stream, request) = read_request(stream).await;
+                    log.lock().unwrap().push(request);
+                    respond(stream, "application/json", NEGOTIATE_RESPONSE).await;
+                    continue;
+                }
+                if &method == b"GET / " {
+                    let (stream, _) = read_request(stream).await;
+                    let body = banner.lock().unwrap().clone();
+                    let content_type = if body.starts_with('{') { "application/json" } else { "text/plain" };
+                    respond(stream, content_type, &body).await;
+                    continue;
+                }
+
+                // The callback signature is dictated by tungstenite
+                #[allow(clippy::result_large_err)]
+                let record = |request: &Request, response: Response| {
+                    log.lock().unwrap().push(MockRequest {
+                        uri: request.uri().to_string(),
+                        authorization: request
+                            .headers()
+                            .get("authorization")
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string),
+                    });
+                    Ok(response)
+                };
+                let Ok(ws) = tokio_tungstenite::accept_hdr_async(stream, record).await else {
+                    continue;
+                };
+                let (mut write, mut read) = ws.split();
+
+                // Handshake request, then an empty handshake response
+                let _ = read.next().await;
+                let _ = write.send(Message::Text(format!("{{}}{}", RECORD_SEPARATOR))).await;
+
+                loop {
+                    tokio::select! {
+                        msg = read.next() => {
+                            let data = match msg {
+                                Some(Ok(Message::Text(text))) => text.into_bytes(),
+                                Some(Ok(Message::Binary(data))) => data,
+                                Some(Ok(_)) => continue,
+                                _ => break,
+                            };
+                            for message in protocol.parse_messages(&data).unwrap_or_default() {
+                                if message != HubMessage::Ping {
+                                    let _ = incoming_tx.send(message);
+                                }
+                            }
+                        }
+                        out = outgoing_rx.recv() => match out {
+                            Some(Some(frame)) => {
+                                let _ = write.send(frame).await;
+                            }
+                            // `None` drops the current connection
+                            _ => break,
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            url,
+            protocol,
+            info,
+            requests,
+            incoming,
+            outgoing,
+        }
+    }
+
+    /// Changes what `/` answers, `Program.cs`'s banner by default
+    pub fn set_info(&self, body: &str) {
+        *self.info.lock().unwrap() = body.to_string();
+    }
+
+    /// HTTP requests received so far, in order
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Next message sent by the client, pings excluded
+    pub async fn recv(&mut self) -> HubMessage {
+        self.incoming.recv().await.expect("mock hub stopped")
+    }
+
+    pub fn send(&self, message: HubMessage) {
+        let data = self.protocol.write_message(&message).unwrap();
+        let frame = if self.protocol.is_binary() {
+            Message::Binary(data)
+        } else {
+            Message::Text(String::from_utf8(data).unwrap())
+        };
+        let _ = self.outgoing.send(Some(frame));
+    }
+
+    /// Completes an invocation with an optional result
+    pub fn complete(&self, invocation_id: &str, result: Option<rmpv::Value>) {
+        self.send(HubMessage::Completion {
+            invocation_id: invocation_id.to_string(),
+            result,
+            error: None,
+        });
+    }
+
+    /// Drops the current connection without a close handshake
+    pub fn drop_connection(&self) {
+        let _ = self.outgoing.send(None);
+    }
+}
+
+/// Reads a plain HTTP request up to its headers
+async fn read_request(stream: TcpStream) -> (TcpStream, MockRequest) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    let _ = reader.read_line(&mut line).await;
+    let uri = line.split_whitespace().nth(1).unwrap_or_default().to_string();
+
+    let mut authorization = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await.unwrap_or(0) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+    (reader.into_inner(), MockRequest { uri, authorization })
+}
+
+async fn respond(mut stream: TcpStream, content_type: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
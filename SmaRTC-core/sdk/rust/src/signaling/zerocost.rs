@@ -0,0 +1,410 @@
+// SmaRTC Rust SDK - Client for the ZeroCostWebRtcHub
+
+use super::auth::AccessTokenFactory;
+use super::connection::{ConnectOptions, HubConnection};
+use super::events;
+use super::hubs::{ZeroCostHub, ZeroCostHubProxy};
+use super::outbound::{OutboundConfig, OutboundPolicy};
+use super::protocol::{self, Compression, HubProtocol};
+use super::HubError;
+use futures_util::Stream;
+use rmpv::Value;
+use serde::Deserialize;
+use serde_bytes::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+
+/// The hub drops connections idle for 2 minutes; it expects a heartbeat every 30s
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Events kept for slow subscribers of [`ZeroCostClient::events`]
+const EVENT_CAPACITY: usize = 256;
+
+/// Decoded `UserJoinedMessage` / `UserLeftMessage`; `SimpleWebRtcHub` sends
+/// the same fields as an object
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerInfo {
+    pub username: String,
+    pub connection_id: String,
+}
+
+impl PeerInfo {
+    /// Decodes the MessagePack DTO (`[Key(0)] Username`, `[Key(1)] ConnectionId`)
+    /// carried as a `byte[]` event argument
+    pub fn decode(value: &Value) -> Option<Self> {
+        let bytes = protocol::as_bytes(value)?;
+        let message = rmpv::decode::read_value(&mut &bytes[..]).ok()?;
+        let fields = message.as_array()?;
+        Some(Self {
+            username: fields.first()?.as_str()?.to_string(),
+            connection_id: fields.get(1)?.as_str()?.to_string(),
+        })
+    }
+}
+
+/// Result of `GetSessionStats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStats {
+    #[serde(alias = "SessionId")]
+    pub session_id: String,
+    #[serde(alias = "ConnectionCount")]
+    pub connection_count: i32,
+    #[serde(alias = "RelayNodeCount", default)]
+    pub relay_node_count: i32,
+}
+
+/// Result of `GetGlobalStats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalStats {
+    #[serde(alias = "TotalConnections")]
+    pub total_connections: i32,
+    #[serde(alias = "TotalSessions")]
+    pub total_sessions: i32,
+    #[serde(alias = "MemoryUsageBytes")]
+    pub memory_usage_bytes: i64,
+    #[serde(alias = "PooledConnectionsAvailable")]
+    pub pooled_connections_available: i32,
+}
+
+pub type SignalCallback = Arc<dyn Fn(String, Vec<u8>) + Send + Sync>;
+pub type PeerCallback = Arc<dyn Fn(PeerInfo) + Send + Sync>;
+
+/// Events of a [`ZeroCostClient`], also passed to the callbacks of
+/// [`ZeroCostClientBuilder`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZeroCostEvent {
+    /// `ReceiveSignal(fromConnectionId, data)`
+    Signal { from: String, data: Vec<u8> },
+    /// `RelaySignal(fromConnectionId, data)`, sent to relay nodes of large sessions
+    RelaySignal { from: String, data: Vec<u8> },
+    UserJoined(PeerInfo),
+    UserLeft(PeerInfo),
+    /// The connection closed; the client does not reconnect
+    Disconnected,
+    /// This stream fell too far behind and `missed` events were dropped
+    Lagged { missed: u64 },
+}
+
+impl ZeroCostEvent {
+    /// Translates a server invocation; `None` for targets of no interest
+    fn decode(target: &str, args: &[Value]) -> Option<Self> {
+        match target {
+            "ReceiveSignal" | "RelaySignal" => {
+                let from = args.first()?.as_str()?.to_string();
+                let data = protocol::as_bytes(args.get(1)?)?;
+                Some(match target {
+                    "ReceiveSignal" => ZeroCostEvent::Signal { from, data },
+                    _ => ZeroCostEvent::RelaySignal { from, data },
+                })
+            }
+            "UserJoined" => Some(ZeroCostEvent::UserJoined(PeerInfo::decode(args.first()?)?)),
+            "UserLeft" => Some(ZeroCostEvent::UserLeft(PeerInfo::decode(args.first()?)?)),
+            _ => None,
+        }
+    }
+}
+
+/// Callbacks registered through [`ZeroCostClientBuilder`]
+#[derive(Clone, Default)]
+struct ZeroCostHandlers {
+    on_signal: Option<SignalCallback>,
+    on_relay_signal: Option<SignalCallback>,
+    on_user_joined: Option<PeerCallback>,
+    on_user_left: Option<PeerCallback>,
+}
+
+impl ZeroCostHandlers {
+    fn dispatch(&self, event: &ZeroCostEvent) {
+        match event {
+            ZeroCostEvent::Signal { from, data } => {
+                if let Some(cb) = &self.on_signal {
+                    cb(from.clone(), data.clone());
+                }
+            }
+            ZeroCostEvent::RelaySignal { from, data } => {
+                if let Some(cb) = &self.on_relay_signal {
+                    cb(from.clone(), data.clone());
+                }
+            }
+            ZeroCostEvent::UserJoined(peer) => {
+                if let Some(cb) = &self.on_user_joined {
+                    cb(peer.clone());
+                }
+            }
+            ZeroCostEvent::UserLeft(peer) => {
+                if let Some(cb) = &self.on_user_left {
+                    cb(peer.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Registers handlers before the client connects
+pub struct ZeroCostClientBuilder {
+    hub_url: String,
+    handlers: ZeroCostHandlers,
+    options: ConnectOptions,
+}
+
+impl ZeroCostClientBuilder {
+    pub fn new(hub_url: &str) -> Self {
+        Self {
+            hub_url: hub_url.to_string(),
+            handlers: ZeroCostHandlers::default(),
+            options: ConnectOptions::default(),
+        }
+    }
+
+    /// `ReceiveSignal(fromConnectionId, data)`
+    pub fn on_signal(mut self, callback: impl Fn(String, Vec<u8>) + Send + Sync + 'static) -> Self {
+        self.handlers.on_signal = Some(Arc::new(callback));
+        self
+    }
+
+    /// `RelaySignal(fromConnectionId, data)`, sent to relay nodes of large sessions
+    pub fn on_relay_signal(mut self, callback: impl Fn(String, Vec<u8>) + Send + Sync + 'static) -> Self {
+        self.handlers.on_relay_signal = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn on_user_joined(mut self, callback: impl Fn(PeerInfo) + Send + Sync + 'static) -> Self {
+        self.handlers.on_user_joined = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn on_user_left(mut self, callback: impl Fn(PeerInfo) + Send + Sync + 'static) -> Self {
+        self.handlers.on_user_left = Some(Arc::new(callback));
+        self
+    }
+
+    /// Bearer token of the connection, see `auth::smartc_token`
+    pub fn access_token_factory(mut self, factory: AccessTokenFactory) -> Self {
+        self.options.access_token_factory = Some(factory);
+        self
+    }
+
+    /// What happens when messages are sent faster than the socket accepts
+    /// them, and how many may wait; see [`OutboundPolicy`]
+    pub fn outbound_policy(mut self, policy: OutboundPolicy, capacity: usize) -> Self {
+        self.options.outbound = OutboundConfig { policy, capacity };
+        self
+    }
+
+    pub fn build(self) -> ZeroCostClient {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        ZeroCostClient {
+            hub_url: self.hub_url,
+            handlers: self.handlers,
+            options: self.options,
+            connection: Arc::new(Mutex::new(None)),
+            tasks: Mutex::new(Vec::new()),
+            events,
+        }
+    }
+}
+
+/// Typed client for `ZeroCostWebRtcHub` (`Program.Optimized.cs`)
+pub struct ZeroCostClient {
+    hub_url: String,
+    handlers: ZeroCostHandlers,
+    options: ConnectOptions,
+    connection: Arc<Mutex<Option<Arc<HubConnection>>>>,
+    /// Event loop and heartbeat of the current connection
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    events: broadcast::Sender<ZeroCostEvent>,
+}
+
+impl ZeroCostClient {
+    pub fn new(hub_url: &str) -> Self {
+        Self::builder(hub_url).build()
+    }
+
+    pub fn builder(hub_url: &str) -> ZeroCostClientBuilder {
+        ZeroCostClientBuilder::new(hub_url)
+    }
+
+    /// Stream of events, starting from the next one; see [`ZeroCostEvent`]
+    pub fn events(&self) -> impl Stream<Item = ZeroCostEvent> {
+        events::event_stream(self.events.subscribe(), |missed| ZeroCostEvent::Lagged { missed })
+    }
+
+    /// Connects with MessagePack and LZ4, as configured by the optimized server
+    pub async fn connect(&self) -> Result<(), HubError> {
+        self.connect_with(HubProtocol::MessagePack(Compression::Lz4BlockArray)).await
+    }
+
+    pub async fn connect_with(&self, protocol: HubProtocol) -> Result<(), HubError> {
+        let options = ConnectOptions { protocol, ..self.options.clone() };
+        let (connection, mut invocations) = HubConnection::connect(&self.hub_url, &options).await?;
+        let connection = Arc::new(connection);
+        let previous = self.connection.lock().await.replace(connection.clone());
+
+        let (handlers, events) = (self.handlers.clone(), self.events.clone());
+        let dispatcher = tokio::spawn(async move {
+            while let Some(invocation) = invocations.recv().await {
+                if let Some(event) = ZeroCostEvent::decode(&invocation.target, &invocation.arguments) {
+                    handlers.dispatch(&event);
+                    let _ = events.send(event);
+                }
+            }
+            let _ = events.send(ZeroCostEvent::Disconnected);
+        });
+
+        // Keep the connection alive past the hub's inactivity cleanup
+        let heartbeat = tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(
+                tokio::time::Instant::now() + HEARTBEAT_INTERVAL,
+                HEARTBEAT_INTERVAL,
+            );
+            loop {
+                interval.tick().await;
+                if ZeroCostHubProxy::new(connection.clone()).heartbeat().await.is_err() {
+                    break;
+                }
+            }
+        });
+        for task in std::mem::replace(&mut *self.tasks.lock().await, vec![dispatcher, heartbeat]) {
+            task.abort();
+        }
+        if let Some(previous) = previous {
+            let _ = previous.close().await;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the connection and stops its tasks
+    pub async fn disconnect(&self) -> Result<(), HubError> {
+        let connection = self.connection.lock().await.take();
+        let result = match connection {
+            Some(connection) => connection.close().await,
+            None => Ok(()),
+        };
+        for task in self.tasks.lock().await.drain(..) {
+            task.abort();
+        }
+        result
+    }
+
+    pub async fn join_session(&self, session_id: &str, username: &str) -> Result<(), HubError> {
+        self.hub().await?.join_session(session_id, username).await
+    }
+
+    pub async fn leave_session(&self, session_id: &str) -> Result<(), HubError> {
+        self.hub().await?.leave_session(session_id).await
+    }
+
+    /// Sends a signal to a single peer, identified by its connection id
+    pub async fn send_signal(&self, target_connection_id: &str, data: &[u8]) -> Result<(), HubError> {
+        self.hub().await?.send_signal(target_connection_id, Bytes::new(data)).await
+    }
+
+    /// Sends a signal to every peer of the session
+    pub async fn broadcast_signal(&self, session_id: &str, data: &[u8]) -> Result<(), HubError> {
+        self.hub().await?.broadcast_signal(session_id, Bytes::new(data)).await
+    }
+
+    pub async fn heartbeat(&self) -> Result<(), HubError> {
+        self.hub().await?.heartbeat().await
+    }
+
+    pub async fn get_session_stats(&self, session_id: &str) -> Result<SessionStats, HubError> {
+        self.hub().await?.get_session_stats(session_id).await
+    }
+
+    pub async fn get_global_stats(&self) -> Result<GlobalStats, HubError> {
+        self.hub().await?.get_global_stats().await
+    }
+
+    async fn hub(&self) -> Result<ZeroCostHubProxy, HubError> {
+        let connection = self.connection.lock().await.clone();
+        connection.map(ZeroCostHubProxy::new).ok_or(HubError::NotConnected)
+    }
+}
+
+impl Drop for ZeroCostClient {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signaling::protocol::from_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_decode_user_joined_message() {
+        let mut bytes = Vec::new();
+        let dto = Value::Array(vec![Value::from("alice"), Value::from("conn-1")]);
+        rmpv::encode::write_value(&mut bytes, &dto).unwrap();
+
+        let expected = PeerInfo {
+            username: "alice".to_string(),
+            connection_id: "conn-1".to_string(),
+        };
+        // MessagePack protocol: `bin`; JSON protocol: base64 string
+        assert_eq!(PeerInfo::decode(&Value::Binary(bytes.clone())), Some(expected.clone()));
+        let base64 = protocol::to_json(&Value::Binary(bytes));
+        assert_eq!(PeerInfo::decode(&from_json(base64)), Some(expected));
+    }
+
+    #[test]
+    fn test_stats_from_json_and_contractless_results() {
+        let json = from_json(json!({ "sessionId": "s1", "connectionCount": 3, "relayNodeCount": 1 }));
+        let stats: SessionStats = rmpv::ext::from_value(json).unwrap();
+        assert_eq!(stats.connection_count, 3);
+
+        let map = Value::Map(vec![
+            (Value::from("TotalConnections"), Value::from(10)),
+            (Value::from("TotalSessions"), Value::from(2)),
+            (Value::from("MemoryUsageBytes"), Value::from(4096)),
+            (Value::from("PooledConnectionsAvailable"), Value::from(5)),
+        ]);
+        let stats: GlobalStats = rmpv::ext::from_value(map).unwrap();
+        assert_eq!(stats.memory_usage_bytes, 4096);
+    }
+
+    #[tokio::test]
+    async fn test_handlers_and_stream_see_events_until_disconnect() {
+        use crate::signaling::protocol::HubMessage;
+        use crate::signaling::testing::MockHub;
+        use futures_util::StreamExt;
+
+        let server = MockHub::start(HubProtocol::Json).await;
+        let joined = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let joined_clone = joined.clone();
+        let client = ZeroCostClient::builder(&server.url)
+            .on_user_joined(move |peer| joined_clone.lock().unwrap().push(peer.username))
+            .build();
+        let events = client.events();
+        tokio::pin!(events);
+        client.connect_with(HubProtocol::Json).await.unwrap();
+
+        let mut bytes = Vec::new();
+        let dto = Value::Array(vec![Value::from("bob"), Value::from("conn-b")]);
+        rmpv::encode::write_value(&mut bytes, &dto).unwrap();
+        server.send(HubMessage::Invocation {
+            invocation_id: None,
+            target: "UserJoined".to_string(),
+            arguments: vec![from_json(protocol::to_json(&Value::Binary(bytes)))],
+        });
+        let bob = PeerInfo { username: "bob".to_string(), connection_id: "conn-b".to_string() };
+        assert_eq!(events.next().await, Some(ZeroCostEvent::UserJoined(bob)));
+        assert_eq!(*joined.lock().unwrap(), vec!["bob".to_string()]);
+
+        client.disconnect().await.unwrap();
+        assert!(client.tasks.lock().await.is_empty());
+        assert!(matches!(client.heartbeat().await, Err(HubError::NotConnected)));
+    }
+}